@@ -1,12 +1,13 @@
 use ansi_term::Colour::Red;
 use clap::{crate_version, AppSettings, Parser};
 
+use debcargo_vendor::config::{json_schema, Compression};
 use debcargo_vendor::crates::CrateInfo;
 use debcargo_vendor::debian::DebInfo;
 use debcargo_vendor::errors::Result;
 use debcargo_vendor::package::*;
 use debcargo_vendor::{
-    build_order::{build_order, BuildOrderArgs},
+    build_order::{build_graph, BuildOrderArgs, GraphFormat},
     crates::invalidate_crates_io_cache,
 };
 
@@ -46,6 +47,8 @@ enum Opt {
         #[clap(flatten)]
         args: BuildOrderArgs,
     },
+    /// Print a JSON Schema of debcargo.toml, for completion in editors.
+    ConfigSchema,
 }
 
 #[test]
@@ -67,7 +70,13 @@ fn real_main() -> Result<()> {
             version,
         } => {
             let crate_info = CrateInfo::new_with_update(&crate_name, version.as_deref(), false)?;
-            let deb_info = DebInfo::new(&crate_info, crate_version!(), version.is_some());
+            let deb_info = DebInfo::new(
+                &crate_info,
+                crate_version!(),
+                version.is_some(),
+                None,
+                Compression::default(),
+            )?;
             println!("{}", deb_info.package_name());
             Ok(())
         }
@@ -85,23 +94,44 @@ fn real_main() -> Result<()> {
         } => {
             log::info!("preparing crate info");
             let mut process = PackageProcess::init(init)?;
-            log::info!("extracting crate");
-            process.extract(extract)?;
-            log::info!("applying overlay and patches");
-            process.apply_overrides()?;
-            log::info!("preparing orig tarball");
-            process.prepare_orig_tarball()?;
+            if finish.regenerate_only.is_empty() && !finish.diff {
+                log::info!("extracting crate");
+                process.extract(extract)?;
+                log::info!("applying overlay and patches");
+                process.apply_overrides()?;
+                log::info!("preparing orig tarball");
+                process.prepare_orig_tarball()?;
+            } else {
+                log::info!("reusing extracted crate");
+                process.reuse_extracted(extract)?;
+                log::info!("applying overlay and patches");
+                process.apply_overrides()?;
+            }
             log::info!("preparing debian folder");
-            process.prepare_debian_folder(finish)?;
+            if let Some(diff) = process.prepare_debian_folder(finish)? {
+                print!("{}", diff);
+                return Ok(());
+            }
             process.post_package_checks()
         }
         BuildOrder { args } => {
-            let build_order = build_order(args)?;
-            for v in &build_order {
-                println!("{}", v);
+            let format = args.graph;
+            let graph = build_graph(args)?;
+            match format {
+                None => {
+                    for v in graph.order() {
+                        println!("{}", v);
+                    }
+                }
+                Some(GraphFormat::Dot) => print!("{}", graph.to_dot()),
+                Some(GraphFormat::Json) => println!("{:#}", graph.to_json()),
             }
             Ok(())
         }
+        ConfigSchema => {
+            println!("{:#}", json_schema());
+            Ok(())
+        }
     }
 }
 
@@ -8,7 +8,7 @@ use clap::{Parser, ValueEnum};
 
 use crate::config::Config;
 use crate::crates::{crate_name_ver_to_dep, show_dep, transitive_deps, CrateDepInfo, CrateInfo};
-use crate::debian::control::base_deb_name;
+use crate::debian::control::{base_deb_name, deb_feature_name, deb_name};
 use crate::errors::Result;
 use crate::package::{PackageExtractArgs, PackageProcess};
 use crate::util;
@@ -39,6 +39,16 @@ pub struct BuildOrderArgs {
     /// Emulate resolution as if every package were built with --collapse-features.
     #[clap(long)]
     emulate_collapse_features: bool,
+    /// Print the per-feature dependency graph in this format, instead of just
+    /// the build order.
+    #[clap(value_enum, long)]
+    pub graph: Option<GraphFormat>,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum GraphFormat {
+    Dot,
+    Json,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
@@ -50,6 +60,91 @@ impl fmt::Display for PackageIdFeat {
     }
 }
 
+impl PackageIdFeat {
+    /// Name of the Debian binary package for this feature, without any
+    /// semver suffix since that depends on the config of the crate.
+    fn deb_name(&self) -> String {
+        if self.1.is_empty() {
+            deb_name(&self.0.name())
+        } else {
+            deb_feature_name(&self.0.name(), self.1)
+        }
+    }
+}
+
+/// The per-feature dependency graph that the build order is computed from,
+/// i.e. the edges given by `dep_features`.
+#[derive(Debug, Clone)]
+pub struct BuildGraph {
+    succ: BTreeMap<PackageIdFeat, BTreeSet<PackageIdFeat>>,
+    order: Vec<PackageId>,
+}
+
+impl BuildGraph {
+    /// Crates in the order they should be built in.
+    pub fn order(&self) -> &[PackageId] {
+        &self.order
+    }
+
+    /// Graphviz DOT form, with an edge from each package to the packages it
+    /// build-depends on.
+    pub fn to_dot(&self) -> String {
+        let nodes = self
+            .succ
+            .keys()
+            .map(|k| format!("  \"{}\" [label=\"{}\"];\n", k, k.deb_name()));
+        let edges = self.succ.iter().flat_map(|(k, vv)| {
+            vv.iter()
+                .map(move |v| format!("  \"{}\" -> \"{}\";\n", k, v))
+        });
+        format!(
+            "digraph build_order {{\n{}}}\n",
+            nodes.chain(edges).collect::<String>()
+        )
+    }
+
+    /// JSON form, with the nodes, the edges between them as in `to_dot`, and
+    /// the build order, so that independent crates can be built in batches.
+    pub fn to_json(&self) -> serde_json::Value {
+        let nodes = self
+            .succ
+            .keys()
+            .map(|k| {
+                serde_json::json!({
+                    "id": k.to_string(),
+                    "package": k.deb_name(),
+                    "crate": k.0.name().as_str(),
+                    "version": k.0.version().to_string(),
+                    "feature": k.1,
+                })
+            })
+            .collect::<Vec<_>>();
+        let edges = self
+            .succ
+            .iter()
+            .flat_map(|(k, vv)| {
+                vv.iter()
+                    .map(move |v| serde_json::json!({"from": k.to_string(), "to": v.to_string()}))
+            })
+            .collect::<Vec<_>>();
+        let order = self
+            .order
+            .iter()
+            .map(|id| {
+                serde_json::json!({
+                    "crate": id.name().as_str(),
+                    "version": id.version().to_string(),
+                })
+            })
+            .collect::<Vec<_>>();
+        serde_json::json!({
+            "nodes": nodes,
+            "edges": edges,
+            "order": order,
+        })
+    }
+}
+
 // First result: if somebody build-depends on us, what do they first need to build?
 // Second result: what other packages need to go into Debian Testing before us?
 fn get_build_deps(
@@ -171,20 +266,24 @@ fn resolve_info(
                 (process.crate_info, process.config)
             }
         };
-        let dep_info = info.all_dependencies_and_features();
+        let dep_info = info.all_dependencies_and_features().clone();
         e.insert((info, dep_info, config));
     };
     Ok(id)
 }
 
 pub fn build_order(args: BuildOrderArgs) -> Result<Vec<PackageId>> {
+    Ok(build_graph(args)?.order)
+}
+
+pub fn build_graph(args: BuildOrderArgs) -> Result<BuildGraph> {
     let crate_name = &args.crate_name;
     let version = args.version.as_deref();
     let config_dir = args.config_dir.as_deref();
 
     let mut infos = BTreeMap::new();
     let mut cache = HashMap::new();
-    let seed_dep = crate_name_ver_to_dep(crate_name, version)?;
+    let seed_dep = crate_name_ver_to_dep(crate_name, version, None)?;
     let seed_id = resolve_info(&mut infos, &mut cache, config_dir, &seed_dep, true)?;
 
     let mut next = |idf: &PackageIdFeat| -> Result<(Vec<PackageIdFeat>, Vec<PackageIdFeat>)> {
@@ -283,5 +382,70 @@ pub fn build_order(args: BuildOrderArgs) -> Result<Vec<PackageId>> {
         );
     }
 
-    Ok(build_order)
+    Ok(BuildGraph {
+        succ: succ_with_features,
+        order: build_order,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::{BTreeMap, BTreeSet};
+
+    use cargo::core::{PackageId, SourceId};
+
+    use super::{BuildGraph, PackageIdFeat};
+
+    #[test]
+    fn build_graph_dot_and_json() {
+        let source_id = SourceId::for_path(std::path::Path::new("/nonexistent")).unwrap();
+        let foo = PackageId::new("foo", "1.0.0", source_id).unwrap();
+        let bar_baz = PackageId::new("bar_baz", "0.2.1", source_id).unwrap();
+        let succ = BTreeMap::from([
+            (
+                PackageIdFeat(foo, ""),
+                BTreeSet::from([PackageIdFeat(bar_baz, ""), PackageIdFeat(bar_baz, "std")]),
+            ),
+            (PackageIdFeat(bar_baz, ""), BTreeSet::new()),
+            (
+                PackageIdFeat(bar_baz, "std"),
+                BTreeSet::from([PackageIdFeat(bar_baz, "")]),
+            ),
+        ]);
+        let graph = BuildGraph {
+            succ,
+            order: vec![bar_baz, foo],
+        };
+
+        let dot = graph.to_dot();
+        assert!(dot.starts_with("digraph build_order {\n"));
+        assert!(dot.contains("  \"bar_baz@0.2.1/std\" [label=\"librust-bar-baz+std-dev\"];\n"));
+        assert!(dot.contains("  \"foo@1.0.0/\" [label=\"librust-foo-dev\"];\n"));
+        assert!(dot.contains("  \"foo@1.0.0/\" -> \"bar_baz@0.2.1/std\";\n"));
+        assert_eq!(dot.matches(" -> ").count(), 3);
+
+        let json = graph.to_json();
+        assert_eq!(json["nodes"].as_array().unwrap().len(), 3);
+        assert_eq!(
+            json["nodes"][1],
+            serde_json::json!({
+                "id": "bar_baz@0.2.1/std",
+                "package": "librust-bar-baz+std-dev",
+                "crate": "bar_baz",
+                "version": "0.2.1",
+                "feature": "std",
+            })
+        );
+        assert_eq!(
+            json["edges"][0],
+            serde_json::json!({"from": "bar_baz@0.2.1/std", "to": "bar_baz@0.2.1/"})
+        );
+        assert_eq!(
+            json["order"],
+            serde_json::json!([
+                {"crate": "bar_baz", "version": "0.2.1"},
+                {"crate": "foo", "version": "1.0.0"},
+            ])
+        );
+    }
 }
@@ -1,61 +1,244 @@
+use schemars::JsonSchema;
 use serde::Deserialize;
 use toml;
 
+use crate::debian::changelog;
 use crate::errors::*;
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fs::File;
 use std::io::Read;
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
 pub const RUST_MAINT: &str =
     "Debian Rust Maintainers <pkg-rust-maintainers@alioth-lists.debian.net>";
 
-#[derive(Deserialize, Debug, Clone)]
+/// Repository that the Rust Team keeps all package configs in, under src/.
+pub const RUST_TEAM_VCS: &str = "https://salsa.debian.org/rust-team/debcargo-conf";
+
+/// Column at which long descriptions in debian/control are wrapped.
+pub const DEFAULT_DESCRIPTION_WIDTH: usize = 79;
+
+#[derive(Deserialize, JsonSchema, Debug, Clone)]
 #[serde(default)]
 pub struct Config {
     pub bin: Option<bool>,
     pub bin_name: String,
+    pub split_bins: bool,
+    pub ship_examples: ShipExamples,
     pub semver_suffix: bool,
     pub overlay: Option<PathBuf>,
+    #[serde(alias = "orig_tar_excludes")]
     pub excludes: Option<Vec<String>>,
     pub whitelist: Option<Vec<String>>,
-    pub allow_prerelease_deps: bool,
+    pub allow_prerelease_deps: AllowPrereleaseDeps,
+    pub strict_semver_deps: bool,
     pub crate_src_path: Option<PathBuf>,
+    pub registry: Option<String>,
     pub summary: Option<String>,
     pub description: Option<String>,
+    pub description_width: usize,
     pub maintainer: String,
+    pub vcs_base: Option<String>,
     pub uploaders: Option<Vec<String>>,
     pub collapse_features: bool,
+    pub omit_empty_feature_packages: bool,
+    pub previous_features: Option<Vec<String>>,
+    pub test: bool,
+    pub test_args: Option<Vec<String>>,
+    pub test_depends_builddeps: bool,
+    pub test_depends_recommends: bool,
     pub requires_root: Option<String>,
+    pub dh_cargo_version: Option<String>,
+    pub changelog_distribution: Option<String>,
+    pub changelog_urgency: Option<String>,
+    pub debian_revision: Option<String>,
+    pub orig_compression: Compression,
+    pub watch_mode: WatchMode,
+    pub watch_pgp: bool,
+    pub watch_pgpsigurlmangle: Option<String>,
+    pub upstream_signing_key: Option<PathBuf>,
+    pub source_format: SourceFormat,
+    pub data_package: bool,
+    pub data_package_paths: Option<Vec<String>>,
+    pub links_build_depends: Option<HashMap<String, Vec<String>>>,
+    pub build_script_reviewed: bool,
+    pub keep_cargo_lock: Option<bool>,
+    pub built_using: Option<bool>,
+    pub source_options_extend_diff_ignore: Option<Vec<String>>,
+    pub source_options_compression_level: Option<u32>,
+    pub clean: Option<Vec<String>>,
 
     pub source: Option<SourceOverride>,
     pub packages: Option<HashMap<String, PackageOverride>>,
+    pub gbp: Option<GbpConfig>,
+
+    /// Keys that debcargo doesn't know about, e.g. typos, to warn about.
+    #[serde(flatten)]
+    #[schemars(skip)]
+    unknown: BTreeMap<String, toml::Value>,
+}
+
+/// Set the given Option fields of `$base` to those of `$other` that are Some.
+macro_rules! merge_options {
+    ($base:ident, $other:ident, $($field:ident),*) => {
+        $(if $other.$field.is_some() {
+            $base.$field = $other.$field;
+        })*
+    };
+}
+
+fn merge_maps<M, K, V>(base: Option<M>, other: Option<M>) -> Option<M>
+where
+    M: IntoIterator<Item = (K, V)> + Extend<(K, V)>,
+{
+    match (base, other) {
+        (Some(mut base), Some(other)) => {
+            base.extend(other);
+            Some(base)
+        }
+        (base, other) => other.or(base),
+    }
+}
+
+/// Settings for debian/gbp.conf, which is only generated if this is present.
+#[derive(Deserialize, JsonSchema, Debug, Clone, Default)]
+pub struct GbpConfig {
+    debian_branch: Option<String>,
+    upstream_tag: Option<String>,
+    pristine_tar: Option<bool>,
+    #[serde(flatten)]
+    #[schemars(skip)]
+    unknown: BTreeMap<String, toml::Value>,
 }
 
-#[derive(Deserialize, Debug, Clone, Default)]
+#[derive(Deserialize, JsonSchema, Debug, Clone, Default)]
 pub struct SourceOverride {
     section: Option<String>,
+    bin_section: Option<String>,
+    architecture: Option<String>,
+    epoch: Option<u64>,
+    testsuite: Option<bool>,
     policy: Option<String>,
+    priority: Option<String>,
     homepage: Option<String>,
     vcs_git: Option<String>,
     vcs_browser: Option<String>,
     build_depends: Option<Vec<String>>,
     build_depends_excludes: Option<Vec<String>>,
+    build_conflicts: Option<Vec<String>>,
+    extra_control_fields: Option<BTreeMap<String, String>>,
+    #[serde(flatten)]
+    #[schemars(skip)]
+    unknown: BTreeMap<String, toml::Value>,
 }
 
-#[derive(Deserialize, Debug, Clone, Default)]
+#[derive(Deserialize, JsonSchema, Debug, Clone, Default)]
 pub struct PackageOverride {
     section: Option<String>,
     summary: Option<String>,
     description: Option<String>,
+    architecture: Option<String>,
+    multi_arch: Option<String>,
+    pre_depends: Option<Vec<String>>,
     depends: Option<Vec<String>>,
     recommends: Option<Vec<String>>,
     suggests: Option<Vec<String>>,
+    enhances: Option<Vec<String>>,
     provides: Option<Vec<String>>,
+    breaks: Option<Vec<String>>,
+    conflicts: Option<Vec<String>>,
+    replaces: Option<Vec<String>>,
     extra_lines: Option<Vec<String>>,
+    extra_control_fields: Option<BTreeMap<String, String>>,
     test_is_broken: Option<bool>,
+    broken_test_policy: Option<BrokenTestPolicy>,
     test_depends: Option<Vec<String>>,
+    test_restrictions: Option<Vec<String>>,
+    #[serde(flatten)]
+    #[schemars(skip)]
+    unknown: BTreeMap<String, toml::Value>,
+}
+
+impl GbpConfig {
+    fn merge(mut self, other: GbpConfig) -> GbpConfig {
+        merge_options!(self, other, debian_branch, upstream_tag, pristine_tar);
+        self.unknown.extend(other.unknown);
+        self
+    }
+
+    pub fn debian_branch(&self) -> &str {
+        self.debian_branch.as_deref().unwrap_or("debian/latest")
+    }
+
+    pub fn upstream_tag(&self) -> &str {
+        self.upstream_tag
+            .as_deref()
+            .unwrap_or("upstream/%(version)s")
+    }
+
+    pub fn pristine_tar(&self) -> bool {
+        self.pristine_tar.unwrap_or(false)
+    }
+}
+
+impl SourceOverride {
+    fn merge(mut self, other: SourceOverride) -> SourceOverride {
+        merge_options!(
+            self,
+            other,
+            section,
+            bin_section,
+            architecture,
+            epoch,
+            testsuite,
+            policy,
+            priority,
+            homepage,
+            vcs_git,
+            vcs_browser,
+            build_depends,
+            build_depends_excludes,
+            build_conflicts
+        );
+        self.extra_control_fields =
+            merge_maps(self.extra_control_fields, other.extra_control_fields);
+        self.unknown.extend(other.unknown);
+        self
+    }
+}
+
+impl PackageOverride {
+    fn merge(mut self, other: PackageOverride) -> PackageOverride {
+        merge_options!(
+            self,
+            other,
+            section,
+            summary,
+            description,
+            architecture,
+            multi_arch,
+            pre_depends,
+            depends,
+            recommends,
+            suggests,
+            enhances,
+            provides,
+            breaks,
+            conflicts,
+            replaces,
+            extra_lines,
+            test_is_broken,
+            broken_test_policy,
+            test_depends,
+            test_restrictions
+        );
+        self.extra_control_fields =
+            merge_maps(self.extra_control_fields, other.extra_control_fields);
+        self.unknown.extend(other.unknown);
+        self
+    }
 }
 
 impl Default for Config {
@@ -63,31 +246,373 @@ impl Default for Config {
         Config {
             bin: None,
             bin_name: "<default>".to_string(),
+            split_bins: false,
+            ship_examples: ShipExamples::default(),
             semver_suffix: false,
             overlay: None,
             excludes: None,
             whitelist: None,
-            allow_prerelease_deps: false,
+            allow_prerelease_deps: AllowPrereleaseDeps::default(),
+            strict_semver_deps: false,
             crate_src_path: None,
+            registry: None,
             summary: None,
             description: None,
+            description_width: DEFAULT_DESCRIPTION_WIDTH,
             maintainer: RUST_MAINT.to_string(),
+            vcs_base: None,
             uploaders: None,
             collapse_features: false,
+            omit_empty_feature_packages: false,
+            previous_features: None,
+            test: true,
+            test_args: None,
+            test_depends_builddeps: false,
+            test_depends_recommends: false,
             source: None,
             packages: None,
+            gbp: None,
+            unknown: BTreeMap::new(),
             requires_root: None,
+            dh_cargo_version: None,
+            changelog_distribution: None,
+            changelog_urgency: None,
+            debian_revision: None,
+            orig_compression: Compression::default(),
+            watch_mode: WatchMode::default(),
+            watch_pgp: false,
+            watch_pgpsigurlmangle: None,
+            upstream_signing_key: None,
+            source_format: SourceFormat::default(),
+            data_package: false,
+            data_package_paths: None,
+            links_build_depends: None,
+            build_script_reviewed: false,
+            keep_cargo_lock: None,
+            built_using: None,
+            source_options_extend_diff_ignore: None,
+            source_options_compression_level: None,
+            clean: None,
+        }
+    }
+}
+
+/// Dependencies whose prerelease version requirements may be rewritten to
+/// the released version: either all or none of them, or those on the listed
+/// crates.
+#[derive(Deserialize, JsonSchema, Debug, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum AllowPrereleaseDeps {
+    All(bool),
+    Crates(Vec<String>),
+}
+
+impl Default for AllowPrereleaseDeps {
+    fn default() -> Self {
+        AllowPrereleaseDeps::All(false)
+    }
+}
+
+impl AllowPrereleaseDeps {
+    pub fn allows(&self, crate_name: &str) -> bool {
+        match self {
+            AllowPrereleaseDeps::All(allow) => *allow,
+            AllowPrereleaseDeps::Crates(crates) => crates.iter().any(|c| c == crate_name),
+        }
+    }
+}
+
+/// Example targets of the crate that are packaged as binaries: either all or
+/// none of them, or those with the listed names.
+#[derive(Deserialize, JsonSchema, Debug, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum ShipExamples {
+    All(bool),
+    Examples(Vec<String>),
+}
+
+impl Default for ShipExamples {
+    fn default() -> Self {
+        ShipExamples::All(false)
+    }
+}
+
+impl ShipExamples {
+    pub fn ships(&self, example: &str) -> bool {
+        match self {
+            ShipExamples::All(ship) => *ship,
+            ShipExamples::Examples(examples) => examples.iter().any(|e| e == example),
+        }
+    }
+}
+
+/// Compression format of the orig tarball.
+#[derive(Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    Gzip,
+    Xz,
+    Zstd,
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Compression::Gzip
+    }
+}
+
+impl Compression {
+    /// File extension for a tarball in this format, without the leading dot.
+    pub fn extension(&self) -> &'static str {
+        use self::Compression::*;
+        match self {
+            Gzip => "gz",
+            Xz => "xz",
+            Zstd => "zst",
+        }
+    }
+
+    /// The format of data that starts with `magic`, or None if it is not
+    /// compressed in any of the known formats.
+    pub fn from_magic(magic: &[u8]) -> Option<Compression> {
+        use self::Compression::*;
+        if magic.starts_with(&[0x1f, 0x8b]) {
+            Some(Gzip)
+        } else if magic.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+            Some(Xz)
+        } else if magic.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Some(Zstd)
+        } else {
+            None
+        }
+    }
+
+    /// Name of this format as understood by dpkg-source.
+    pub fn dpkg_name(&self) -> &'static str {
+        use self::Compression::*;
+        match self {
+            Gzip => "gzip",
+            Xz => "xz",
+            Zstd => "zstd",
+        }
+    }
+}
+
+/// What to do with tests that are marked as broken with test_is_broken.
+#[derive(Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum BrokenTestPolicy {
+    /// Run the tests, but ignore failures.
+    Flaky,
+    /// Do not run the tests at all.
+    Skip,
+    /// Run the tests and fail on failures, as if they were not broken.
+    Fail,
+}
+
+impl Default for BrokenTestPolicy {
+    fn default() -> Self {
+        BrokenTestPolicy::Flaky
+    }
+}
+
+/// Where debian/watch looks for new upstream versions.
+#[derive(Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchMode {
+    #[serde(rename = "crates.io")]
+    CratesIo,
+    #[serde(rename = "git")]
+    Git,
+}
+
+impl Default for WatchMode {
+    fn default() -> Self {
+        WatchMode::CratesIo
+    }
+}
+
+/// Format of the Debian source package, i.e. debian/source/format.
+#[derive(Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SourceFormat {
+    #[serde(rename = "3.0 (quilt)")]
+    Quilt,
+    #[serde(rename = "3.0 (native)")]
+    Native,
+}
+
+impl Default for SourceFormat {
+    fn default() -> Self {
+        SourceFormat::Quilt
+    }
+}
+
+impl SourceFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SourceFormat::Quilt => "3.0 (quilt)",
+            SourceFormat::Native => "3.0 (native)",
+        }
+    }
+
+    pub fn is_native(&self) -> bool {
+        *self == SourceFormat::Native
+    }
+}
+
+impl FromStr for Config {
+    type Err = anyhow::Error;
+
+    fn from_str(content: &str) -> Result<Config> {
+        let config: Config =
+            toml::from_str(content).map_err(|e| DebcargoError::Config(e.to_string()))?;
+        for key in config.unknown_keys() {
+            debcargo_warn!("ignoring unknown key {}", key);
         }
+        Ok(config)
     }
 }
 
+/// JSON Schema of debcargo.toml, e.g. for completion and validation in
+/// editors.
+pub fn json_schema() -> serde_json::Value {
+    serde_json::to_value(schemars::schema_for!(Config)).unwrap()
+}
+
 impl Config {
     pub fn parse(src: &Path) -> Result<Config> {
-        let mut config_file = File::open(src)?;
+        let config_error =
+            |e: &dyn std::error::Error| DebcargoError::Config(format!("{}: {}", src.display(), e));
         let mut content = String::new();
-        config_file.read_to_string(&mut content)?;
+        File::open(src)
+            .and_then(|mut f| f.read_to_string(&mut content))
+            .map_err(|e| config_error(&e))?;
+
+        let config: Config = toml::from_str(&content).map_err(|e| config_error(&e))?;
+        for key in config.unknown_keys() {
+            debcargo_warn!("{}: ignoring unknown key {}", src.display(), key);
+        }
+        Ok(config)
+    }
 
-        Ok(toml::from_str(&content)?)
+    /// Layer `other` over this config, e.g. per-crate overrides over a global
+    /// default config. Values that are set in `other`, i.e. that are not None
+    /// or not the default, win. Lists are replaced as a whole rather than
+    /// concatenated, so that `other` can also remove entries. Tables are
+    /// merged by key: [packages.KEY] and [source] field by field, and the
+    /// entries of links_build_depends and extra_control_fields one by one.
+    pub fn merge(mut self, other: Config) -> Config {
+        let default = Config::default();
+        macro_rules! merge_values {
+            ($base:ident, $other:ident, $($field:ident),*) => {
+                $(if $other.$field != default.$field {
+                    $base.$field = $other.$field;
+                })*
+            };
+        }
+        merge_values!(
+            self,
+            other,
+            bin_name,
+            split_bins,
+            ship_examples,
+            semver_suffix,
+            allow_prerelease_deps,
+            strict_semver_deps,
+            description_width,
+            maintainer,
+            collapse_features,
+            omit_empty_feature_packages,
+            test,
+            test_depends_builddeps,
+            test_depends_recommends,
+            orig_compression,
+            watch_mode,
+            watch_pgp,
+            source_format,
+            data_package,
+            build_script_reviewed
+        );
+        merge_options!(
+            self,
+            other,
+            bin,
+            overlay,
+            excludes,
+            whitelist,
+            crate_src_path,
+            registry,
+            summary,
+            description,
+            uploaders,
+            requires_root,
+            dh_cargo_version,
+            changelog_distribution,
+            changelog_urgency,
+            debian_revision,
+            watch_pgpsigurlmangle,
+            upstream_signing_key,
+            data_package_paths,
+            keep_cargo_lock,
+            previous_features,
+            test_args,
+            built_using,
+            vcs_base,
+            source_options_extend_diff_ignore,
+            source_options_compression_level,
+            clean
+        );
+        self.links_build_depends = merge_maps(self.links_build_depends, other.links_build_depends);
+        self.source = match (self.source, other.source) {
+            (Some(base), Some(other)) => Some(base.merge(other)),
+            (base, other) => other.or(base),
+        };
+        self.packages = match (self.packages, other.packages) {
+            (Some(mut base), Some(other)) => {
+                for (key, package) in other {
+                    let merged = match base.remove(&key) {
+                        Some(b) => b.merge(package),
+                        None => package,
+                    };
+                    base.insert(key, merged);
+                }
+                Some(base)
+            }
+            (base, other) => other.or(base),
+        };
+        self.gbp = match (self.gbp, other.gbp) {
+            (Some(base), Some(other)) => Some(base.merge(other)),
+            (base, other) => other.or(base),
+        };
+        self.unknown.extend(other.unknown);
+        self
+    }
+
+    /// Keys in the config that debcargo doesn't know about, as dotted paths
+    /// such as "packages.lib.recomends".
+    pub fn unknown_keys(&self) -> Vec<String> {
+        let mut keys: Vec<String> = self.unknown.keys().cloned().collect();
+        if let Some(source) = &self.source {
+            keys.extend(source.unknown.keys().map(|k| format!("source.{}", k)));
+        }
+        if let Some(gbp) = &self.gbp {
+            keys.extend(gbp.unknown.keys().map(|k| format!("gbp.{}", k)));
+        }
+        let mut packages: Vec<_> = self.packages.iter().flatten().collect();
+        packages.sort_by_key(|(name, _)| *name);
+        for (name, package) in packages {
+            let name = if name.contains('.') || name.contains('+') {
+                format!("{:?}", name)
+            } else {
+                name.to_string()
+            };
+            keys.extend(
+                package
+                    .unknown
+                    .keys()
+                    .map(|k| format!("packages.{}.{}", name, k)),
+            );
+        }
+        keys
     }
 
     pub fn build_bin_package(&self) -> bool {
@@ -105,6 +630,20 @@ impl Config {
         Some(config_path?.parent()?.join(self.crate_src_path.as_ref()?))
     }
 
+    pub fn upstream_signing_key(&self, config_path: Option<&Path>) -> Option<PathBuf> {
+        Some(
+            config_path?
+                .parent()?
+                .join(self.upstream_signing_key.as_ref()?),
+        )
+    }
+
+    /// How uscan finds the detached signature of a download, by default the
+    /// download URL with ".asc" appended.
+    pub fn watch_pgpsigurlmangle(&self) -> &str {
+        self.watch_pgpsigurlmangle.as_deref().unwrap_or("s/$/.asc/")
+    }
+
     pub fn orig_tar_excludes(&self) -> Option<&Vec<String>> {
         self.excludes.as_ref()
     }
@@ -117,6 +656,13 @@ impl Config {
         self.maintainer.as_str()
     }
 
+    pub fn vcs_base(&self) -> &str {
+        self.vcs_base
+            .as_deref()
+            .unwrap_or(RUST_TEAM_VCS)
+            .trim_end_matches('/')
+    }
+
     pub fn uploaders(&self) -> Option<&Vec<String>> {
         self.uploaders.as_ref()
     }
@@ -125,16 +671,75 @@ impl Config {
         self.requires_root.as_ref()
     }
 
+    pub fn test_args(&self) -> Vec<&str> {
+        match self.test_args.as_ref() {
+            Some(args) => args.iter().map(String::as_str).collect(),
+            None => vec!["test", "--all"],
+        }
+    }
+
+    pub fn dh_cargo_version(&self) -> &str {
+        self.dh_cargo_version.as_deref().unwrap_or("25")
+    }
+
+    pub fn changelog_distribution(&self) -> &str {
+        self.changelog_distribution
+            .as_deref()
+            .unwrap_or(changelog::DEFAULT_DIST)
+    }
+
+    pub fn changelog_urgency(&self) -> &str {
+        self.changelog_urgency
+            .as_deref()
+            .unwrap_or(changelog::DEFAULT_URGENCY)
+    }
+
+    pub fn debian_revision(&self) -> &str {
+        self.debian_revision
+            .as_deref()
+            .unwrap_or(changelog::DEFAULT_REVISION)
+    }
+
+    pub fn data_package_paths(&self) -> Vec<&str> {
+        match self.data_package_paths.as_ref() {
+            Some(paths) => paths.iter().map(String::as_str).collect(),
+            None => vec!["data"],
+        }
+    }
+
     // Source shortcuts
 
     pub fn section(&self) -> Option<&str> {
         Some(self.source.as_ref()?.section.as_ref()?)
     }
 
+    pub fn bin_section(&self) -> Option<&str> {
+        Some(self.source.as_ref()?.bin_section.as_ref()?)
+    }
+
+    pub fn architecture(&self) -> Option<&str> {
+        Some(self.source.as_ref()?.architecture.as_ref()?)
+    }
+
+    pub fn epoch(&self) -> Option<u64> {
+        self.source.as_ref()?.epoch
+    }
+
+    pub fn testsuite(&self) -> bool {
+        self.source
+            .as_ref()
+            .and_then(|s| s.testsuite)
+            .unwrap_or(true)
+    }
+
     pub fn policy_version(&self) -> Option<&str> {
         Some(self.source.as_ref()?.policy.as_ref()?)
     }
 
+    pub fn priority(&self) -> Option<&str> {
+        Some(self.source.as_ref()?.priority.as_ref()?)
+    }
+
     pub fn homepage(&self) -> Option<&str> {
         Some(self.source.as_ref()?.homepage.as_ref()?)
     }
@@ -155,6 +760,22 @@ impl Config {
         self.source.as_ref()?.build_depends_excludes.as_ref()
     }
 
+    pub fn links_build_depends(&self, links: &str) -> Option<&Vec<String>> {
+        self.links_build_depends.as_ref()?.get(links)
+    }
+
+    pub fn clean(&self) -> &[String] {
+        self.clean.as_deref().unwrap_or(&[])
+    }
+
+    pub fn build_conflicts(&self) -> Option<&Vec<String>> {
+        self.source.as_ref()?.build_conflicts.as_ref()
+    }
+
+    pub fn extra_control_fields(&self) -> Option<&BTreeMap<String, String>> {
+        self.source.as_ref()?.extra_control_fields.as_ref()
+    }
+
     // Packages shortcuts
 
     fn with_package<'a, T, F: FnOnce(&'a PackageOverride) -> Option<T>>(
@@ -180,6 +801,18 @@ impl Config {
         self.with_package(key, |pkg| pkg.description.as_deref())
     }
 
+    pub fn package_architecture(&self, key: PackageKey) -> Option<&str> {
+        self.with_package(key, |pkg| pkg.architecture.as_deref())
+    }
+
+    pub fn package_multi_arch(&self, key: PackageKey) -> Option<&str> {
+        self.with_package(key, |pkg| pkg.multi_arch.as_deref())
+    }
+
+    pub fn package_pre_depends(&self, key: PackageKey) -> Option<&Vec<String>> {
+        self.with_package(key, |pkg| pkg.pre_depends.as_ref())
+    }
+
     pub fn package_depends(&self, key: PackageKey) -> Option<&Vec<String>> {
         self.with_package(key, |pkg| pkg.depends.as_ref())
     }
@@ -192,21 +825,52 @@ impl Config {
         self.with_package(key, |pkg| pkg.suggests.as_ref())
     }
 
+    pub fn package_enhances(&self, key: PackageKey) -> Option<&Vec<String>> {
+        self.with_package(key, |pkg| pkg.enhances.as_ref())
+    }
+
     pub fn package_provides(&self, key: PackageKey) -> Option<&Vec<String>> {
         self.with_package(key, |pkg| pkg.provides.as_ref())
     }
 
+    pub fn package_breaks(&self, key: PackageKey) -> Option<&Vec<String>> {
+        self.with_package(key, |pkg| pkg.breaks.as_ref())
+    }
+
+    pub fn package_conflicts(&self, key: PackageKey) -> Option<&Vec<String>> {
+        self.with_package(key, |pkg| pkg.conflicts.as_ref())
+    }
+
+    pub fn package_replaces(&self, key: PackageKey) -> Option<&Vec<String>> {
+        self.with_package(key, |pkg| pkg.replaces.as_ref())
+    }
+
     pub fn package_extra_lines(&self, key: PackageKey) -> Option<&Vec<String>> {
         self.with_package(key, |pkg| pkg.extra_lines.as_ref())
     }
 
+    pub fn package_extra_control_fields(
+        &self,
+        key: PackageKey,
+    ) -> Option<&BTreeMap<String, String>> {
+        self.with_package(key, |pkg| pkg.extra_control_fields.as_ref())
+    }
+
     pub fn package_test_is_broken(&self, key: PackageKey) -> Option<bool> {
         self.with_package(key, |pkg| pkg.test_is_broken)
     }
 
+    pub fn package_broken_test_policy(&self, key: PackageKey) -> Option<BrokenTestPolicy> {
+        self.with_package(key, |pkg| pkg.broken_test_policy)
+    }
+
     pub fn package_test_depends(&self, key: PackageKey) -> Option<&Vec<String>> {
         self.with_package(key, |pkg| pkg.test_depends.as_ref())
     }
+
+    pub fn package_test_restrictions(&self, key: PackageKey) -> Option<&Vec<String>> {
+        self.with_package(key, |pkg| pkg.test_restrictions.as_ref())
+    }
 }
 
 pub fn package_field_for_feature<'a>(
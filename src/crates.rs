@@ -1,4 +1,4 @@
-use anyhow::{format_err, Error};
+use anyhow::{format_err, Context, Error};
 use cargo::{
     core::manifest::ManifestMetadata,
     core::registry::PackageRegistry,
@@ -10,7 +10,7 @@ use cargo::{
     ops,
     ops::{PackageOpts, Packages},
     sources::RegistrySource,
-    util::{interning::InternedString, toml::read_manifest, FileLock},
+    util::{interning::InternedString, toml::read_manifest, FileLock, IntoUrl},
     Config,
 };
 use filetime::{set_file_times, FileTime};
@@ -20,27 +20,37 @@ use regex::Regex;
 use semver::Version;
 use tar::Archive;
 use tempfile;
+use xz2::read::XzDecoder;
 
 use std;
-use std::collections::{BTreeMap, HashSet};
+use std::cell::{Ref, RefCell};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs;
 use std::hash::{Hash, Hasher};
-use std::io::{self, Read, Write};
-use std::path::Path;
+use std::io::{self, BufRead, Read, Seek, Write};
+use std::path::{Path, PathBuf};
 
-use crate::config::testing_ignore_debpolv;
+use crate::config::{testing_ignore_debpolv, Compression, ShipExamples};
 use crate::errors::*;
+use crate::util;
 
 pub struct CrateInfo {
     // only used for to_registry_toml in extract_crate. DO NOT USE ELSEWHERE
     package: Package,
     // allows overriding package.manifest() e.g. via patches
     manifest: Manifest,
+    // computed from manifest on first use; reset by replace_manifest
+    dep_info: RefCell<Option<CrateDepInfo>>,
     crate_file: FileLock,
+    // unpacked source directory, for local crates
+    crate_src_dir: Option<PathBuf>,
     config: Config,
     source_id: SourceId,
     excludes: Vec<Pattern>,
     includes: Vec<Pattern>,
+    strip_cargo_lock: bool,
+    // cargo-checksum.json "files" of the patched sources, if there are patches
+    patched_checksums: Option<BTreeMap<String, String>>,
 }
 
 pub type CrateDepInfo = BTreeMap<
@@ -81,10 +91,23 @@ pub fn invalidate_crates_io_cache() -> Result<()> {
     Ok(())
 }
 
-pub fn crate_name_ver_to_dep(crate_name: &str, version: Option<&str>) -> Result<Dependency> {
+/// The source for crates in the given registry, by index URL, or crates.io.
+pub fn registry_source_id(config: &Config, registry: Option<&str>) -> Result<SourceId> {
+    Ok(match registry {
+        None => SourceId::crates_io(config)?,
+        Some(url) if url.starts_with("registry+") => SourceId::from_url(url)?,
+        Some(url) => SourceId::for_registry(&url.into_url()?)?,
+    })
+}
+
+pub fn crate_name_ver_to_dep(
+    crate_name: &str,
+    version: Option<&str>,
+    registry: Option<&str>,
+) -> Result<Dependency> {
     // note: this forces a network call
     let config = Config::default()?;
-    let source_id = SourceId::crates_io(&config)?;
+    let source_id = registry_source_id(&config, registry)?;
     let version = version.and_then(|v| {
         if v.is_empty() {
             None
@@ -97,6 +120,97 @@ pub fn crate_name_ver_to_dep(crate_name: &str, version: Option<&str>) -> Result<
     Dependency::parse(crate_name, version.as_deref(), source_id)
 }
 
+/// Compile glob patterns for paths relative to the crate root, to match paths
+/// in the crate tarball, which are under a top-level crate directory.
+fn crate_path_patterns(patterns: Option<&Vec<String>>) -> Result<Vec<Pattern>> {
+    patterns
+        .into_iter()
+        .flatten()
+        .map(|x| Pattern::new(&("*/".to_owned() + x)).map_err(|e| format_err!("{}: {}", x, e)))
+        .collect()
+}
+
+/// Whether `path` in the crate tarball is its top-level Cargo.lock.
+fn is_cargo_lock(path: &Path) -> bool {
+    path.iter().count() == 2 && path.ends_with("Cargo.lock")
+}
+
+/// Whether the library target of the crate is a procedural macro.
+fn is_proc_macro(manifest: &Manifest) -> bool {
+    manifest
+        .targets()
+        .iter()
+        .any(|t| t.is_lib() && t.proc_macro())
+}
+
+/// Whether the crate has a build script, usually build.rs.
+fn has_build_script(manifest: &Manifest) -> bool {
+    manifest.targets().iter().any(|t| t.is_custom_build())
+}
+
+fn example_targets<'a>(manifest: &'a Manifest, ship: &ShipExamples) -> Vec<&'a str> {
+    let mut examples: Vec<&str> = manifest
+        .targets()
+        .iter()
+        .filter(|t| matches!(t.kind(), TargetKind::ExampleBin) && ship.ships(t.name()))
+        .map(|t| t.name())
+        .collect();
+    examples.sort_unstable();
+    examples
+}
+
+/// The directory of the crate `crate_name` under `crate_path`. This is
+/// `crate_path` itself, unless it is the root of a workspace with a virtual
+/// manifest, i.e. without a [package], in which case it is the directory of
+/// the workspace member of that name.
+fn workspace_member_path(config: &Config, crate_name: &str, crate_path: &Path) -> Result<PathBuf> {
+    let manifest_path = crate_path.join("Cargo.toml");
+    let source_id = SourceId::for_path(crate_path)?;
+    if let (EitherManifest::Real(_), _) = read_manifest(&manifest_path, source_id, config)? {
+        return Ok(crate_path.to_path_buf());
+    }
+    let workspace = Workspace::new(&manifest_path, config)?;
+    let members = workspace
+        .members()
+        .map(|p| (p.name(), p.root().to_path_buf()))
+        .collect::<Vec<_>>();
+    match members.iter().find(|(name, _)| name.as_str() == crate_name) {
+        Some((_, root)) => Ok(root.clone()),
+        None => debcargo_bail!(
+            "{} is a virtual workspace manifest without a member crate {}; \
+            package one of its members instead: {}",
+            manifest_path.display(),
+            crate_name,
+            members
+                .iter()
+                .map(|(name, _)| name.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    }
+}
+
+/// The cargo config, optionally without network access.
+fn cargo_config(offline: bool) -> Result<Config> {
+    let mut config = Config::default()?;
+    if offline {
+        // unfriendly API from cargo; we'll have to make do with it for
+        // now as there is no other alternative
+        config.configure(
+            0,
+            false,
+            None,
+            config.frozen(),
+            config.locked(),
+            true, // offline
+            &config.target_dir()?.map(|x| x.into_path_unlocked()),
+            &[],
+            &[],
+        )?;
+    }
+    Ok(config)
+}
+
 pub fn show_dep(dep: &Dependency) -> String {
     format!("{} {}", dep.package_name(), dep.version_req())
 }
@@ -106,13 +220,37 @@ impl CrateInfo {
         CrateInfo::new_with_update(crate_name, version, true)
     }
 
+    /// Like `new`, but fetch the crate from the registry with the given
+    /// index URL instead of crates.io.
+    pub fn new_with_registry(
+        crate_name: &str,
+        version: Option<&str>,
+        registry: &str,
+    ) -> Result<CrateInfo> {
+        let dep = crate_name_ver_to_dep(crate_name, version, Some(registry))?;
+        Self::new_from_dependency(&dep, true)
+    }
+
+    /// Like `new`, but without any network access. The crate and the index
+    /// metadata are read from the local registry cache under $CARGO_HOME,
+    /// and it is an error if the crate was not downloaded there before.
+    pub fn new_offline(
+        crate_name: &str,
+        version: Option<&str>,
+        registry: Option<&str>,
+    ) -> Result<CrateInfo> {
+        let dep = crate_name_ver_to_dep(crate_name, version, registry)?;
+        Self::from_dependency(&dep, false, true)
+    }
+
     pub fn new_with_local_crate(
         crate_name: &str,
         version: Option<&str>,
         crate_path: &Path,
+        offline: bool,
     ) -> Result<CrateInfo> {
-        let config = Config::default()?;
-        let crate_path = crate_path.canonicalize()?;
+        let config = cargo_config(offline)?;
+        let crate_path = workspace_member_path(&config, crate_name, &crate_path.canonicalize()?)?;
         let source_id = SourceId::for_path(&crate_path)?;
 
         let (package, crate_file) = {
@@ -190,11 +328,15 @@ impl CrateInfo {
         Ok(CrateInfo {
             package,
             manifest,
+            dep_info: RefCell::new(None),
             crate_file,
+            crate_src_dir: Some(crate_path),
             config,
             source_id,
             excludes: vec![],
             includes: vec![],
+            strip_cargo_lock: false,
+            patched_checksums: None,
         })
     }
 
@@ -203,27 +345,16 @@ impl CrateInfo {
         version: Option<&str>,
         update: bool,
     ) -> Result<CrateInfo> {
-        let dep = crate_name_ver_to_dep(crate_name, version)?;
+        let dep = crate_name_ver_to_dep(crate_name, version, None)?;
         Self::new_from_dependency(&dep, update)
     }
 
     pub fn new_from_dependency(dependency: &Dependency, update: bool) -> Result<CrateInfo> {
-        let mut config = Config::default()?;
-        if !update {
-            // unfriendly API from cargo; we'll have to make do with it for
-            // now as there is no other alternative
-            config.configure(
-                0,
-                false,
-                None,
-                config.frozen(),
-                config.locked(),
-                true, // offline
-                &config.target_dir()?.map(|x| x.into_path_unlocked()),
-                &[],
-                &[],
-            )?;
-        }
+        Self::from_dependency(dependency, update, false)
+    }
+
+    fn from_dependency(dependency: &Dependency, update: bool, offline: bool) -> Result<CrateInfo> {
+        let config = cargo_config(offline || !update)?;
 
         let source_id = dependency.source_id();
         let registry_name = format!(
@@ -245,7 +376,8 @@ impl CrateInfo {
                 format_err!(
                     concat!(
                         "Couldn't find any crate matching {}\n",
-                        "Try `debcargo update` to update the crates.io index."
+                        "Try `debcargo update` to update the crates.io index, \
+                        or check the registry configuration."
                     ),
                     show_dep(dependency)
                 )
@@ -277,19 +409,33 @@ impl CrateInfo {
                 .open_ro(&filename, config, &filename)?;
             Ok((package.clone(), manifest.clone(), crate_file))
         };
-        // if update is false but the user never downloaded the crate then the
-        // first call will error; re-try with online in that case
-        let (package, manifest, crate_file) =
-            get_package_info(&config).or_else(|_| get_package_info(&Config::default()?))?;
+        let (package, manifest, crate_file) = if offline {
+            get_package_info(&config).with_context(|| {
+                format!(
+                    "{} is not available in the local registry cache at {}, \
+                    download it first or run without --offline",
+                    show_dep(dependency),
+                    config.registry_cache_path().display()
+                )
+            })?
+        } else {
+            // if update is false but the user never downloaded the crate then the
+            // first call will error; re-try with online in that case
+            get_package_info(&config).or_else(|_| get_package_info(&Config::default()?))?
+        };
 
         Ok(CrateInfo {
             package,
             manifest,
+            dep_info: RefCell::new(None),
             crate_file,
+            crate_src_dir: None,
             config,
             source_id,
             excludes: vec![],
             includes: vec![],
+            strip_cargo_lock: false,
+            patched_checksums: None,
         })
     }
 
@@ -315,8 +461,11 @@ impl CrateInfo {
     }
 
     pub fn replace_manifest(&mut self, path: &Path) -> Result<&Self> {
-        if let (EitherManifest::Real(v), _) = read_manifest(path, self.source_id, &self.config)? {
+        let (manifest, _) = read_manifest(path, self.source_id, &self.config)
+            .map_err(|e| Self::manifest_error(path, e.into()))?;
+        if let EitherManifest::Real(v) = manifest {
             self.manifest = v;
+            self.dep_info.replace(None);
         }
         Ok(self)
     }
@@ -337,6 +486,11 @@ impl CrateInfo {
         self.manifest.targets()
     }
 
+    /// Name of the native library that the crate links to, from its `links` key.
+    pub fn links(&self) -> Option<&str> {
+        self.manifest.links()
+    }
+
     pub fn is_lib(&self) -> bool {
         let mut lib = false;
         for target in self.manifest.targets() {
@@ -351,6 +505,19 @@ impl CrateInfo {
         lib
     }
 
+    pub fn is_proc_macro(&self) -> bool {
+        is_proc_macro(&self.manifest)
+    }
+
+    pub fn has_build_script(&self) -> bool {
+        has_build_script(&self.manifest)
+    }
+
+    /// The example targets that are shipped as binaries, see ship_examples.
+    pub fn get_example_targets(&self, ship: &ShipExamples) -> Vec<&str> {
+        example_targets(&self.manifest, ship)
+    }
+
     pub fn get_binary_targets(&self) -> Vec<&str> {
         let mut bins = Vec::new();
         for target in self.manifest.targets() {
@@ -373,6 +540,16 @@ impl CrateInfo {
         self.manifest.summary().checksum()
     }
 
+    /// Checksums of the individual files with the patches applied, if the
+    /// package has patches.
+    pub fn patched_checksums(&self) -> Option<&BTreeMap<String, String>> {
+        self.patched_checksums.as_ref()
+    }
+
+    pub fn set_patched_checksums(&mut self, checksums: BTreeMap<String, String>) {
+        self.patched_checksums = Some(checksums);
+    }
+
     pub fn package_id(&self) -> PackageId {
         self.manifest.summary().package_id()
     }
@@ -381,6 +558,27 @@ impl CrateInfo {
         &self.crate_file
     }
 
+    /// The compression of the crate tarball, and a reader of its contents.
+    pub fn crate_tarball(&self) -> Result<(Option<Compression>, Box<dyn Read + '_>)> {
+        let mut f = self.crate_file.file();
+        f.seek(io::SeekFrom::Start(0))?;
+        Ok(decompressed_tarball(f)?)
+    }
+
+    /// Index URL of the registry the crate was fetched from, unless that
+    /// is crates.io or the crate is local.
+    pub fn registry_url(&self) -> Option<String> {
+        if self.source_id.is_default_registry() || self.source_id.is_path() {
+            None
+        } else {
+            Some(self.source_id.url().to_string())
+        }
+    }
+
+    pub fn crate_src_dir(&self) -> Option<&Path> {
+        self.crate_src_dir.as_deref()
+    }
+
     pub fn dependencies(&self) -> &[Dependency] {
         self.manifest.dependencies()
     }
@@ -396,113 +594,18 @@ impl CrateInfo {
         deps
     }
 
+    fn manifest_error(path: &Path, e: Error) -> Error {
+        DebcargoError::Manifest(format!("{}: {:#}", path.display(), e)).into()
+    }
+
     /// Collect information about the dependency structure of features and
     /// their external crate dependencies, in a simple output format.
-    pub fn all_dependencies_and_features(&self) -> CrateDepInfo {
-        use cargo::core::dependency::DepKind;
-
-        let mut deps_by_name: BTreeMap<&str, Vec<&Dependency>> = BTreeMap::new();
-        for dep in self.dependencies() {
-            // we treat build-dependencies also as dependencies in Debian
-            if dep.kind() != DepKind::Development {
-                let s = dep.name_in_toml().as_str();
-                deps_by_name.entry(s).or_default().push(dep);
-            }
+    pub fn all_dependencies_and_features(&self) -> Ref<'_, CrateDepInfo> {
+        if self.dep_info.borrow().is_none() {
+            self.dep_info
+                .replace(Some(dependencies_and_features(&self.manifest)));
         }
-        let deps_by_name = deps_by_name;
-
-        let mut features_with_deps = BTreeMap::new();
-
-        // calculate dependencies of this crate's features
-        for (feature, deps) in self.manifest.summary().features() {
-            let mut feature_deps: Vec<&'static str> = vec![];
-            let mut other_deps: Vec<Dependency> = Vec::new();
-            for dep in deps {
-                use self::FeatureValue::*;
-                match dep {
-                    // another feature is a dependency
-                    Feature(dep_feature) => {
-                        feature_deps.push(InternedString::new(dep_feature).as_str())
-                    }
-                    // another package is a dependency
-                    Dep { dep_name } => {
-                        // unwrap is ok, valid Cargo.toml files must have this
-                        for &dep in deps_by_name.get(dep_name.as_str()).unwrap() {
-                            other_deps.push(dep.clone());
-                        }
-                    }
-                    // another package is a dependency
-                    DepFeature {
-                        dep_name,
-                        dep_feature,
-                        ..
-                    } => match deps_by_name.get(dep_name.as_str()) {
-                        // unwrap is ok, valid Cargo.toml files must have this
-                        Some(dd) => {
-                            for &dep in dd {
-                                let mut dep = dep.clone();
-                                let mut features: Vec<InternedString> =
-                                    vec![InternedString::new(dep_feature)];
-                                features.extend(dep.features());
-                                dep.set_features(features);
-                                dep.set_default_features(false);
-                                other_deps.push(dep);
-                            }
-                        }
-                        None => {
-                            let mut expected = false;
-                            for dep in self.dependencies() {
-                                if dep.kind() == DepKind::Development {
-                                    let s = dep.name_in_toml().as_str();
-                                    if s == dep_name.as_str() {
-                                        expected = true;
-                                    }
-                                }
-                            }
-                            if expected {
-                                debcargo_warn!(
-                                    "Ignoring \"{}\" feature \"{}\" as it depends on a \
-                                     dev-dependency \"{}\"",
-                                    self.package_id(),
-                                    feature,
-                                    dep_name
-                                );
-                            } else {
-                                panic!(
-                                    "failed to account for dependency \"{}\" of \"{}\" feature \"{}\"",
-                                    dep_name, self.package_id(), feature
-                                );
-                            }
-                        }
-                    },
-                }
-            }
-            if feature_deps.is_empty() {
-                // everything depends on bare library
-                feature_deps.push("");
-            }
-            features_with_deps.insert(feature.as_str(), (feature_deps, other_deps));
-        }
-
-        // calculate required dependencies for implicit no-default-features
-        let mut deps_required: Vec<Dependency> = Vec::new();
-        for deps in deps_by_name.values() {
-            for &dep in deps {
-                if !dep.is_optional() {
-                    deps_required.push(dep.clone())
-                }
-            }
-        }
-
-        // implicit no-default-features
-        features_with_deps.insert("", (vec![], deps_required));
-
-        // implicit default feature
-        if !features_with_deps.contains_key("default") {
-            features_with_deps.insert("default", (vec![""], vec![]));
-        }
-
-        features_with_deps
+        Ref::map(self.dep_info.borrow(), |d| d.as_ref().unwrap())
     }
 
     pub fn get_summary_description(&self) -> (Option<String>, Option<String>) {
@@ -565,21 +668,25 @@ impl CrateInfo {
         &mut self,
         excludes: Option<&Vec<String>>,
         includes: Option<&Vec<String>>,
-    ) {
-        self.excludes = excludes
-            .into_iter()
-            .flatten()
-            .map(|x| Pattern::new(&("*/".to_owned() + x)).unwrap())
-            .collect::<Vec<_>>();
-        self.includes = includes
-            .into_iter()
-            .flatten()
-            .map(|x| Pattern::new(&("*/".to_owned() + x)).unwrap())
-            .collect::<Vec<_>>();
+    ) -> Result<()> {
+        self.excludes = crate_path_patterns(excludes).context("invalid excludes pattern")?;
+        self.includes = crate_path_patterns(includes).context("invalid whitelist pattern")?;
+        Ok(())
+    }
+
+    /// Drop the top-level Cargo.lock from the orig tarball, see keep_cargo_lock.
+    pub fn set_strip_cargo_lock(&mut self, strip: bool) {
+        self.strip_cargo_lock = strip;
+    }
+
+    /// Whether the given path is excluded from the orig tarball by config.
+    pub fn is_excluded(&self, path: &Path) -> bool {
+        (self.strip_cargo_lock && is_cargo_lock(path))
+            || self.excludes.iter().any(|p| p.matches_path(path))
     }
 
     pub fn filter_path(&self, path: &Path) -> ::std::result::Result<bool, String> {
-        if self.excludes.iter().any(|p| p.matches_path(path)) {
+        if self.is_excluded(path) {
             return Ok(true);
         }
         let suspicious = match path.extension() {
@@ -604,8 +711,32 @@ impl CrateInfo {
         }
     }
 
+    /// Paths, relative to the crate root, that `filter_path` removes from the
+    /// crate tarball.
+    pub fn excluded_files(&self) -> Result<Vec<String>> {
+        let (_, tarball) = self.crate_tarball()?;
+        excluded_paths(&mut Archive::new(tarball), &|p| self.filter_path(p))
+    }
+
     pub fn extract_crate(&self, path: &Path) -> Result<bool> {
-        let mut archive = Archive::new(GzDecoder::new(self.crate_file.file()));
+        if let Some(expected) = self.checksum() {
+            let mut f = self.crate_file.file();
+            f.seek(io::SeekFrom::Start(0))?;
+            let actual = cargo_util::Sha256::new().update_file(f)?.finish_hex();
+            if actual != expected {
+                let pkgid = self.package_id();
+                return Err(DebcargoError::ChecksumMismatch(format!(
+                    "{}-{}.crate has checksum {}, but the registry says {}",
+                    pkgid.name(),
+                    pkgid.version(),
+                    actual,
+                    expected
+                ))
+                .into());
+            }
+        }
+        let (_, tarball) = self.crate_tarball()?;
+        let mut archive = Archive::new(tarball);
         let tempdir = tempfile::Builder::new()
             .prefix("debcargo")
             .tempdir_in(".")?;
@@ -654,7 +785,7 @@ impl CrateInfo {
             );
         }
 
-        if let Err(e) = fs::rename(entries[0].path(), &path) {
+        if let Err(e) = util::rename_or_copy(&entries[0].path(), path) {
             return Err(Error::from(e).context(format!(
                 concat!(
                     "Could not create source directory {0}\n",
@@ -667,7 +798,8 @@ impl CrateInfo {
         // Ensure that Cargo.toml is in standard form, e.g. does not contain
         // path dependencies, so can be built standalone (see #4030).
         let toml_path = path.join("Cargo.toml");
-        let ws = Workspace::new(&toml_path.canonicalize()?, &self.config)?;
+        let ws = Workspace::new(&toml_path.canonicalize()?, &self.config)
+            .map_err(|e| Self::manifest_error(&toml_path, e))?;
         let registry_toml = self.package.to_registry_toml(&ws)?;
         let mut actual_toml = String::new();
         fs::File::open(&toml_path)?.read_to_string(&mut actual_toml)?;
@@ -700,22 +832,234 @@ impl CrateInfo {
     }
 }
 
+/// Collect information about the dependency structure of features and
+/// their external crate dependencies, from the given manifest.
+fn dependencies_and_features(manifest: &Manifest) -> CrateDepInfo {
+    use cargo::core::dependency::DepKind;
+
+    let mut deps_by_name: BTreeMap<&str, Vec<&Dependency>> = BTreeMap::new();
+    for dep in manifest.dependencies() {
+        // we treat build-dependencies also as dependencies in Debian
+        if dep.kind() != DepKind::Development {
+            let s = dep.name_in_toml().as_str();
+            deps_by_name.entry(s).or_default().push(dep);
+        }
+    }
+    let deps_by_name = deps_by_name;
+
+    let mut features_with_deps = BTreeMap::new();
+    // weak dependency features ("foo?/bar") of each feature
+    let mut weak_deps: BTreeMap<&'static str, Vec<(InternedString, InternedString)>> =
+        BTreeMap::new();
+
+    // calculate dependencies of this crate's features
+    for (feature, deps) in manifest.summary().features() {
+        let mut feature_deps: Vec<&'static str> = vec![];
+        let mut other_deps: Vec<Dependency> = Vec::new();
+        for dep in deps {
+            use self::FeatureValue::*;
+            match dep {
+                // another feature is a dependency
+                Feature(dep_feature) => {
+                    feature_deps.push(InternedString::new(dep_feature).as_str())
+                }
+                // another package is a dependency
+                Dep { dep_name } => {
+                    // unwrap is ok, valid Cargo.toml files must have this
+                    for &dep in deps_by_name.get(dep_name.as_str()).unwrap() {
+                        other_deps.push(dep.clone());
+                    }
+                }
+                // a feature of another package, that does not pull in the
+                // package itself; dealt with below once we know all features
+                DepFeature {
+                    dep_name,
+                    dep_feature,
+                    weak: true,
+                } => weak_deps
+                    .entry(feature.as_str())
+                    .or_default()
+                    .push((*dep_name, *dep_feature)),
+                // another package is a dependency
+                DepFeature {
+                    dep_name,
+                    dep_feature,
+                    ..
+                } => {
+                    match deps_by_name.get(dep_name.as_str()) {
+                        // unwrap is ok, valid Cargo.toml files must have this
+                        Some(dd) => {
+                            for &dep in dd {
+                                let mut dep = dep.clone();
+                                let mut features: Vec<InternedString> =
+                                    vec![InternedString::new(dep_feature)];
+                                features.extend(dep.features());
+                                dep.set_features(features);
+                                dep.set_default_features(false);
+                                other_deps.push(dep);
+                            }
+                        }
+                        None => {
+                            let mut expected = false;
+                            for dep in manifest.dependencies() {
+                                if dep.kind() == DepKind::Development {
+                                    let s = dep.name_in_toml().as_str();
+                                    if s == dep_name.as_str() {
+                                        expected = true;
+                                    }
+                                }
+                            }
+                            if expected {
+                                debcargo_warn!(
+                                    "Ignoring \"{}\" feature \"{}\" as it depends on a \
+                                 dev-dependency \"{}\"",
+                                    manifest.package_id(),
+                                    feature,
+                                    dep_name
+                                );
+                            } else {
+                                panic!(
+                                "failed to account for dependency \"{}\" of \"{}\" feature \"{}\"",
+                                dep_name, manifest.package_id(), feature
+                            );
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        if feature_deps.is_empty() {
+            // everything depends on bare library
+            feature_deps.push("");
+        }
+        features_with_deps.insert(feature.as_str(), (feature_deps, other_deps));
+    }
+
+    // calculate required dependencies for implicit no-default-features
+    let mut deps_required: Vec<Dependency> = Vec::new();
+    for deps in deps_by_name.values() {
+        for &dep in deps {
+            if !dep.is_optional() {
+                deps_required.push(dep.clone())
+            }
+        }
+    }
+
+    // implicit no-default-features
+    features_with_deps.insert("", (vec![], deps_required));
+
+    // implicit default feature
+    if !features_with_deps.contains_key("default") {
+        features_with_deps.insert("default", (vec![""], vec![]));
+    }
+
+    // A weak dependency feature "foo?/bar" only takes effect if foo is
+    // enabled some other way. Debian has no way to express this condition, so
+    // we add the dependency on foo with bar to every feature that (transitively)
+    // enables both the weak dependency feature and foo itself.
+    let mut weak_additions = Vec::new();
+    for &feature in features_with_deps.keys() {
+        let (mut ff, mut dd) = transitive_deps(&features_with_deps, feature);
+        ff.push(feature);
+        dd.extend(features_with_deps[feature].1.iter().cloned());
+        for (dep_name, dep_feature) in ff.iter().filter_map(|f| weak_deps.get(f)).flatten() {
+            for dep in dd.iter().filter(|d| d.name_in_toml() == *dep_name) {
+                let mut dep = dep.clone();
+                let mut features: Vec<InternedString> = vec![*dep_feature];
+                features.extend(dep.features());
+                dep.set_features(features);
+                dep.set_default_features(false);
+                weak_additions.push((feature, dep));
+            }
+        }
+    }
+    for (feature, dep) in weak_additions {
+        let other_deps = &mut features_with_deps.get_mut(feature).unwrap().1;
+        if !other_deps.contains(&dep) {
+            other_deps.push(dep);
+        }
+    }
+
+    features_with_deps
+}
+
+/// Detect the compression of a tarball from its first bytes, and return it
+/// together with a reader of the uncompressed tarball. Crates are normally
+/// gzip-compressed, but mirrors or local builds may provide them differently.
+pub fn decompressed_tarball<'a, R: Read + 'a>(
+    r: R,
+) -> io::Result<(Option<Compression>, Box<dyn Read + 'a>)> {
+    let mut r = io::BufReader::new(r);
+    let compression = Compression::from_magic(r.fill_buf()?);
+    let tarball: Box<dyn Read + 'a> = match compression {
+        Some(Compression::Gzip) => Box::new(GzDecoder::new(r)),
+        Some(Compression::Xz) => Box::new(XzDecoder::new(r)),
+        Some(Compression::Zstd) => Box::new(zstd::Decoder::with_buffer(r)?),
+        None => Box::new(r),
+    };
+    Ok((compression, tarball))
+}
+
+/// Paths of the entries of the crate tarball `archive` that `filter_path`
+/// removes, relative to the crate root and sorted.
+fn excluded_paths<R: Read>(
+    archive: &mut Archive<R>,
+    filter_path: &dyn Fn(&Path) -> ::std::result::Result<bool, String>,
+) -> Result<Vec<String>> {
+    let mut excluded = Vec::new();
+    for entry in archive.entries()? {
+        let entry = entry?;
+        let path = entry.path()?;
+        // suspicious files are reported when the crate is extracted
+        if let Ok(true) = filter_path(&path) {
+            let rel: PathBuf = path.iter().skip(1).collect();
+            excluded.push(rel.to_string_lossy().into_owned());
+        }
+    }
+    excluded.sort();
+    Ok(excluded)
+}
+
 /// Calculate all feature-dependencies and external-dependencies of a given
 /// feature, using the information previously generated by
-/// `all_dependencies_and_features`.
+/// `all_dependencies_and_features`. The result of each feature is computed
+/// only once, so that features reachable along many paths do not make this
+/// exponential; the order is that of a plain depth-first expansion, keeping
+/// only the first occurrence of each feature and dependency.
 pub fn transitive_deps<'a>(
     features_with_deps: &'a CrateDepInfo,
     feature: &str,
 ) -> (Vec<&'a str>, Vec<Dependency>) {
-    let mut all_features = Vec::new();
+    collect_transitive_deps(features_with_deps, feature, &mut HashMap::new())
+}
+
+fn collect_transitive_deps<'a>(
+    features_with_deps: &'a CrateDepInfo,
+    feature: &str,
+    done: &mut HashMap<&'a str, (Vec<&'a str>, Vec<Dependency>)>,
+) -> (Vec<&'a str>, Vec<Dependency>) {
+    if let Some(result) = done.get(feature) {
+        return result.clone();
+    }
+    fn push_new<T: PartialEq>(all: &mut Vec<T>, new: impl IntoIterator<Item = T>) {
+        for x in new {
+            if !all.contains(&x) {
+                all.push(x);
+            }
+        }
+    }
+    let (&key, (ff, dd)) = features_with_deps.get_key_value(feature).unwrap();
+    let mut all_features = ff.clone();
     let mut all_deps = Vec::new();
-    let &(ref ff, ref dd) = features_with_deps.get(feature).unwrap();
-    all_features.extend(ff.clone());
-    all_deps.extend(dd.clone());
+    push_new(&mut all_deps, dd.iter().cloned());
     for f in ff {
-        let (ff1, dd1) = transitive_deps(features_with_deps, f);
-        all_features.extend(ff1);
-        all_deps.extend(dd1);
+        let (ff1, dd1) = collect_transitive_deps(features_with_deps, f, done);
+        push_new(&mut all_features, ff1);
+        push_new(&mut all_deps, dd1);
     }
+    done.insert(key, (all_features.clone(), all_deps.clone()));
     (all_features, all_deps)
 }
+
+#[cfg(test)]
+mod tests;
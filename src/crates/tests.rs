@@ -0,0 +1,497 @@
+use super::{
+    crate_path_patterns, dependencies_and_features, example_targets, excluded_paths,
+    has_build_script, is_proc_macro, registry_source_id, transitive_deps, workspace_member_path,
+    CrateInfo,
+};
+use crate::config::ShipExamples;
+use crate::debian::{deb_build_deps, deb_deps};
+
+use std::fs;
+use std::path::Path;
+use std::rc::Rc;
+
+use cargo::{
+    core::{manifest::Manifest, SourceId},
+    util::{config::Config, toml::TomlManifest},
+};
+
+fn build_manifest(toml: &str) -> Manifest {
+    let toml_manifest: Rc<TomlManifest> = Rc::new(toml::from_str(toml).unwrap());
+    let source_id = SourceId::for_path(Path::new("/path/to/mypackage")).unwrap();
+    let package_root = Path::new("/path/to/mypackage");
+    let config = Config::default().unwrap();
+    TomlManifest::to_real_manifest(&toml_manifest, source_id, package_root, &config)
+        .unwrap()
+        .0
+}
+
+#[test]
+fn namespaced_and_weak_features() {
+    let manifest = build_manifest(
+        r#"
+[package]
+name = "mypackage"
+version = "1.2.3"
+
+[dependencies]
+foo = { version = "1", optional = true }
+bar = { version = "1", optional = true }
+
+[features]
+a = ["dep:foo"]
+b = ["bar?/std"]
+c = ["bar", "b"]
+"#,
+    );
+    let features = dependencies_and_features(&manifest);
+    let dep_names = |f: &str| {
+        features[f]
+            .1
+            .iter()
+            .map(|d| {
+                let mut feats = d.features().iter().map(|x| x.as_str()).collect::<Vec<_>>();
+                feats.sort_unstable();
+                (d.name_in_toml().as_str(), feats)
+            })
+            .collect::<Vec<_>>()
+    };
+
+    // dep: does not create an implicit feature, but pulls in the dependency
+    assert!(!features.contains_key("foo"));
+    assert_eq!(features["a"].0, vec![""]);
+    assert_eq!(dep_names("a"), vec![("foo", vec![])]);
+
+    // without a dep: anywhere, bar keeps its implicit feature
+    assert_eq!(dep_names("bar"), vec![("bar", vec![])]);
+
+    // a weak feature alone does not pull in the dependency
+    assert_eq!(features["b"].0, vec![""]);
+    assert!(dep_names("b").is_empty());
+
+    // but it does when the dependency is enabled some other way
+    assert_eq!(features["c"].0, vec!["bar", "b"]);
+    assert_eq!(dep_names("c"), vec![("bar", vec!["std"])]);
+}
+
+#[test]
+fn transitive_deps_order() {
+    let manifest = build_manifest(
+        r#"[package]
+name = "mypackage"
+version = "1.2.3"
+
+[features]
+a = ["b", "c"]
+b = ["c", "y"]
+c = ["z"]
+y = ["w"]
+z = []
+w = []
+"#,
+    );
+    let features = dependencies_and_features(&manifest);
+    // same as expanding each feature depth-first and dropping repetitions;
+    // features without any others depend on the bare library ""
+    assert_eq!(
+        transitive_deps(&features, "a").0,
+        vec!["b", "c", "y", "z", "", "w"]
+    );
+}
+
+#[test]
+fn transitive_deps_of_many_features() {
+    // every feature enables the previous two, so there are exponentially many
+    // paths from the last feature to the first one
+    let mut toml = "[package]\nname = \"mypackage\"\nversion = \"1.2.3\"\n\n\
+                    [dependencies]\nfoo = { version = \"1\", optional = true }\n\n\
+                    [features]\nf0 = [\"foo\"]\nf1 = [\"f0\"]\n"
+        .to_string();
+    for i in 2..200 {
+        toml.push_str(&format!("f{} = [\"f{}\", \"f{}\"]\n", i, i - 1, i - 2));
+    }
+    let manifest = build_manifest(&toml);
+    let features = dependencies_and_features(&manifest);
+
+    let (ff, dd) = transitive_deps(&features, "f199");
+    let mut expected = (0..199)
+        .rev()
+        .map(|i| format!("f{}", i))
+        .collect::<Vec<_>>();
+    expected.push("foo".to_string());
+    expected.push("".to_string());
+    assert_eq!(ff, expected);
+    assert_eq!(
+        dd.iter()
+            .map(|d| d.name_in_toml().as_str())
+            .collect::<Vec<_>>(),
+        vec!["foo"]
+    );
+    // deterministic
+    assert_eq!(transitive_deps(&features, "f199").0, ff);
+}
+
+#[test]
+fn no_default_features_dependency() {
+    let manifest = build_manifest(
+        r#"
+[package]
+name = "mypackage"
+version = "1.2.3"
+
+[dependencies]
+foo = { version = "1", default-features = false, features = ["x", "y"] }
+bar = "1"
+
+[features]
+z = ["foo/z"]
+"#,
+    );
+    let features = dependencies_and_features(&manifest);
+    let config = crate::config::Config::default();
+
+    assert_eq!(
+        deb_deps(&config, &features[""].1).unwrap(),
+        vec![
+            "librust-bar-1+default-dev",
+            "librust-foo-1+x-dev",
+            "librust-foo-1+y-dev",
+        ]
+    );
+    // enabling a feature of foo must not pull in its default features either
+    assert_eq!(
+        deb_deps(&config, &features["z"].1).unwrap(),
+        vec![
+            "librust-foo-1+x-dev",
+            "librust-foo-1+y-dev",
+            "librust-foo-1+z-dev",
+        ]
+    );
+}
+
+#[test]
+fn build_dependency_constraints() {
+    let manifest = build_manifest(
+        r#"
+[package]
+name = "mypackage"
+version = "1.2.3"
+
+[dependencies]
+both = "1"
+
+[build-dependencies]
+cc = ">= 1.2"
+both = "1"
+"#,
+    );
+    let features = dependencies_and_features(&manifest);
+    let config = crate::config::Config::default();
+
+    assert_eq!(
+        deb_build_deps(&config, &features[""].1).unwrap(),
+        vec![
+            "librust-both-1+default-dev",
+            "librust-cc+default-dev:native (>= 1.2-~~)",
+        ]
+    );
+    // the -dev packages depend on them like on any other dependency
+    assert_eq!(
+        deb_deps(&config, &features[""].1).unwrap(),
+        vec![
+            "librust-both-1+default-dev",
+            "librust-cc+default-dev (>= 1.2-~~)",
+        ]
+    );
+}
+
+#[test]
+fn renamed_dependency() {
+    let manifest = build_manifest(
+        r#"
+[package]
+name = "mypackage"
+version = "1.2.3"
+
+[dependencies]
+foo = { package = "real_crate", version = "1", optional = true }
+
+[features]
+a = ["foo/x"]
+"#,
+    );
+    let features = dependencies_and_features(&manifest);
+    let config = crate::config::Config::default();
+
+    // features are keyed by the local name, packages by the published one
+    assert_eq!(
+        deb_deps(&config, &features["foo"].1).unwrap(),
+        vec!["librust-real-crate-1+default-dev"]
+    );
+    assert_eq!(
+        deb_deps(&config, &features["a"].1).unwrap(),
+        vec!["librust-real-crate-1+x-dev"]
+    );
+}
+
+#[test]
+fn proc_macro_crate() {
+    let manifest = build_manifest(
+        r#"
+[package]
+name = "mypackage"
+version = "1.2.3"
+
+[lib]
+path = "src/lib.rs"
+"#,
+    );
+    assert!(!is_proc_macro(&manifest));
+
+    let manifest = build_manifest(
+        r#"
+[package]
+name = "mypackage"
+version = "1.2.3"
+
+[lib]
+path = "src/lib.rs"
+proc-macro = true
+"#,
+    );
+    assert!(is_proc_macro(&manifest));
+}
+
+#[test]
+fn build_script_crate() {
+    let manifest = build_manifest(
+        r#"
+[package]
+name = "mypackage"
+version = "1.2.3"
+build = false
+
+[lib]
+path = "src/lib.rs"
+"#,
+    );
+    assert!(!has_build_script(&manifest));
+
+    let manifest = build_manifest(
+        r#"
+[package]
+name = "mypackage"
+version = "1.2.3"
+build = "build.rs"
+
+[lib]
+path = "src/lib.rs"
+"#,
+    );
+    assert!(has_build_script(&manifest));
+}
+
+#[test]
+fn excluded_paths_are_relative_to_crate_root() {
+    let mut builder = tar::Builder::new(Vec::new());
+    for path in [
+        "foo-1.0/src/lib.rs",
+        "foo-1.0/tests/data/blob.bin",
+        "foo-1.0/vendor/a.c",
+    ] {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(0);
+        builder
+            .append_data(&mut header, path, std::io::empty())
+            .unwrap();
+    }
+    let data = builder.into_inner().unwrap();
+
+    let filter_path = |p: &Path| {
+        if p.ends_with("a.c") {
+            Err("suspicious".to_string())
+        } else {
+            Ok(p.starts_with("foo-1.0/tests/data"))
+        }
+    };
+    let excluded = excluded_paths(&mut tar::Archive::new(data.as_slice()), &filter_path).unwrap();
+    assert_eq!(excluded, vec!["tests/data/blob.bin"]);
+}
+
+#[test]
+fn keep_or_strip_cargo_lock() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let root = tempdir.path().join("mypackage");
+    fs::create_dir_all(root.join("src")).unwrap();
+    fs::create_dir_all(root.join("tests").join("fixture")).unwrap();
+    fs::write(
+        root.join("Cargo.toml"),
+        "[package]\nname = \"mypackage\"\nversion = \"0.1.0\"\n",
+    )
+    .unwrap();
+    // cargo only ships the Cargo.lock of crates with binaries
+    fs::write(root.join("src").join("main.rs"), "fn main() {}\n").unwrap();
+    fs::write(root.join("tests").join("fixture").join("Cargo.lock"), "").unwrap();
+    let mut crate_info = CrateInfo::new_with_local_crate("mypackage", None, &root, true).unwrap();
+
+    let kept = tempdir.path().join("kept");
+    assert!(!crate_info.extract_crate(&kept).unwrap());
+    assert!(kept.join("Cargo.lock").exists());
+    assert!(crate_info.excluded_files().unwrap().is_empty());
+
+    crate_info.set_strip_cargo_lock(true);
+    let stripped = tempdir.path().join("stripped");
+    assert!(crate_info.extract_crate(&stripped).unwrap());
+    assert!(!stripped.join("Cargo.lock").exists());
+    // only the top-level one, not those of test fixtures
+    assert!(stripped
+        .join("tests")
+        .join("fixture")
+        .join("Cargo.lock")
+        .exists());
+    assert_eq!(crate_info.excluded_files().unwrap(), vec!["Cargo.lock"]);
+}
+
+#[test]
+fn crate_path_patterns_match_under_crate_dir() {
+    let patterns = crate_path_patterns(Some(&vec!["tests/data/**".to_string()])).unwrap();
+    assert!(patterns[0].matches_path(Path::new("foo-1.0/tests/data/blob.bin")));
+    assert!(!patterns[0].matches_path(Path::new("foo-1.0/src/lib.rs")));
+
+    assert!(crate_path_patterns(None).unwrap().is_empty());
+    assert!(crate_path_patterns(Some(&vec!["[".to_string()])).is_err());
+}
+
+#[test]
+fn registry_source_ids() {
+    let config = Config::default().unwrap();
+    assert!(registry_source_id(&config, None)
+        .unwrap()
+        .is_default_registry());
+    for url in [
+        "https://example.org/index",
+        "registry+https://example.org/index",
+    ] {
+        let source_id = registry_source_id(&config, Some(url)).unwrap();
+        assert!(source_id.is_registry());
+        assert!(!source_id.is_default_registry());
+        assert_eq!(source_id.url().as_str(), "https://example.org/index");
+    }
+}
+
+#[test]
+fn shipped_example_targets() {
+    let manifest = build_manifest(
+        r#"
+[package]
+name = "mypackage"
+version = "1.2.3"
+
+[[bin]]
+name = "mybin"
+path = "src/main.rs"
+
+[[example]]
+name = "demo"
+path = "examples/demo.rs"
+
+[[example]]
+name = "another_demo"
+path = "examples/another_demo.rs"
+"#,
+    );
+    assert!(example_targets(&manifest, &ShipExamples::default()).is_empty());
+    assert_eq!(
+        example_targets(&manifest, &ShipExamples::All(true)),
+        vec!["another_demo", "demo"]
+    );
+    assert_eq!(
+        example_targets(&manifest, &ShipExamples::Examples(vec!["demo".to_string()])),
+        vec!["demo"]
+    );
+}
+
+#[test]
+fn workspace_members() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let root = tempdir.path().canonicalize().unwrap();
+    let write = |path: &str, content: &str| {
+        let path = root.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    };
+    write(
+        "Cargo.toml",
+        "[workspace]\nmembers = [\"foo-core\", \"foo\"]\n",
+    );
+    write(
+        "foo-core/Cargo.toml",
+        "[package]\nname = \"foo-core\"\nversion = \"0.1.0\"\n",
+    );
+    write("foo-core/src/lib.rs", "");
+    write(
+        "foo/Cargo.toml",
+        "[package]\nname = \"foo\"\nversion = \"0.1.0\"\n\n\
+        [dependencies]\nfoo-core = { version = \"0.1\", path = \"../foo-core\" }\n",
+    );
+    write("foo/src/lib.rs", "");
+    let config = Config::default().unwrap();
+
+    assert_eq!(
+        workspace_member_path(&config, "foo", &root).unwrap(),
+        root.join("foo")
+    );
+    assert_eq!(
+        workspace_member_path(&config, "foo-core", &root).unwrap(),
+        root.join("foo-core")
+    );
+    // a crate directory is used as is
+    assert_eq!(
+        workspace_member_path(&config, "foo", &root.join("foo")).unwrap(),
+        root.join("foo")
+    );
+    let err = workspace_member_path(&config, "bar", &root).unwrap_err();
+    assert!(err.to_string().contains("foo-core"), "{}", err);
+}
+
+#[test]
+fn replace_manifest_resets_cached_dependencies() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let root = tempdir.path();
+    let manifest = "[package]\nname = \"mypackage\"\nversion = \"0.1.0\"\n\n[features]\na = []\n";
+    fs::write(root.join("Cargo.toml"), manifest).unwrap();
+    fs::create_dir(root.join("src")).unwrap();
+    fs::write(root.join("src").join("lib.rs"), "").unwrap();
+
+    let mut crate_info = CrateInfo::new_with_local_crate("mypackage", None, root, true).unwrap();
+    {
+        let features = crate_info.all_dependencies_and_features();
+        assert!(features.contains_key("a"));
+        assert!(!features.contains_key("b"));
+        assert!(features[""].1.is_empty());
+        // served from the cache
+        assert!(std::ptr::eq(
+            &*features,
+            &*crate_info.all_dependencies_and_features()
+        ));
+    }
+
+    // as if patched by quilt
+    let patched = root.join("Cargo.toml.patched");
+    fs::write(
+        &patched,
+        format!(
+            "{}b = [\"a\"]\n\n[dependencies]\nserde = \"1.0.100\"\n",
+            manifest
+        ),
+    )
+    .unwrap();
+    crate_info.replace_manifest(&patched).unwrap();
+    assert!(crate_info.manifest().summary().features().contains_key("b"));
+    let features = crate_info.all_dependencies_and_features();
+    assert!(features.contains_key("b"));
+    assert_eq!(features["b"].0, vec!["a"]);
+    let deps = &features[""].1;
+    assert_eq!(deps.len(), 1);
+    assert_eq!(deps[0].package_name().as_str(), "serde");
+    assert_eq!(deps[0].version_req().to_string(), "^1.0.100");
+}
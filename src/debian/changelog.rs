@@ -7,6 +7,9 @@ use std::str;
 
 pub const DEFAULT_DIST: &str = "UNRELEASED-FIXME-AUTOGENERATED-DEBCARGO";
 pub const COMMENT_TEAM_UPLOAD: &str = "  * Team upload.";
+pub const DEFAULT_URGENCY: &str = "medium";
+pub const DEFAULT_REVISION: &str = "1";
+pub const URGENCIES: &[&str] = &["low", "medium", "high", "emergency", "critical"];
 
 pub struct ChangelogEntry {
     pub source: String,
@@ -15,6 +18,19 @@ pub struct ChangelogEntry {
     pub options: String,
     pub maintainer: String,
     pub date: DateTime<FixedOffset>,
+    /// Items outside of any section, i.e. by whoever signs off the entry.
+    pub items: Vec<String>,
+    pub sections: Vec<ChangelogSection>,
+    // the lines that `items` and `sections` were parsed from, to render them
+    // with their original spacing as long as they are unchanged
+    parsed_lines: Option<Vec<String>>,
+}
+
+/// A `[ Name ]` section of a changelog entry, with the items contributed by
+/// someone other than whoever signs off the entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChangelogSection {
+    pub author: String,
     pub items: Vec<String>,
 }
 
@@ -35,18 +51,99 @@ impl fmt::Display for ChangelogEntry {
             self.source, self.version, self.distribution, self.options
         )?;
 
-        for entry in self.items.iter() {
-            writeln!(f, "{}", entry)?;
+        for line in self.lines() {
+            writeln!(f, "{}", line)?;
         }
 
-        writeln!(f, "\n -- {}  {}", self.maintainer, self.date.to_rfc2822())
+        // like `date -R`, unlike to_rfc2822 which doesn't zero-pad the day
+        writeln!(
+            f,
+            "\n -- {}  {}",
+            self.maintainer,
+            self.date.format("%a, %d %b %Y %H:%M:%S %z")
+        )
+    }
+}
+
+/// Check a distribution and urgency for a new changelog entry, so that the
+/// entry can be parsed again by `ChangelogEntry::from_str`.
+pub fn check_dist_urgency(distribution: &str, urgency: &str) -> anyhow::Result<()> {
+    let dist_re = Regex::new(r"(?i)^[-+0-9a-z.]+$").unwrap();
+    if !dist_re.is_match(distribution) {
+        return Err(anyhow::format_err!(
+            "invalid changelog distribution: {:?}",
+            distribution
+        ));
+    }
+    if !URGENCIES.contains(&urgency) {
+        return Err(anyhow::format_err!(
+            "invalid changelog urgency: {:?}, must be one of: {}",
+            urgency,
+            URGENCIES.join(", ")
+        ));
+    }
+    Ok(())
+}
+
+/// Check a Debian revision used for the first entry of a new upstream version,
+/// see deb-version(7). It is bumped by `deb_version_suffix_bump` afterwards, so
+/// it must end in a digit.
+pub fn check_debian_revision(revision: &str) -> anyhow::Result<()> {
+    let revision_re = Regex::new(r"^[0-9A-Za-z+.~]*[0-9]$").unwrap();
+    if !revision_re.is_match(revision) {
+        return Err(anyhow::format_err!(
+            "invalid debian_revision: {:?}, must consist of alphanumerics and +.~ and end in a digit",
+            revision
+        ));
     }
+    Ok(())
 }
 
 fn line_is_blank(s: &str) -> bool {
     s.chars().all(char::is_whitespace)
 }
 
+fn trim_blank(mut lines: Vec<String>) -> Vec<String> {
+    while lines.last().map_or(false, |l| line_is_blank(l)) {
+        lines.pop();
+    }
+    let start = lines.iter().take_while(|l| line_is_blank(l)).count();
+    lines.split_off(start)
+}
+
+/// Split the lines of an entry into the items outside of any section, and the
+/// `[ Name ]` sections.
+fn split_sections(lines: Vec<String>) -> (Vec<String>, Vec<ChangelogSection>) {
+    let header_re = Regex::new(r"^\s*\[ (.*) \]\s*$").unwrap();
+    let mut items = Vec::new();
+    let mut sections: Vec<ChangelogSection> = Vec::new();
+    for line in lines {
+        if let Some(m) = header_re.captures(&line) {
+            sections.push(ChangelogSection {
+                author: m[1].to_string(),
+                items: Vec::new(),
+            });
+        } else if let Some(section) = sections.last_mut() {
+            section.items.push(line);
+        } else {
+            items.push(line);
+        }
+    }
+    for section in sections.iter_mut() {
+        section.items = trim_blank(std::mem::take(&mut section.items));
+    }
+    (trim_blank(items), sections)
+}
+
+/// Name part of a "Name <email>" maintainer.
+fn maintainer_name(maintainer: &str) -> String {
+    let re = Regex::new(r"^\s*(\S.*\S)\s*<.*>\s*$").unwrap();
+    match re.captures(maintainer) {
+        Some(matches) => matches[1].to_string(),
+        None => maintainer.trim().to_string(),
+    }
+}
+
 impl str::FromStr for ChangelogEntry {
     type Err = anyhow::Error;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
@@ -74,20 +171,26 @@ impl str::FromStr for ChangelogEntry {
         }
         let re2 = Regex::new(r"^ \-\- ((?:.*) <(?:.*)>)  ?(\w.*\S)\s*$").unwrap();
         let matches2 = re2.captures(lastline).unwrap();
+        let lines: Vec<String> = lines.iter().map(|s| s.to_string()).collect();
+        let (items, sections) = split_sections(lines.clone());
 
-        Ok(Self::new(
+        let mut entry = Self::new(
             matches1[1].to_string(),
             matches1[2].to_string(),
-            matches1[3].to_string(),
-            matches1[4].to_string(),
+            matches1[3].trim().to_string(),
+            matches1[4].trim().to_string(),
             matches2[1].to_string(),
             DateTime::parse_from_rfc2822(&matches2[2])?,
-            lines.iter().map(|s| s.to_string()).collect(),
-        ))
+            items,
+            sections,
+        );
+        entry.parsed_lines = Some(lines);
+        Ok(entry)
     }
 }
 
 impl ChangelogEntry {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         source: String,
         version: String,
@@ -96,6 +199,7 @@ impl ChangelogEntry {
         maintainer: String,
         date: DateTime<FixedOffset>,
         items: Vec<String>,
+        sections: Vec<ChangelogSection>,
     ) -> Self {
         ChangelogEntry {
             source,
@@ -105,31 +209,132 @@ impl ChangelogEntry {
             maintainer,
             date,
             items,
+            sections,
+            parsed_lines: None,
         }
     }
 
+    /// The lines between the heading and the sign-off: the items, followed
+    /// by each section with its `[ Name ]` header. A parsed entry keeps its
+    /// original lines, unless its items or sections were changed.
+    pub fn lines(&self) -> Vec<String> {
+        if let Some(parsed) = &self.parsed_lines {
+            let (items, sections) = split_sections(parsed.clone());
+            if items == self.items && sections == self.sections {
+                return parsed.clone();
+            }
+        }
+        let mut lines = self.items.clone();
+        for section in &self.sections {
+            if !lines.is_empty() {
+                lines.push("".to_string());
+            }
+            lines.push(format!("  [ {} ]", section.author));
+            lines.extend(section.items.iter().cloned());
+        }
+        lines
+    }
+
     pub fn maintainer_name(self: &ChangelogEntry) -> String {
-        let re = Regex::new(r"^\s*(\S.*\S)\s*<.*>\s*$").unwrap();
-        let matches = re.captures(&self.maintainer).unwrap();
-        matches[1].to_string()
+        maintainer_name(&self.maintainer)
+    }
+
+    /// Merge the items and sections of this unreleased entry with a new
+    /// autogenerated item, for the entry to be signed off by `author`.
+    ///
+    /// Items outside of any section belong to whoever signs off the entry. If
+    /// that changes, they are moved into the section of the previous
+    /// maintainer, creating it if needed, and the new author's own section is
+    /// unwrapped. So with several people contributing in turn, each of them
+    /// keeps a section, except for the last one. Older autogenerated items are
+    /// dropped, so merging again gives the same result.
+    pub fn merge_items(
+        self: &ChangelogEntry,
+        author: &str,
+        autogenerated_item: String,
+        autogenerated_re: &Regex,
+    ) -> (Vec<String>, Vec<ChangelogSection>) {
+        let not_autogenerated = |items: &[String]| {
+            items
+                .iter()
+                .filter(|x| !autogenerated_re.is_match(x))
+                .cloned()
+                .collect::<Vec<_>>()
+        };
+        let mut own = not_autogenerated(&self.items);
+        let mut autogenerated_pos = self.items.iter().position(|x| autogenerated_re.is_match(x));
+        let mut sections = self
+            .sections
+            .iter()
+            .map(|s| ChangelogSection {
+                author: s.author.clone(),
+                items: not_autogenerated(&s.items),
+            })
+            .collect::<Vec<_>>();
+
+        let author_name = maintainer_name(author);
+        let entry_name = self.maintainer_name();
+        if entry_name != author_name {
+            let (team_upload, others): (Vec<String>, Vec<String>) =
+                own.into_iter().partition(|x| x == COMMENT_TEAM_UPLOAD);
+            own = team_upload;
+            match sections.iter_mut().find(|s| s.author == entry_name) {
+                Some(section) => {
+                    section.items.push("".to_string());
+                    section.items.extend(others);
+                }
+                None => sections.insert(
+                    0,
+                    ChangelogSection {
+                        author: entry_name,
+                        items: others,
+                    },
+                ),
+            }
+            // the new author's items go first, as they sign off the entry
+            autogenerated_pos = Some(own.len());
+        }
+        if let Some(pos) = sections.iter().position(|s| s.author == author_name) {
+            own.extend(sections.remove(pos).items);
+        }
+        own.insert(autogenerated_pos.unwrap_or(own.len()), autogenerated_item);
+
+        let sections = sections
+            .into_iter()
+            .map(|s| ChangelogSection {
+                author: s.author,
+                items: trim_blank(s.items),
+            })
+            .filter(|s| !s.items.is_empty())
+            .collect();
+        (trim_blank(own), sections)
     }
 
     pub fn version_parts(self: &ChangelogEntry) -> (String, String) {
         let re = Regex::new(r"^(.*)-([^-]*)$").unwrap();
-        let matches = re.captures(&self.version).unwrap();
-        (matches[1].to_string(), matches[2].to_string())
+        match re.captures(&self.version) {
+            Some(matches) => (matches[1].to_string(), matches[2].to_string()),
+            // native version, without a Debian revision
+            None => (self.version.clone(), "".to_string()),
+        }
     }
 
-    pub fn deb_version_suffix(self: &ChangelogEntry) -> String {
-        let re = Regex::new(r".*-([^-]*)$").unwrap();
-        re.captures(&self.version).unwrap()[1].to_string()
+    /// The Debian revision, or None for a native version.
+    pub fn deb_version_suffix(self: &ChangelogEntry) -> Option<String> {
+        let (_, revision) = self.version_parts();
+        if revision.is_empty() {
+            None
+        } else {
+            Some(revision)
+        }
     }
 
-    pub fn deb_version_suffix_bump(self: &ChangelogEntry) -> String {
-        let suf = self.deb_version_suffix();
+    /// The Debian revision after this one, or None for a native version.
+    pub fn deb_version_suffix_bump(self: &ChangelogEntry) -> Option<String> {
+        let suf = self.deb_version_suffix()?;
         let re = Regex::new(r"^((?:.*\D)?)(\d*)$").unwrap();
         let matches = re.captures(&suf).unwrap();
-        if matches[2].is_empty() {
+        Some(if matches[2].is_empty() {
             format!("{}.1", &matches[1])
         } else {
             format!(
@@ -137,7 +342,7 @@ impl ChangelogEntry {
                 &matches[1],
                 (matches[2].parse::<u64>().unwrap() + 1)
             )
-        }
+        })
     }
 }
 
@@ -181,3 +386,6 @@ impl<'a> Iterator for ChangelogIterator<'a> {
         Some(str::from_utf8(result).unwrap())
     }
 }
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,326 @@
+use std::str::FromStr;
+
+use regex::Regex;
+
+use super::{
+    check_debian_revision, ChangelogEntry, ChangelogIterator, ChangelogSection, COMMENT_TEAM_UPLOAD,
+};
+
+const ALICE: &str = "Alice Doe <alice@example.org>";
+const BOB: &str = "Bob Roe <bob@example.org>";
+const CAROL: &str = "Carol Poe <carol@example.org>";
+
+fn entry(maintainer: &str, items: &[&str]) -> ChangelogEntry {
+    ChangelogEntry::from_str(&format!(
+        "rust-foo (1.0.0-1) UNRELEASED; urgency=medium\n\n{}\n\n -- {}  Thu, 01 Jan 2026 00:00:00 +0000\n",
+        items.join("\n"),
+        maintainer
+    ))
+    .unwrap()
+}
+
+/// Merge a new autogenerated item into `e`, returning the resulting lines.
+fn merge(e: &ChangelogEntry, author: &str, version: &str) -> Vec<String> {
+    let re = Regex::new(r"^  \* Package foo (.*) from crates.io using debcargo (.*)$").unwrap();
+    let (items, sections) = e.merge_items(
+        author,
+        format!(
+            "  * Package foo {} from crates.io using debcargo 2.6.0",
+            version
+        ),
+        &re,
+    );
+    ChangelogEntry::new(
+        e.source.clone(),
+        e.version.clone(),
+        e.distribution.clone(),
+        e.options.clone(),
+        author.to_string(),
+        e.date,
+        items,
+        sections,
+    )
+    .lines()
+}
+
+/// Merge again, as if the result of a previous merge was signed off by
+/// `signed_off` and debcargo is now run by `author`.
+fn rerun(items: &[String], signed_off: &str, author: &str) -> Vec<String> {
+    let items = items.iter().map(String::as_str).collect::<Vec<_>>();
+    merge(&entry(signed_off, &items), author, "1.0.1")
+}
+
+#[test]
+fn merge_items_same_author() {
+    let e = entry(
+        ALICE,
+        &[
+            "  * Package foo 1.0.0 from crates.io using debcargo 2.6.0",
+            "  * Fix the tests.",
+        ],
+    );
+    let items = merge(&e, ALICE, "1.0.1");
+    assert_eq!(
+        items,
+        vec![
+            "  * Package foo 1.0.1 from crates.io using debcargo 2.6.0",
+            "  * Fix the tests.",
+        ]
+    );
+    assert_eq!(rerun(&items, ALICE, ALICE), items);
+}
+
+#[test]
+fn merge_items_two_authors() {
+    let e = entry(
+        ALICE,
+        &[
+            COMMENT_TEAM_UPLOAD,
+            "  * Package foo 1.0.0 from crates.io using debcargo 2.6.0",
+            "  * Fix the tests.",
+        ],
+    );
+    let items = merge(&e, BOB, "1.0.1");
+    assert_eq!(
+        items,
+        vec![
+            COMMENT_TEAM_UPLOAD,
+            "  * Package foo 1.0.1 from crates.io using debcargo 2.6.0",
+            "",
+            "  [ Alice Doe ]",
+            "  * Fix the tests.",
+        ]
+    );
+    // running again as the same author changes nothing
+    assert_eq!(rerun(&items, BOB, BOB), items);
+
+    // handing back to the first author unwraps their block again
+    assert_eq!(
+        rerun(&items, BOB, ALICE),
+        vec![
+            COMMENT_TEAM_UPLOAD,
+            "  * Package foo 1.0.1 from crates.io using debcargo 2.6.0",
+            "  * Fix the tests.",
+        ]
+    );
+}
+
+#[test]
+fn merge_items_existing_blocks() {
+    let e = entry(
+        BOB,
+        &[
+            "  * Package foo 1.0.0 from crates.io using debcargo 2.6.0",
+            "  * Bump the MSRV.",
+            "",
+            "  [ Alice Doe ]",
+            "  * Fix the tests.",
+        ],
+    );
+    let items = merge(&e, CAROL, "1.0.1");
+    assert_eq!(
+        items,
+        vec![
+            "  * Package foo 1.0.1 from crates.io using debcargo 2.6.0",
+            "",
+            "  [ Bob Roe ]",
+            "  * Bump the MSRV.",
+            "",
+            "  [ Alice Doe ]",
+            "  * Fix the tests.",
+        ]
+    );
+    assert_eq!(rerun(&items, CAROL, CAROL), items);
+}
+
+#[test]
+fn merge_items_three_authors_in_sequence() {
+    let re = Regex::new(r"^  \* Package foo (.*) from crates.io using debcargo (.*)$").unwrap();
+    let item = |version: &str| {
+        format!(
+            "  * Package foo {} from crates.io using debcargo 2.6.0",
+            version
+        )
+    };
+    let sign_off = |e: &ChangelogEntry, author: &str, version: &str, extra: &str| {
+        let (mut items, sections) = e.merge_items(author, item(version), &re);
+        items.push(extra.to_string());
+        ChangelogEntry::new(
+            e.source.clone(),
+            e.version.clone(),
+            e.distribution.clone(),
+            e.options.clone(),
+            author.to_string(),
+            e.date,
+            items,
+            sections,
+        )
+    };
+    let section = |author: &str, items: &[&str]| ChangelogSection {
+        author: author.to_string(),
+        items: items.iter().map(|s| s.to_string()).collect(),
+    };
+
+    let e = entry(ALICE, &[&item("1.0.0"), "  * Fix the tests."]);
+    let e = sign_off(&e, BOB, "1.0.1", "  * Bump the MSRV.");
+    let e = sign_off(&e, CAROL, "1.0.2", "  * Update the homepage.");
+    assert_eq!(
+        e.items,
+        vec![item("1.0.2"), "  * Update the homepage.".into()]
+    );
+    assert_eq!(
+        e.sections,
+        vec![
+            section("Bob Roe", &["  * Bump the MSRV."]),
+            section("Alice Doe", &["  * Fix the tests."]),
+        ]
+    );
+
+    // the rendered entry parses back into the same sections
+    let parsed = ChangelogEntry::from_str(&e.to_string()).unwrap();
+    assert_eq!(parsed.items, e.items);
+    assert_eq!(parsed.sections, e.sections);
+
+    // the first author comes back, and gets their section unwrapped
+    let e = sign_off(&parsed, ALICE, "1.0.3", "  * Drop a patch.");
+    assert_eq!(
+        e.items,
+        vec![
+            item("1.0.3"),
+            "  * Fix the tests.".into(),
+            "  * Drop a patch.".into()
+        ]
+    );
+    assert_eq!(
+        e.sections,
+        vec![
+            section("Carol Poe", &["  * Update the homepage."]),
+            section("Bob Roe", &["  * Bump the MSRV."]),
+        ]
+    );
+}
+
+#[test]
+fn custom_debian_revision() {
+    for revision in ["1", "0tah1", "1~bpo12+1"] {
+        assert!(check_debian_revision(revision).is_ok(), "{}", revision);
+    }
+    for revision in ["", "1-1", "tah", "1 2"] {
+        assert!(check_debian_revision(revision).is_err(), "{}", revision);
+    }
+
+    let bump = |version: &str| {
+        ChangelogEntry::from_str(&format!(
+            "rust-foo ({}) UNRELEASED; urgency=medium\n\n  * Foo.\n\n -- {}  Thu, 01 Jan 2026 00:00:00 +0000\n",
+            version, ALICE
+        ))
+        .unwrap()
+        .deb_version_suffix_bump()
+    };
+    assert_eq!(bump("1.0.0-0tah1").as_deref(), Some("0tah2"));
+    assert_eq!(bump("1.0.0-1~bpo12+1").as_deref(), Some("1~bpo12+2"));
+    assert_eq!(bump("1.0.0-1").as_deref(), Some("2"));
+    assert_eq!(bump("1:1.0.0-a").as_deref(), Some("a.1"));
+    // native versions have no Debian revision to bump
+    assert_eq!(bump("1.0.0"), None);
+    assert_eq!(bump("1:1.0.0"), None);
+}
+
+const CHANGELOG: &str = "\
+rust-foo (1.2.0-2) unstable; urgency=medium
+
+  * Team upload.
+  * Package foo 1.2.0 from crates.io using debcargo 2.6.0
+
+  [ Alice Doe ]
+  * Fix the tests.
+    - with a nested item,
+
+    - and another one after a blank line.
+
+  * Drop a patch applied upstream.
+
+ -- Bob Roe <bob@example.org>  Thu, 01 Jan 2026 09:05:00 +0100
+
+rust-foo (1.2.0-1) experimental; urgency=low, binary-only=yes
+
+  * Package foo 1.2.0 from crates.io using debcargo 2.6.0
+
+ -- Alice Doe <alice@example.org>  Sun, 07 Dec 2025 23:59:59 -0800
+";
+
+#[test]
+fn changelog_round_trip() {
+    let entries = ChangelogIterator::from(CHANGELOG)
+        .map(|e| ChangelogEntry::from_str(e).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0].distribution, "unstable");
+    assert_eq!(entries[1].options, "urgency=low, binary-only=yes");
+
+    let output = entries
+        .iter()
+        .map(|e| e.to_string())
+        .collect::<Vec<_>>()
+        .join("\n");
+    assert_eq!(output, CHANGELOG);
+
+    // merging as the same author keeps the blank lines inside the block
+    let re = Regex::new(r"^  \* Package foo (.*) from crates.io using debcargo (.*)$").unwrap();
+    let (items, sections) = entries[0].merge_items(
+        "Bob Roe <bob@example.org>",
+        "  * Package foo 1.2.0 from crates.io using debcargo 2.6.0".to_string(),
+        &re,
+    );
+    assert_eq!(items, entries[0].items);
+    assert_eq!(sections, entries[0].sections);
+}
+
+#[test]
+fn changelog_round_trip_irregular_spacing() {
+    let changelog = "\
+rust-foo (1.2.0-1) unstable; urgency=medium
+
+  * Package foo 1.2.0 from crates.io using debcargo 2.6.0
+  [ Alice Doe ]
+  * Fix the tests.
+
+
+   [ Bob Roe ]
+
+  * Bump the MSRV.
+
+ -- Carol Poe <carol@example.org>  Thu, 01 Jan 2026 09:05:00 +0100
+";
+    let mut e = ChangelogEntry::from_str(changelog).unwrap();
+    assert_eq!(e.to_string(), changelog);
+    assert_eq!(
+        e.sections,
+        vec![
+            ChangelogSection {
+                author: "Alice Doe".to_string(),
+                items: vec!["  * Fix the tests.".to_string()],
+            },
+            ChangelogSection {
+                author: "Bob Roe".to_string(),
+                items: vec!["  * Bump the MSRV.".to_string()],
+            },
+        ]
+    );
+
+    // once changed, the entry is rendered with the usual spacing
+    e.items.push("  * Update the homepage.".to_string());
+    assert_eq!(
+        e.lines(),
+        vec![
+            "  * Package foo 1.2.0 from crates.io using debcargo 2.6.0",
+            "  * Update the homepage.",
+            "",
+            "  [ Alice Doe ]",
+            "  * Fix the tests.",
+            "",
+            "  [ Bob Roe ]",
+            "  * Bump the MSRV.",
+        ]
+    );
+}
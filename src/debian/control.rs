@@ -1,7 +1,10 @@
+use std::borrow::Cow;
+use std::collections::BTreeMap;
 use std::env::{self, VarError};
 use std::fmt::{self, Write};
+use std::process::Command;
 
-use anyhow::{format_err, Error};
+use anyhow::Error;
 use semver::Version;
 use textwrap::fill;
 
@@ -16,11 +19,14 @@ pub struct Source {
     uploaders: Vec<String>,
     standards: String,
     build_deps: Vec<String>,
+    build_conflicts: Vec<String>,
     vcs_git: String,
     vcs_browser: String,
     homepage: String,
+    testsuite: Option<String>,
     crate_name: String,
-    requires_root: String,
+    rules_requires_root: String,
+    extra_fields: Vec<(String, String)>,
 }
 
 pub struct Package {
@@ -28,13 +34,20 @@ pub struct Package {
     arch: String,
     multi_arch: String,
     section: Option<String>,
+    pre_depends: Vec<String>,
     depends: Vec<String>,
     recommends: Vec<String>,
     suggests: Vec<String>,
+    enhances: Vec<String>,
+    breaks: Vec<String>,
+    conflicts: Vec<String>,
     provides: Vec<String>,
+    replaces: Vec<String>,
     summary: Description,
     description: Description,
+    description_width: usize,
     extra_lines: Vec<String>,
+    extra_fields: Vec<(String, String)>,
 }
 
 pub struct Description {
@@ -58,6 +71,9 @@ impl fmt::Display for Source {
         writeln!(f, "Section: {}", self.section)?;
         writeln!(f, "Priority: {}", self.priority)?;
         writeln!(f, "Build-Depends: {}", self.build_deps.join(",\n "))?;
+        if !self.build_conflicts.is_empty() {
+            writeln!(f, "Build-Conflicts: {}", self.build_conflicts.join(",\n "))?;
+        }
         writeln!(f, "Maintainer: {}", self.maintainer)?;
         if !self.uploaders.is_empty() {
             writeln!(f, "Uploaders:\n {}", self.uploaders.join(",\n "))?;
@@ -69,6 +85,9 @@ impl fmt::Display for Source {
         if !self.homepage.is_empty() {
             writeln!(f, "Homepage: {}", self.homepage)?;
         }
+        if let Some(testsuite) = &self.testsuite {
+            writeln!(f, "Testsuite: {}", testsuite)?;
+        }
 
         // We used to set this conditionally, however it's best to do it
         // unconditionally as some crates' names have a number suffix e.g.
@@ -77,7 +96,12 @@ impl fmt::Display for Source {
         //   b) "utf-8" crate at version latest with semver_suffix = false.
         // dh-cargo assumes (a) which is wrong for the "utf-8" crate
         writeln!(f, "X-Cargo-Crate: {}", self.crate_name)?;
-        writeln!(f, "Rules-Requires-Root: {}", self.requires_root)?;
+        // Always written: the dpkg default used to be binary-targets, so "no"
+        // has to be explicit for older toolchains.
+        writeln!(f, "Rules-Requires-Root: {}", self.rules_requires_root)?;
+        for (name, value) in &self.extra_fields {
+            writeln!(f, "{}: {}", name, value)?;
+        }
 
         Ok(())
     }
@@ -92,6 +116,9 @@ impl fmt::Display for Package {
             writeln!(f, "Section: {}", section)?;
         }
 
+        if !self.pre_depends.is_empty() {
+            writeln!(f, "Pre-Depends:\n {}", self.pre_depends.join(",\n "))?;
+        }
         if !self.depends.is_empty() {
             writeln!(f, "Depends:\n {}", self.depends.join(",\n "))?;
         }
@@ -101,13 +128,28 @@ impl fmt::Display for Package {
         if !self.suggests.is_empty() {
             writeln!(f, "Suggests:\n {}", self.suggests.join(",\n "))?;
         }
+        if !self.enhances.is_empty() {
+            writeln!(f, "Enhances:\n {}", self.enhances.join(",\n "))?;
+        }
+        if !self.breaks.is_empty() {
+            writeln!(f, "Breaks:\n {}", self.breaks.join(",\n "))?;
+        }
+        if !self.conflicts.is_empty() {
+            writeln!(f, "Conflicts:\n {}", self.conflicts.join(",\n "))?;
+        }
         if !self.provides.is_empty() {
             writeln!(f, "Provides:\n {}", self.provides.join(",\n "))?;
         }
+        if !self.replaces.is_empty() {
+            writeln!(f, "Replaces:\n {}", self.replaces.join(",\n "))?;
+        }
 
         for line in &self.extra_lines {
             writeln!(f, "{}", line)?;
         }
+        for (name, value) in &self.extra_fields {
+            writeln!(f, "{}: {}", name, value)?;
+        }
 
         self.write_description(f)
     }
@@ -146,7 +188,8 @@ impl fmt::Display for PkgTest {
         };
         writeln!(
             f,
-            "Restrictions: allow-stderr, skip-not-installable{}",
+            "Restrictions: {}{}",
+            DEFAULT_TEST_RESTRICTIONS.join(", "),
             restricts,
         )?;
         Ok(())
@@ -172,8 +215,8 @@ impl Source {
         lib: bool,
         maintainer: String,
         uploaders: Vec<String>,
+        vcs_base: &str,
         build_deps: Vec<String>,
-        _requires_root: String,
     ) -> Result<Source> {
         let pkgbase = match name_suffix {
             None => basename.to_string(),
@@ -185,27 +228,29 @@ impl Source {
             "FIXME-IN-THE-SOURCE-SECTION"
         };
         let priority = "optional".to_string();
-        let vcs_browser = format!(
-            "https://salsa.debian.org/rust-team/debcargo-conf/tree/master/src/{}",
-            pkgbase
-        );
-        let vcs_git = format!(
-            "https://salsa.debian.org/rust-team/debcargo-conf.git [src/{}]",
-            pkgbase
-        );
+        let vcs_browser = format!("{}/tree/master/src/{}", vcs_base, pkgbase);
+        let vcs_git = format!("{}.git [src/{}]", vcs_base, pkgbase);
         Ok(Source {
             name: dsc_name(&pkgbase),
             section: section.to_string(),
             priority,
             maintainer,
             uploaders,
-            standards: "4.6.2".to_string(),
+            standards: STANDARDS_VERSION.to_string(),
             build_deps,
+            build_conflicts: vec![],
             vcs_git,
             vcs_browser,
             homepage: home.to_string(),
+            // library crates always get a debian/tests/control
+            testsuite: if lib {
+                Some("autopkgtest-pkg-rust".to_string())
+            } else {
+                None
+            },
             crate_name: crate_name.to_string(),
-            requires_root: "no".to_string(),
+            rules_requires_root: "no".to_string(),
+            extra_fields: vec![],
         })
     }
 
@@ -213,15 +258,43 @@ impl Source {
         &self.name
     }
 
-    pub fn apply_overrides(&mut self, config: &Config) {
+    pub fn apply_overrides(&mut self, config: &Config) -> Result<()> {
         if let Some(section) = config.section() {
             self.section = section.to_string();
+        } else if let Some(bin_section) = config.bin_section() {
+            // binary-only crates get a FIXME section; the binary package's
+            // section is the best guess we have for the source package
+            if self.section.starts_with("FIXME") {
+                self.section = bin_section.to_string();
+            }
         }
 
         if let Some(policy) = config.policy_version() {
             self.standards = policy.to_string();
         }
 
+        if let Some(arch) = config.architecture() {
+            check_arch_list(arch)?;
+            debcargo_info!(
+                "Building the library and binary packages only for: {}",
+                arch
+            );
+        }
+
+        if let Some(priority) = config.priority() {
+            if !PRIORITIES.contains(&priority) {
+                debcargo_bail!(
+                    "source.priority \"{}\" is not one of {}",
+                    priority,
+                    PRIORITIES.join(", ")
+                );
+            }
+            if priority == "extra" {
+                debcargo_warn!("source.priority \"extra\" is deprecated, use \"optional\" instead");
+            }
+            self.priority = priority.to_string();
+        }
+
         self.build_deps.extend(
             config
                 .build_depends()
@@ -235,6 +308,14 @@ impl Source {
             .unwrap_or(&[]);
         self.build_deps.retain(|x| !bdeps_ex.contains(x));
 
+        self.build_conflicts.extend(
+            config
+                .build_conflicts()
+                .into_iter()
+                .flatten()
+                .map(String::to_string),
+        );
+
         if let Some(homepage) = config.homepage() {
             self.homepage = homepage.to_string();
         }
@@ -247,10 +328,120 @@ impl Source {
             self.vcs_browser = vcs_browser.to_string();
         }
 
+        if !config.test || !config.testsuite() {
+            self.testsuite = None;
+        }
+
         if let Some(requires_root) = config.requires_root() {
-            self.requires_root = requires_root.to_string();
+            check_rules_requires_root(requires_root)?;
+            self.rules_requires_root = requires_root.to_string();
+        }
+
+        if let Some(fields) = config.extra_control_fields() {
+            self.extra_fields = check_extra_fields(fields)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Check that `arch` is a list of Debian architectures or architecture
+/// wildcards, such as "amd64 arm64" or "linux-any", for source.architecture.
+fn check_arch_list(arch: &str) -> Result<()> {
+    let archs: Vec<&str> = arch.split_whitespace().collect();
+    let valid = |a: &str| {
+        a.starts_with(|c: char| c.is_ascii_lowercase() || c.is_ascii_digit())
+            && a.chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+    };
+    if archs.is_empty() || !archs.iter().all(|a| valid(a)) {
+        debcargo_bail!(
+            "source.architecture {:?} is not a space-separated list of Debian \
+             architectures or wildcards",
+            arch
+        );
+    }
+    if archs.len() > 1 && archs.iter().any(|a| *a == "all" || *a == "any") {
+        debcargo_bail!(
+            "source.architecture {:?}: \"all\" and \"any\" cannot be combined \
+             with other architectures",
+            arch
+        );
+    }
+    Ok(())
+}
+
+/// Check a Rules-Requires-Root value: "no", "binary-targets" or a
+/// space-separated list of keywords of the form `namespace/case`.
+fn check_rules_requires_root(value: &str) -> Result<()> {
+    if value == "no" || value == "binary-targets" {
+        return Ok(());
+    }
+    let valid_keyword = |k: &str| {
+        k.contains('/')
+            && k.split('/').all(|p| {
+                !p.is_empty()
+                    && p.chars()
+                        .all(|c| c.is_ascii_alphanumeric() || "+-._".contains(c))
+            })
+    };
+    let keywords: Vec<&str> = value.split_whitespace().collect();
+    if keywords.is_empty() || !keywords.iter().all(|k| valid_keyword(k)) {
+        debcargo_bail!(
+            "invalid requires_root value {:?}: expected \"no\", \"binary-targets\" \
+            or a space-separated list of namespace/case keywords",
+            value
+        );
+    }
+    Ok(())
+}
+
+/// Check a Multi-Arch value, and that it can be combined with the
+/// Architecture `arch` of the package.
+fn check_arch_multi_arch(arch: &str, multi_arch: &str) -> Result<()> {
+    if !["same", "foreign", "allowed", "no"].contains(&multi_arch) {
+        debcargo_bail!(
+            "invalid multi_arch value {:?}: expected one of same, foreign, allowed or no",
+            multi_arch
+        );
+    }
+    if arch.split_whitespace().next().is_none() {
+        debcargo_bail!("architecture must not be empty");
+    }
+    if arch == "all" && multi_arch == "same" {
+        debcargo_bail!("Architecture: all packages cannot be Multi-Arch: same");
+    }
+    Ok(())
+}
+
+/// Check that extra control fields can be written as-is, see deb822(5): field
+/// names are printable ASCII without colons or spaces, that don't start with
+/// "#" or "-", and values only span several lines as continuation lines.
+fn check_extra_fields(fields: &BTreeMap<String, String>) -> Result<Vec<(String, String)>> {
+    let mut checked = vec![];
+    for (name, value) in fields {
+        let valid_name = !name.is_empty()
+            && !name.starts_with('#')
+            && !name.starts_with('-')
+            && name.chars().all(|c| c.is_ascii_graphic() && c != ':');
+        if !valid_name {
+            debcargo_bail!("invalid control field name {:?}", name);
         }
+        let value = value.trim_end();
+        let bare_newline = value.split('\n').skip(1).any(|line| {
+            !(line.starts_with(' ') || line.starts_with('\t')) || line.trim().is_empty()
+        });
+        if value.is_empty() || bare_newline {
+            debcargo_bail!(
+                "invalid value for control field {}: {:?}, continuation lines must \
+                 start with a space and must not be empty",
+                name,
+                value
+            );
+        }
+        checked.push((name.clone(), value.to_string()));
     }
+    Ok(checked)
 }
 
 impl Package {
@@ -334,6 +525,14 @@ impl Package {
         depends.extend(f_deps.into_iter().map(deb_feature));
         depends.extend(o_deps);
 
+        let (breaks, replaces) = match (name_suffix, feature) {
+            (Some(_), None) => {
+                let fullpkg = format!("{}-{}", basename, version);
+                (vec![deb_name(&fullpkg)], vec![deb_name(&fullpkg)])
+            }
+            (_, _) => (vec![], vec![]),
+        };
+
         Ok(Package {
             name: match feature {
                 None => deb_name(&pkgbase),
@@ -357,30 +556,60 @@ impl Package {
             //
             // The recommended work-around for now from the dpkg developers is to
             // make our packages arch:any M-A:same even though this results in
-            // duplicate packages in the Debian archive. For very large crates we
-            // will eventually want to make debcargo generate -data packages that
-            // are arch:all and have the arch:any -dev packages depend on it.
+            // duplicate packages in the Debian archive. For very large crates,
+            // data_package makes debcargo generate a -data package that is
+            // arch:all, which the arch:any -dev packages depend on.
             multi_arch: "same".to_string(),
             section: None,
+            pre_depends: vec![],
             depends,
             recommends,
             suggests,
+            enhances: vec![],
+            breaks,
+            conflicts: vec![],
             provides,
+            replaces,
             summary,
             description,
-            extra_lines: match (name_suffix, feature) {
-                (Some(_), None) => {
-                    let fullpkg = format!("{}-{}", basename, version);
-                    vec![
-                        format!("Replaces: {}", deb_name(&fullpkg)),
-                        format!("Breaks: {}", deb_name(&fullpkg)),
-                    ]
-                }
-                (_, _) => vec![],
-            },
+            description_width: config::DEFAULT_DESCRIPTION_WIDTH,
+            extra_lines: vec![],
+            extra_fields: vec![],
         })
     }
 
+    pub fn new_data(
+        basename: &str,
+        name_suffix: Option<&str>,
+        summary: Description,
+        description: Description,
+    ) -> Self {
+        let pkgbase = match name_suffix {
+            None => basename.to_string(),
+            Some(suf) => format!("{}{}", basename, suf),
+        };
+        Package {
+            name: deb_data_name(&pkgbase),
+            arch: "all".to_string(),
+            multi_arch: "foreign".to_string(),
+            section: None,
+            pre_depends: vec![],
+            depends: vec!["${misc:Depends}".to_string()],
+            recommends: vec![],
+            suggests: vec![],
+            enhances: vec![],
+            breaks: vec![],
+            conflicts: vec![],
+            provides: vec![],
+            replaces: vec![],
+            summary,
+            description,
+            description_width: config::DEFAULT_DESCRIPTION_WIDTH,
+            extra_lines: vec![],
+            extra_fields: vec![],
+        }
+    }
+
     pub fn new_bin(
         basename: &str,
         name_suffix: Option<&str>,
@@ -401,6 +630,7 @@ impl Package {
             arch: "any".to_string(),
             multi_arch: "allowed".to_string(),
             section: section.map(|s| s.to_string()),
+            pre_depends: vec![],
             depends: vec![
                 "${misc:Depends}".to_string(),
                 "${shlibs:Depends}".to_string(),
@@ -408,13 +638,19 @@ impl Package {
             ],
             recommends: vec!["${cargo:Recommends}".to_string()],
             suggests: vec!["${cargo:Suggests}".to_string()],
+            enhances: vec![],
+            breaks: vec![],
+            conflicts: vec![],
             provides,
+            replaces: vec![],
             summary,
             description,
+            description_width: config::DEFAULT_DESCRIPTION_WIDTH,
             extra_lines: vec![
                 "Built-Using: ${cargo:Built-Using}".to_string(),
                 "XB-X-Cargo-Built-Using: ${cargo:X-Cargo-Built-Using}".to_string(),
             ],
+            extra_fields: vec![],
         }
     }
 
@@ -422,10 +658,55 @@ impl Package {
         self.name.as_str()
     }
 
+    pub fn provides(&self) -> &[String] {
+        &self.provides
+    }
+
+    pub fn set_multi_arch(&mut self, multi_arch: &str) {
+        self.multi_arch = multi_arch.to_string();
+    }
+
+    /// Take over from an older package, that is no longer built or is now
+    /// provided by this one. `version` is the upstream version, with the
+    /// epoch if there is one.
+    pub fn break_and_replace(&mut self, old_package: &str, version: &str) {
+        let rel = format!("{} (<< {}-~~)", old_package, version);
+        self.breaks.push(rel.clone());
+        self.replaces.push(rel);
+    }
+
+    /// Declare that this package enhances `package`, i.e. that `package`
+    /// works with but does not need this one.
+    pub fn enhance(&mut self, package: &str) {
+        self.enhances.push(package.to_string());
+    }
+
+    fn apply_arch_overrides(&mut self, arch: Option<&str>, multi_arch: Option<&str>) -> Result<()> {
+        if let Some(arch) = arch {
+            self.arch = arch.to_string();
+            // arch-independent packages can satisfy dependencies of any
+            // architecture
+            if arch == "all" {
+                self.multi_arch = "foreign".to_string();
+            }
+        }
+        if let Some(multi_arch) = multi_arch {
+            self.multi_arch = multi_arch.to_string();
+        }
+        check_arch_multi_arch(&self.arch, &self.multi_arch)
+    }
+
     fn write_description(&self, out: &mut fmt::Formatter) -> fmt::Result {
         writeln!(out, "Description: {}", &self.summary)?;
         let description = format!("{}", &self.description);
-        for line in fill(description.trim(), 79).lines() {
+        let description = description.trim();
+        // a width of 0 keeps the description as written
+        let description = if self.description_width == 0 {
+            Cow::Borrowed(description)
+        } else {
+            Cow::Owned(fill(description, self.description_width))
+        };
+        for line in description.lines() {
             let line = line.trim_end();
             if line.is_empty() {
                 writeln!(out, " .")?;
@@ -438,24 +719,70 @@ impl Package {
         Ok(())
     }
 
-    #[allow(clippy::result_unit_err)]
-    pub fn summary_check_len(&self) -> std::result::Result<(), ()> {
-        if self.summary.prefix.len() <= 80 {
+    /// Check the length of the summary as written to debian/control, i.e.
+    /// including its suffix, against `MAX_SYNOPSIS_LEN`, returning its length
+    /// in characters if it is too long.
+    pub fn summary_check_len(&self) -> std::result::Result<(), usize> {
+        let len = self.summary.to_string().chars().count();
+        if len <= MAX_SYNOPSIS_LEN {
             Ok(())
         } else {
-            Err(())
+            Err(len)
         }
     }
 
-    pub fn apply_overrides(&mut self, config: &Config, key: PackageKey, f_provides: Vec<&str>) {
+    pub fn apply_overrides(
+        &mut self,
+        config: &Config,
+        key: PackageKey,
+        f_provides: Vec<&str>,
+    ) -> Result<()> {
+        match key {
+            PackageKey::Bin => {
+                if let Some(section) = config.bin_section().or_else(|| config.section()) {
+                    self.section = Some(section.to_string());
+                }
+                self.apply_arch_overrides(
+                    config
+                        .package_architecture(key)
+                        .or_else(|| config.architecture()),
+                    config.package_multi_arch(key),
+                )?;
+            }
+            PackageKey::FeatureLib(_)
+                if config.package_architecture(key).is_some()
+                    || config.package_multi_arch(key).is_some() =>
+            {
+                debcargo_bail!(
+                    "architecture and multi_arch can only be given for [packages.lib], \
+                    as all library packages must agree on them"
+                );
+            }
+            // the feature packages depend on the exact binary version of the
+            // library package, which only works when they share an architecture
+            PackageKey::BareLib | PackageKey::FeatureLib(_) => {
+                self.apply_arch_overrides(
+                    config
+                        .package_architecture(PackageKey::BareLib)
+                        .or_else(|| config.architecture()),
+                    config.package_multi_arch(PackageKey::BareLib),
+                )?;
+            }
+        }
         if let Some(section) = config.package_section(key) {
             self.section = Some(section.to_string());
         }
+        self.description_width = config.description_width;
         self.summary
             .apply_overrides(&config.summary, config.package_summary(key));
         self.description
             .apply_overrides(&config.description, config.package_description(key));
 
+        self.pre_depends.extend(config::package_field_for_feature(
+            &|x| config.package_pre_depends(x),
+            key,
+            &f_provides,
+        ));
         self.depends.extend(config::package_field_for_feature(
             &|x| config.package_depends(x),
             key,
@@ -471,12 +798,38 @@ impl Package {
             key,
             &f_provides,
         ));
+        self.enhances.extend(config::package_field_for_feature(
+            &|x| config.package_enhances(x),
+            key,
+            &f_provides,
+        ));
+        self.breaks.extend(config::package_field_for_feature(
+            &|x| config.package_breaks(x),
+            key,
+            &f_provides,
+        ));
+        self.conflicts.extend(config::package_field_for_feature(
+            &|x| config.package_conflicts(x),
+            key,
+            &f_provides,
+        ));
+        // appended to, rather than replacing, the auto-generated provides
         self.provides.extend(config::package_field_for_feature(
             &|x| config.package_provides(x),
             key,
             &f_provides,
         ));
+        self.replaces.extend(config::package_field_for_feature(
+            &|x| config.package_replaces(x),
+            key,
+            &f_provides,
+        ));
 
+        // dh-cargo always computes the substvars, so Built-Using is disabled
+        // by not using them
+        if config.built_using == Some(false) {
+            self.extra_lines.retain(|l| !l.contains("${cargo:"));
+        }
         self.extra_lines.extend(
             config
                 .package_extra_lines(key)
@@ -484,6 +837,10 @@ impl Package {
                 .flatten()
                 .map(|s| s.to_string()),
         );
+        if let Some(fields) = config.package_extra_control_fields(key) {
+            self.extra_fields = check_extra_fields(fields)?;
+        }
+        Ok(())
     }
 }
 
@@ -536,6 +893,38 @@ pub fn deb_upstream_version(v: &Version) -> String {
     s
 }
 
+/// Whether the string is a syntactically valid Debian version, see
+/// deb-version(7).
+pub fn is_deb_version(v: &str) -> bool {
+    let (epoch, rest) = match v.split_once(':') {
+        Some((epoch, rest)) => (Some(epoch), rest),
+        None => (None, v),
+    };
+    let (upstream, revision) = match rest.rsplit_once('-') {
+        Some((upstream, revision)) => (upstream, Some(revision)),
+        None => (rest, None),
+    };
+    let valid = |s: &str, extra: &str| {
+        s.chars()
+            .all(|c| c.is_ascii_alphanumeric() || extra.contains(c))
+    };
+    epoch.map_or(true, |e| {
+        !e.is_empty() && e.chars().all(|c| c.is_ascii_digit())
+    }) && upstream.starts_with(|c: char| c.is_ascii_digit())
+        && valid(upstream, ".+~-:")
+        && revision.map_or(true, |r| !r.is_empty() && valid(r, ".+~"))
+}
+
+/// Whether the string is a valid Debian package name, see Debian policy
+/// §5.6.1: at least two characters, starting with an alphanumeric one.
+pub fn is_deb_package_name(name: &str) -> bool {
+    name.len() >= 2
+        && name.starts_with(|c: char| c.is_ascii_lowercase() || c.is_ascii_digit())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || "+-.".contains(c))
+}
+
 pub fn base_deb_name(crate_name: &str) -> String {
     crate_name.replace('_', "-").to_lowercase()
 }
@@ -548,20 +937,142 @@ pub fn deb_name(name: &str) -> String {
     format!("{}-{}-dev", Package::pkg_prefix(), base_deb_name(name))
 }
 
+pub fn deb_data_name(name: &str) -> String {
+    format!("{}-{}-data", Package::pkg_prefix(), base_deb_name(name))
+}
+
+/// Normalize a feature name for use in a Debian package name, which may only
+/// contain lowercase ASCII letters, digits, `+`, `-` and `.`. Cargo features
+/// may also contain `_` and non-ASCII letters; these, and `+` which separates
+/// the feature from the crate name, become `-`. A leading digit is fine, as
+/// the feature never starts the package name.
+pub fn deb_feature_base_name(feature: &str) -> String {
+    feature
+        .chars()
+        .map(|c| match c.to_ascii_lowercase() {
+            c @ ('a'..='z' | '0'..='9' | '.' | '-') => c,
+            _ => '-',
+        })
+        .collect()
+}
+
 pub fn deb_feature_name(name: &str, feature: &str) -> String {
     format!(
         "{}-{}+{}-dev",
         Package::pkg_prefix(),
         base_deb_name(name),
-        base_deb_name(feature)
+        deb_feature_base_name(feature)
     )
 }
 
-/// Retrieve one of a series of environment variables, and provide a friendly error message for
-/// non-UTF-8 values.
-fn get_envs(keys: &[&str]) -> Result<Option<String>> {
+/// Debian policy version that the generated packages comply with, used as
+/// Standards-Version unless source.policy overrides it.
+pub const STANDARDS_VERSION: &str = "4.7.2";
+
+/// Recommended maximum length of the synopsis, i.e. the first line of the
+/// Description, as per the Debian developer's reference §6.2.2.
+pub const MAX_SYNOPSIS_LEN: usize = 60;
+
+/// Debian does not limit the length of package names, but longer ones than
+/// this get unwieldy, e.g. in .deb file names and apt output.
+pub const MAX_PACKAGE_NAME_LEN: usize = 80;
+
+/// Values of the Priority field permitted by Debian policy §2.5.
+pub const PRIORITIES: &[&str] = &["required", "important", "standard", "optional", "extra"];
+
+/// Restrictions that every generated autopkgtest has.
+pub const DEFAULT_TEST_RESTRICTIONS: &[&str] = &["allow-stderr", "skip-not-installable"];
+
+/// Restrictions that autopkgtest knows about, see README.package-tests.rst in
+/// the autopkgtest package. Tests with any other restriction are skipped.
+pub const TEST_RESTRICTIONS: &[&str] = &[
+    "allow-stderr",
+    "breaks-testbed",
+    "build-needed",
+    "flaky",
+    "hint-testsuite-triggers",
+    "isolation-container",
+    "isolation-machine",
+    "needs-internet",
+    "needs-reboot",
+    "needs-root",
+    "needs-sudo",
+    "rw-build-tree",
+    "skip-foreign-architecture",
+    "skip-not-installable",
+    "skippable",
+    "superficial",
+];
+
+/// Names of the binary packages listed in a debian/control.
+pub fn binary_packages_in_control(control: &str) -> Vec<String> {
+    control
+        .lines()
+        .filter_map(|l| l.strip_prefix("Package:"))
+        .map(|p| p.trim().to_string())
+        .collect()
+}
+
+/// Names of the feature packages of `pkgbase` listed in a debian/control.
+pub fn feature_packages_in_control(control: &str, pkgbase: &str) -> Vec<String> {
+    let prefix = format!("{}+", deb_name(pkgbase).trim_end_matches("-dev"));
+    binary_packages_in_control(control)
+        .into_iter()
+        .filter(|p| p.starts_with(&prefix))
+        .collect()
+}
+
+/// Names of the feature packages of `pkgbase` that a debian/control Breaks,
+/// i.e. that were already dropped before the run that generated it.
+pub fn broken_feature_packages_in_control(control: &str, pkgbase: &str) -> Vec<String> {
+    let prefix = format!("{}+", deb_name(pkgbase).trim_end_matches("-dev"));
+    let mut packages: Vec<String> = vec![];
+    let mut in_breaks = false;
+    for line in control.lines() {
+        let value = match line.strip_prefix("Breaks:") {
+            Some(value) => value,
+            None if in_breaks && line.starts_with(' ') => line,
+            None => {
+                in_breaks = false;
+                continue;
+            }
+        };
+        in_breaks = true;
+        for package in value.split(',').filter_map(|r| r.split_whitespace().next()) {
+            if package.starts_with(&prefix) && !packages.iter().any(|p| p == package) {
+                packages.push(package.to_string());
+            }
+        }
+    }
+    packages
+}
+
+/// Group features by the Debian package name they are packaged as, keeping
+/// only the names that more than one feature maps to.
+pub fn feature_name_collisions<'a>(
+    pkgbase: &str,
+    features: impl IntoIterator<Item = &'a str>,
+) -> BTreeMap<String, Vec<&'a str>> {
+    let mut names: BTreeMap<String, Vec<&str>> = BTreeMap::new();
+    for feature in features {
+        let name = match feature {
+            "" => deb_name(pkgbase),
+            _ => deb_feature_name(pkgbase, feature),
+        };
+        names.entry(name).or_default().push(feature);
+    }
+    names.retain(|_, features| features.len() > 1);
+    names
+}
+
+/// Retrieve one of a series of environment variables via `getenv`, and provide a friendly error
+/// message for non-UTF-8 values.
+fn get_envs(
+    getenv: &dyn Fn(&str) -> std::result::Result<String, VarError>,
+    keys: &[&str],
+) -> Result<Option<String>> {
     for key in keys {
-        match env::var(key) {
+        match getenv(key) {
             Ok(val) => {
                 return Ok(Some(val));
             }
@@ -575,13 +1086,57 @@ fn get_envs(keys: &[&str]) -> Result<Option<String>> {
     Ok(None)
 }
 
-/// Determine a name and email address from environment variables.
+/// Retrieve a value from the user's git configuration, if git is available and
+/// the value is set.
+fn get_git_config(key: &str) -> Result<Option<String>> {
+    let output = match Command::new("git").args(["config", "--get", key]).output() {
+        Ok(output) => output,
+        Err(_) => return Ok(None),
+    };
+    if !output.status.success() {
+        return Ok(None);
+    }
+    let val = String::from_utf8(output.stdout)
+        .map_err(|e| Error::from(e).context(format!("git config {} not valid UTF-8", key)))?;
+    let val = val.trim();
+    Ok(if val.is_empty() {
+        None
+    } else {
+        Some(val.to_string())
+    })
+}
+
+/// Determine a name and email address from environment variables, falling
+/// back to the git configuration.
 pub fn get_deb_author() -> Result<String> {
-    let name = get_envs(&["DEBFULLNAME", "NAME"])?.ok_or_else(|| {
-        format_err!("Unable to determine your name; please set $DEBFULLNAME or $NAME")
+    deb_author(&|key| env::var(key), &get_git_config)
+}
+
+/// Like `get_deb_author`, with the environment and the git configuration
+/// looked up by `getenv` and `git_config`.
+fn deb_author(
+    getenv: &dyn Fn(&str) -> std::result::Result<String, VarError>,
+    git_config: &dyn Fn(&str) -> Result<Option<String>>,
+) -> Result<String> {
+    let name = match get_envs(getenv, &["DEBFULLNAME", "NAME"])? {
+        Some(name) => Some(name),
+        None => git_config("user.name")?,
+    }
+    .ok_or_else(|| {
+        DebcargoError::MissingAuthor(
+            "Unable to determine your name; please set $DEBFULLNAME or $NAME, or git config user.name"
+                .to_string(),
+        )
     })?;
-    let email = get_envs(&["DEBEMAIL", "EMAIL"])?.ok_or_else(|| {
-        format_err!("Unable to determine your email; please set $DEBEMAIL or $EMAIL")
+    let email = match get_envs(getenv, &["DEBEMAIL", "EMAIL"])? {
+        Some(email) => Some(email),
+        None => git_config("user.email")?,
+    }
+    .ok_or_else(|| {
+        DebcargoError::MissingAuthor(
+            "Unable to determine your email; please set $DEBEMAIL or $EMAIL, or git config user.email"
+                .to_string(),
+        )
     })?;
     Ok(format!("{} <{}>", name, email))
 }
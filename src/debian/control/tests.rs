@@ -1,4 +1,15 @@
-use super::PkgTest;
+use std::env::VarError;
+
+use semver::Version;
+
+use super::{
+    broken_feature_packages_in_control, deb_author, deb_data_name, deb_feature_name,
+    feature_name_collisions, feature_packages_in_control, is_deb_package_name, is_deb_version,
+    Description, Package, PkgTest, Source, MAX_SYNOPSIS_LEN, STANDARDS_VERSION,
+};
+use crate::config::{Config, PackageKey};
+use crate::debian::tests::parse_config;
+use crate::errors::{error_kind, DebcargoError};
 
 struct PkgTestFmtData<'a> {
     feature: &'a str,
@@ -50,3 +61,715 @@ Restrictions: allow-stderr, skip-not-installable, flaky
         assert_eq!(check.expected, &pkgtest.to_string());
     }
 }
+
+#[test]
+fn package_fmt_relationship_field_order() {
+    let package = Package::new(
+        "crate",
+        Some("-1"),
+        &Version::new(1, 2, 3),
+        Description {
+            prefix: "Summary".into(),
+            suffix: "".into(),
+        },
+        Description {
+            prefix: "".into(),
+            suffix: "".into(),
+        },
+        None,
+        vec![],
+        vec![],
+        vec![],
+        vec![],
+        vec![],
+    )
+    .unwrap();
+    let out = package.to_string();
+
+    let breaks = out.find("Breaks:\n librust-crate-1.2.3-dev\n").unwrap();
+    let provides = out.find("Provides:\n").unwrap();
+    let replaces = out.find("Replaces:\n librust-crate-1.2.3-dev\n").unwrap();
+    assert!(breaks < provides);
+    assert!(provides < replaces);
+    assert!(!out.contains("Conflicts:"));
+}
+
+#[test]
+fn deb_author_falls_back_to_git_config() {
+    let git_config = |key: &str| -> crate::errors::Result<Option<String>> {
+        Ok(match key {
+            "user.name" => Some("Jordan Doe".to_string()),
+            _ => Some("jordan@example.org".to_string()),
+        })
+    };
+    let no_env = |_: &str| -> Result<String, VarError> { Err(VarError::NotPresent) };
+    assert_eq!(
+        deb_author(&no_env, &git_config).unwrap(),
+        "Jordan Doe <jordan@example.org>"
+    );
+
+    let env = |key: &str| -> Result<String, VarError> {
+        match key {
+            "DEBFULLNAME" => Ok("Jane Doe".to_string()),
+            _ => Err(VarError::NotPresent),
+        }
+    };
+    assert_eq!(
+        deb_author(&env, &git_config).unwrap(),
+        "Jane Doe <jordan@example.org>"
+    );
+
+    let no_email = |key: &str| -> crate::errors::Result<Option<String>> {
+        Ok(match key {
+            "user.name" => Some("Jordan Doe".to_string()),
+            _ => None,
+        })
+    };
+    let err = deb_author(&env, &no_email).unwrap_err();
+    match error_kind(&err) {
+        Some(DebcargoError::MissingAuthor(msg)) => assert!(msg.contains("$DEBEMAIL")),
+        kind => panic!("unexpected error kind {:?}", kind),
+    }
+    assert!(!error_kind(&err).unwrap().is_recoverable());
+}
+
+fn test_source() -> Source {
+    Source::new(
+        "crate",
+        None,
+        "crate",
+        "",
+        true,
+        "Maintainer <m@example.org>".to_string(),
+        vec![],
+        crate::config::RUST_TEAM_VCS,
+        vec!["debhelper (>= 12)".to_string()],
+    )
+    .unwrap()
+}
+
+#[test]
+fn source_architecture_override() {
+    let config = parse_config(
+        "[source]\narchitecture = \"amd64 arm64\"\n\
+         [packages.bin]\narchitecture = \"all\"\n",
+    );
+    test_source().apply_overrides(&config).unwrap();
+    let mut lib = test_package();
+    lib.apply_overrides(&config, PackageKey::BareLib, vec![])
+        .unwrap();
+    let out = lib.to_string();
+    assert!(out.contains("\nArchitecture: amd64 arm64\nMulti-Arch: same\n"));
+    let mut feature = test_package();
+    feature
+        .apply_overrides(&config, PackageKey::feature("std"), vec![])
+        .unwrap();
+    assert!(feature
+        .to_string()
+        .contains("\nArchitecture: amd64 arm64\n"));
+    // the per-package architecture wins
+    let description = || Description {
+        prefix: "Summary".into(),
+        suffix: "".into(),
+    };
+    let mut bin = Package::new_bin("crate", None, None, description(), description());
+    bin.apply_overrides(&config, PackageKey::Bin, vec![])
+        .unwrap();
+    assert!(bin.to_string().contains("\nArchitecture: all\n"));
+
+    for arch in &["", "amd64,arm64", "any amd64", "AMD64"] {
+        let config = parse_config(&format!("[source]\narchitecture = {:?}\n", arch));
+        assert!(test_source().apply_overrides(&config).is_err(), "{}", arch);
+    }
+}
+
+#[test]
+fn source_standards_version() {
+    let mut source = test_source();
+    assert!(source
+        .to_string()
+        .contains(&format!("\nStandards-Version: {}\n", STANDARDS_VERSION)));
+
+    let config = parse_config("[source]\npolicy = \"4.0.0\"\n");
+    source.apply_overrides(&config).unwrap();
+    assert!(source.to_string().contains("\nStandards-Version: 4.0.0\n"));
+}
+
+#[test]
+fn source_priority_override() {
+    let mut source = test_source();
+    assert!(source.to_string().contains("\nPriority: optional\n"));
+
+    let config = parse_config("[source]\npriority = \"standard\"\n");
+    source.apply_overrides(&config).unwrap();
+    assert!(source.to_string().contains("\nPriority: standard\n"));
+
+    let config = parse_config("[source]\npriority = \"low\"\n");
+    assert!(source.apply_overrides(&config).is_err());
+}
+
+#[test]
+fn source_vcs_from_vcs_base() {
+    let source = test_source().to_string();
+    assert!(source
+        .contains("\nVcs-Git: https://salsa.debian.org/rust-team/debcargo-conf.git [src/crate]\n"));
+    assert!(source.contains(
+        "\nVcs-Browser: https://salsa.debian.org/rust-team/debcargo-conf/tree/master/src/crate\n"
+    ));
+
+    let config = parse_config("vcs_base = \"https://git.example.org/rust/debcargo-conf/\"\n");
+    let source = Source::new(
+        "crate",
+        None,
+        "crate",
+        "",
+        true,
+        config.maintainer().to_string(),
+        vec![],
+        config.vcs_base(),
+        vec![],
+    )
+    .unwrap()
+    .to_string();
+    assert!(
+        source.contains("\nVcs-Git: https://git.example.org/rust/debcargo-conf.git [src/crate]\n")
+    );
+    assert!(source.contains(
+        "\nVcs-Browser: https://git.example.org/rust/debcargo-conf/tree/master/src/crate\n"
+    ));
+}
+
+#[test]
+fn source_always_has_cargo_crate() {
+    // also for crates whose name is the same as its dashed form
+    let source = test_source();
+    assert!(source.to_string().contains("\nX-Cargo-Crate: crate\n"));
+
+    let source = Source::new(
+        "utf-8",
+        None,
+        "utf-8",
+        "",
+        true,
+        "Maintainer <m@example.org>".to_string(),
+        vec![],
+        crate::config::RUST_TEAM_VCS,
+        vec![],
+    )
+    .unwrap();
+    assert!(source.to_string().contains("\nX-Cargo-Crate: utf-8\n"));
+}
+
+#[test]
+fn source_rules_requires_root_from_config() {
+    let mut source = test_source();
+    source.apply_overrides(&Config::default()).unwrap();
+    assert!(source.to_string().contains("\nRules-Requires-Root: no\n"));
+
+    let mut config = Config::default();
+    config.requires_root = Some("binary-targets".to_string());
+    let mut source = test_source();
+    source.apply_overrides(&config).unwrap();
+    assert!(source
+        .to_string()
+        .contains("\nRules-Requires-Root: binary-targets\n"));
+
+    config.requires_root = Some("dpkg/target-subcommand foo/bar-baz".to_string());
+    assert!(test_source().apply_overrides(&config).is_ok());
+
+    for invalid in &["yes", "", "dpkg", "dpkg/"] {
+        config.requires_root = Some(invalid.to_string());
+        assert!(test_source().apply_overrides(&config).is_err());
+    }
+}
+
+#[test]
+fn source_build_conflicts_only_when_set() {
+    let mut source = test_source();
+    source.apply_overrides(&Config::default()).unwrap();
+    assert!(!source.to_string().contains("Build-Conflicts:"));
+
+    let config = parse_config("[source]\nbuild_conflicts = [\"libfoo-dev\", \"bar (<< 2)\"]\n");
+    let mut source = test_source();
+    source.apply_overrides(&config).unwrap();
+    assert!(source
+        .to_string()
+        .contains("Build-Depends: debhelper (>= 12)\nBuild-Conflicts: libfoo-dev,\n bar (<< 2)\n"));
+}
+
+#[test]
+fn source_testsuite_for_lib() {
+    let source = test_source();
+    assert!(source
+        .to_string()
+        .contains("\nTestsuite: autopkgtest-pkg-rust\n"));
+
+    let config = parse_config("[source]\ntestsuite = false\n");
+    let mut source = test_source();
+    source.apply_overrides(&config).unwrap();
+    assert!(!source.to_string().contains("Testsuite:"));
+}
+
+#[test]
+fn package_description_width() {
+    let mut package = Package::new_data(
+        "crate",
+        None,
+        Description {
+            prefix: "Summary".into(),
+            suffix: "".into(),
+        },
+        Description {
+            prefix: "A fairly long line of description text.\n\n- a bullet point\n".into(),
+            suffix: "".into(),
+        },
+    );
+
+    package.description_width = 20;
+    let out = package.to_string();
+    assert!(out.ends_with(
+        "Description: Summary
+ A fairly long line
+ of description text.
+ .
+  - a bullet point
+"
+    ));
+
+    package.description_width = 0;
+    let out = package.to_string();
+    assert!(out.ends_with(
+        "Description: Summary
+ A fairly long line of description text.
+ .
+  - a bullet point
+"
+    ));
+}
+
+#[test]
+fn check_is_deb_version() {
+    for v in [
+        "25",
+        "25.1~bpo1",
+        "1:2.3-4",
+        "2.3-rc1-1+deb12u1",
+        "1.0+dfsg1",
+    ] {
+        assert!(is_deb_version(v), "{}", v);
+    }
+    for v in ["", ">= 25", "x1.0", "1.0-", ":1.0", "a:1.0", "1.0_1"] {
+        assert!(!is_deb_version(v), "{}", v);
+    }
+}
+
+fn test_package() -> Package {
+    Package::new(
+        "crate",
+        None,
+        &Version::new(1, 2, 3),
+        Description {
+            prefix: "Summary".into(),
+            suffix: "".into(),
+        },
+        Description {
+            prefix: "".into(),
+            suffix: "".into(),
+        },
+        None,
+        vec![],
+        vec![],
+        vec![],
+        vec![],
+        vec![],
+    )
+    .unwrap()
+}
+
+#[test]
+fn package_recommends_and_suggests_overrides() {
+    let config = parse_config(
+        "[packages.lib]\nrecommends = [\"foo-tool\"]\n\
+         [packages.\"lib+serde\"]\nrecommends = [\"serde-tool\"]\nsuggests = [\"serde-doc\"]\n",
+    );
+    let description = || Description {
+        prefix: "Summary".into(),
+        suffix: "".into(),
+    };
+    let version = Version::new(1, 2, 3);
+
+    let mut base = Package::new(
+        "crate",
+        None,
+        &version,
+        description(),
+        description(),
+        None,
+        vec![],
+        vec![],
+        vec![],
+        vec!["default"],
+        vec!["serde"],
+    )
+    .unwrap();
+    base.apply_overrides(&config, PackageKey::BareLib, vec![])
+        .unwrap();
+    let out = base.to_string();
+    assert!(out
+        .contains("\nRecommends:\n librust-crate+default-dev (= ${binary:Version}),\n foo-tool\n"));
+    assert!(out.contains("\nSuggests:\n librust-crate+serde-dev (= ${binary:Version})\n"));
+
+    // feature packages get no generated Recommends or Suggests, but keep the
+    // configured ones
+    let mut feature = Package::new(
+        "crate",
+        None,
+        &version,
+        description(),
+        description(),
+        Some("serde"),
+        vec![""],
+        vec![],
+        vec![],
+        vec!["default"],
+        vec!["serde"],
+    )
+    .unwrap();
+    feature
+        .apply_overrides(&config, PackageKey::feature("serde"), vec![])
+        .unwrap();
+    let out = feature.to_string();
+    assert!(out.contains("\nRecommends:\n serde-tool\n"));
+    assert!(out.contains("\nSuggests:\n serde-doc\n"));
+}
+
+#[test]
+fn package_breaks_and_replaces_old_feature_package() {
+    let mut package = test_package();
+    package.break_and_replace("librust-crate+old-dev", "1.2.3");
+    let control = package.to_string();
+    assert!(control.contains("Breaks:\n librust-crate+old-dev (<< 1.2.3-~~)\n"));
+    assert!(control.contains("Replaces:\n librust-crate+old-dev (<< 1.2.3-~~)\n"));
+
+    let control = format!("Source: rust-crate\n\n{}", package);
+    assert_eq!(
+        feature_packages_in_control(&control, "crate"),
+        Vec::<String>::new()
+    );
+    // the old package is still known on the next run
+    assert_eq!(
+        broken_feature_packages_in_control(&control, "crate"),
+        vec!["librust-crate+old-dev"]
+    );
+
+    let mut package = test_package();
+    package.break_and_replace("librust-crate+old-dev", "1:1.2.3");
+    package.break_and_replace("librust-crate+older-dev", "1:1.2.3");
+    let control = format!("Source: rust-crate\n\n{}", package);
+    assert!(control.contains("Breaks:\n librust-crate+old-dev (<< 1:1.2.3-~~),\n"));
+    assert_eq!(
+        broken_feature_packages_in_control(&control, "crate"),
+        vec!["librust-crate+old-dev", "librust-crate+older-dev"]
+    );
+}
+
+#[test]
+fn package_depends_alternatives() {
+    let config =
+        parse_config("[packages.lib]\ndepends = [\"librust-ring-dev | librust-aws-lc-dev\"]\n");
+    let mut package = test_package();
+    package
+        .apply_overrides(&config, PackageKey::BareLib, vec![])
+        .unwrap();
+    // the alternatives stay together on one line of the field
+    let out = package.to_string();
+    assert!(out
+        .lines()
+        .any(|line| line.trim_end_matches(',') == " librust-ring-dev | librust-aws-lc-dev"));
+}
+
+#[test]
+fn package_pre_depends_and_enhances() {
+    let out = test_package().to_string();
+    assert!(!out.contains("Pre-Depends:"));
+    assert!(!out.contains("Enhances:"));
+
+    let config = parse_config(
+        r#"
+[packages.lib]
+pre_depends = ["dpkg (>= 1.17.14)"]
+depends = ["libfoo-dev"]
+suggests = ["bar"]
+enhances = ["baz", "qux"]
+"#,
+    );
+    let mut package = test_package();
+    package
+        .apply_overrides(&config, PackageKey::BareLib, vec![])
+        .unwrap();
+    let out = package.to_string();
+    let pre_depends = out.find("Pre-Depends:\n dpkg (>= 1.17.14)\n").unwrap();
+    let depends = out.find("\nDepends:\n").unwrap();
+    let suggests = out.find("Suggests:\n bar\n").unwrap();
+    let enhances = out.find("Enhances:\n baz,\n qux\n").unwrap();
+    let description = out.find("\nDescription:").unwrap();
+    assert!(pre_depends < depends);
+    assert!(suggests < enhances);
+    // with the other relationship fields, before the description at the end
+    assert!(enhances < description);
+}
+
+#[test]
+fn check_deb_package_names() {
+    for name in ["rust-4-byte", "4-byte", "xy", "librust-foo+std-dev", "g++"] {
+        assert!(is_deb_package_name(name), "{}", name);
+    }
+    for name in ["x", "-internal", "Foo", "foo_bar", ""] {
+        assert!(!is_deb_package_name(name), "{}", name);
+    }
+}
+
+#[test]
+fn feature_names_are_normalized() {
+    assert_eq!(
+        deb_feature_name("crate", "feature.with.dots"),
+        "librust-crate+feature.with.dots-dev"
+    );
+    assert_eq!(
+        deb_feature_name("crate", "Feature_Upper"),
+        "librust-crate+feature-upper-dev"
+    );
+    assert_eq!(
+        deb_feature_name("crate", "c++17"),
+        "librust-crate+c--17-dev"
+    );
+    assert_eq!(deb_feature_name("crate", "2d"), "librust-crate+2d-dev");
+    assert_eq!(
+        deb_feature_name("crate", "ünicode"),
+        "librust-crate+-nicode-dev"
+    );
+}
+
+#[test]
+fn check_feature_name_collisions() {
+    assert!(feature_name_collisions("crate", vec!["", "std", "a_b"]).is_empty());
+    let collisions = feature_name_collisions("crate", vec!["", "Foo_bar", "foo-Bar", "baz"]);
+    assert_eq!(collisions.len(), 1);
+    assert_eq!(
+        collisions["librust-crate+foo-bar-dev"],
+        vec!["Foo_bar", "foo-Bar"]
+    );
+    let collisions = feature_name_collisions("crate", vec!["a+b", "a.b", "a-b"]);
+    assert_eq!(collisions["librust-crate+a-b-dev"], vec!["a+b", "a-b"]);
+}
+
+#[test]
+fn package_summary_check_len() {
+    let package_with_summary = |summary: String| {
+        Package::new_data(
+            "crate",
+            None,
+            Description {
+                prefix: summary,
+                suffix: " - data".into(),
+            },
+            Description {
+                prefix: "".into(),
+                suffix: "".into(),
+            },
+        )
+    };
+    // the suffix counts towards the limit too
+    let stem_len = MAX_SYNOPSIS_LEN - " - data".len();
+    let at_limit = "x".repeat(stem_len);
+    assert_eq!(package_with_summary(at_limit).summary_check_len(), Ok(()));
+    let over_limit = "x".repeat(stem_len + 1);
+    assert_eq!(
+        package_with_summary(over_limit).summary_check_len(),
+        Err(MAX_SYNOPSIS_LEN + 1)
+    );
+    // the limit is in characters, not bytes
+    let multibyte = "é".repeat(stem_len);
+    assert_eq!(package_with_summary(multibyte).summary_check_len(), Ok(()));
+}
+
+#[test]
+fn data_package() {
+    assert_eq!(deb_data_name("Foo_bar"), "librust-foo-bar-data");
+    let package = Package::new_data(
+        "crate",
+        Some("-1"),
+        Description {
+            prefix: "Summary".into(),
+            suffix: " - data files".into(),
+        },
+        Description {
+            prefix: "".into(),
+            suffix: "".into(),
+        },
+    );
+    let out = package.to_string();
+    assert!(out.starts_with(
+        "Package: librust-crate-1-data
+Architecture: all
+Multi-Arch: foreign
+"
+    ));
+    assert!(out.contains(
+        "
+Depends:
+ ${misc:Depends}
+"
+    ));
+    assert!(out.contains(
+        "
+Description: Summary - data files
+"
+    ));
+}
+
+#[test]
+fn package_multi_arch() {
+    let mut package = test_package();
+    assert!(package.to_string().contains("\nMulti-Arch: same\n"));
+    package.set_multi_arch("allowed");
+    assert!(package.to_string().contains("\nMulti-Arch: allowed\n"));
+}
+
+#[test]
+fn lib_package_arch_overrides() {
+    let config = parse_config("[packages.lib]\narchitecture = \"all\"\n");
+    for key in [PackageKey::BareLib, PackageKey::FeatureLib("foo")] {
+        let mut package = test_package();
+        package.apply_overrides(&config, key, vec![]).unwrap();
+        assert!(package
+            .to_string()
+            .contains("\nArchitecture: all\nMulti-Arch: foreign\n"));
+    }
+
+    let config = parse_config("[packages.lib]\narchitecture = \"all\"\nmulti_arch = \"same\"\n");
+    let mut package = test_package();
+    assert!(package
+        .apply_overrides(&config, PackageKey::BareLib, vec![])
+        .is_err());
+
+    // feature packages cannot diverge from the library package
+    let config = parse_config("[packages.\"lib+foo\"]\narchitecture = \"all\"\n");
+    let mut package = test_package();
+    assert!(package
+        .apply_overrides(&config, PackageKey::FeatureLib("foo"), vec![])
+        .is_err());
+}
+
+fn test_bin_package() -> Package {
+    Package::new_bin(
+        "crate",
+        None,
+        None,
+        Description {
+            prefix: "Summary".into(),
+            suffix: "".into(),
+        },
+        Description {
+            prefix: "".into(),
+            suffix: "".into(),
+        },
+    )
+}
+
+#[test]
+fn bin_package_arch_overrides() {
+    let mut package = test_bin_package();
+    package
+        .apply_overrides(&Config::default(), PackageKey::Bin, vec![])
+        .unwrap();
+    let out = package.to_string();
+    assert!(out.contains("\nArchitecture: any\nMulti-Arch: allowed\n"));
+
+    let config = parse_config("[packages.bin]\narchitecture = \"all\"\n");
+    let mut package = test_bin_package();
+    package
+        .apply_overrides(&config, PackageKey::Bin, vec![])
+        .unwrap();
+    let out = package.to_string();
+    assert!(out.contains("\nArchitecture: all\nMulti-Arch: foreign\n"));
+
+    let config = parse_config("[packages.bin]\nmulti_arch = \"foreign\"\n");
+    let mut package = test_bin_package();
+    package
+        .apply_overrides(&config, PackageKey::Bin, vec![])
+        .unwrap();
+    assert!(package
+        .to_string()
+        .contains("\nArchitecture: any\nMulti-Arch: foreign\n"));
+
+    for bad in [
+        "architecture = \"all\"\nmulti_arch = \"same\"\n",
+        "multi_arch = \"sometimes\"\n",
+    ] {
+        let config = parse_config(&format!("[packages.bin]\n{}", bad));
+        let mut package = test_bin_package();
+        assert!(package
+            .apply_overrides(&config, PackageKey::Bin, vec![])
+            .is_err());
+    }
+}
+
+#[test]
+fn bin_package_built_using() {
+    let mut package = test_bin_package();
+    package
+        .apply_overrides(&Config::default(), PackageKey::Bin, vec![])
+        .unwrap();
+    let out = package.to_string();
+    assert!(out.contains("\nBuilt-Using: ${cargo:Built-Using}\n"));
+    assert!(out.contains("\nXB-X-Cargo-Built-Using: ${cargo:X-Cargo-Built-Using}\n"));
+
+    let config = parse_config("built_using = false\n");
+    let mut package = test_bin_package();
+    package
+        .apply_overrides(&config, PackageKey::Bin, vec![])
+        .unwrap();
+    let out = package.to_string();
+    assert!(!out.contains("Built-Using"), "{}", out);
+    assert!(out.contains("\nDepends:"));
+}
+
+#[test]
+fn extra_control_fields() {
+    let config = parse_config(
+        r#"
+[source.extra_control_fields]
+X-Foo = "bar"
+XS-Multi = "first\n second"
+
+[packages.lib.extra_control_fields]
+X-Foo = "baz"
+"#,
+    );
+    let mut source = test_source();
+    source.apply_overrides(&config).unwrap();
+    assert!(source
+        .to_string()
+        .ends_with("Rules-Requires-Root: no\nX-Foo: bar\nXS-Multi: first\n second\n"));
+
+    let mut package = test_package();
+    package
+        .apply_overrides(&config, PackageKey::BareLib, vec![])
+        .unwrap();
+    let out = package.to_string();
+    let field = out.find("\nX-Foo: baz\n").unwrap();
+    assert!(field < out.find("\nDescription: ").unwrap());
+
+    for bad in [
+        "\"X Foo\" = \"bar\"",
+        "\"X:Foo\" = \"bar\"",
+        "\"#X-Foo\" = \"bar\"",
+        "X-Foo = \"\"",
+        "X-Foo = \"bar\\nX-Bar: baz\"",
+        "X-Foo = \"bar\\n\\n baz\"",
+    ] {
+        let config = parse_config(&format!("[source.extra_control_fields]\n{}\n", bad));
+        assert!(test_source().apply_overrides(&config).is_err(), "{}", bad);
+    }
+}
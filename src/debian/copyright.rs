@@ -14,6 +14,7 @@ use std::fs;
 use std::io::{BufRead, BufReader, Read};
 use std::path::Path;
 
+use super::forge_repository;
 use crate::errors::*;
 
 const DEB_COPYRIGHT_FORMAT: &str = "\
@@ -37,6 +38,7 @@ struct UpstreamInfo {
     name: String,
     contacts: Vec<String>,
     source: String,
+    files_excluded: Vec<String>,
 }
 
 #[derive(Clone)]
@@ -85,34 +87,110 @@ impl DebCopyright {
             files: f.to_vec(),
             licenses: l.to_vec(),
         }
+        .deduplicated()
+    }
+
+    /// Collapse License paragraphs with identical text into the first one, and
+    /// merge adjacent Files paragraphs that end up with the same copyright,
+    /// license and comment. Files paragraphs are never reordered, since the
+    /// last one that matches a file applies to it; for the same reason the
+    /// catch-all "*" paragraph is left alone.
+    fn deduplicated(mut self) -> DebCopyright {
+        let mut renames = BTreeMap::new();
+        let mut licenses: Vec<License> = Vec::new();
+        for license in self.licenses {
+            match licenses
+                .iter()
+                .find(|l| l.text.trim() == license.text.trim())
+            {
+                Some(kept) => {
+                    if kept.name != license.name {
+                        renames.insert(license.name, kept.name.clone());
+                    }
+                }
+                None => licenses.push(license),
+            }
+        }
+        self.licenses = licenses;
+
+        let mut files: Vec<Files> = Vec::new();
+        for mut file in self.files {
+            file.license = file
+                .license
+                .split(' ')
+                .map(|word| {
+                    let name = word.trim_matches(|c| c == '(' || c == ')');
+                    match renames.get(name) {
+                        Some(kept) => word.replace(name, kept),
+                        None => word.to_string(),
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join(" ");
+            let same = files.last_mut().filter(|f| {
+                f.files != "*"
+                    && file.files != "*"
+                    && f.copyright == file.copyright
+                    && f.license == file.license
+                    && f.comment == file.comment
+            });
+            match same {
+                Some(f) => {
+                    f.files.push(' ');
+                    f.files.push_str(&file.files);
+                }
+                None => files.push(file),
+            }
+        }
+        self.files = files;
+        self
     }
 }
 
 impl fmt::Display for UpstreamInfo {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         writeln!(f, "Upstream-Name: {}", self.name)?;
-        write!(f, "Upstream-Contact:")?;
-        if self.contacts.len() > 1 {
-            writeln!(f)?;
-        }
-        for contact in &self.contacts {
-            writeln!(f, " {}", contact)?;
+        if !self.contacts.is_empty() {
+            write!(f, "Upstream-Contact:")?;
+            if self.contacts.len() > 1 {
+                writeln!(f)?;
+            }
+            for contact in &self.contacts {
+                writeln!(f, " {}", contact)?;
+            }
         }
         if !self.source.is_empty() {
             writeln!(f, "Source: {}", self.source)?;
         }
+        if !self.files_excluded.is_empty() {
+            writeln!(f, "Files-Excluded:")?;
+            for path in &self.files_excluded {
+                writeln!(f, " {}", path)?;
+            }
+        }
 
         Ok(())
     }
 }
 
 impl UpstreamInfo {
-    fn new(name: String, authors: &[String], repo: &str) -> UpstreamInfo {
-        assert!(!authors.is_empty());
+    fn new(
+        name: String,
+        authors: &[String],
+        repo: &str,
+        files_excluded: &[String],
+    ) -> UpstreamInfo {
+        let mut contacts: Vec<String> = Vec::new();
+        for author in authors.iter().map(|a| a.trim()) {
+            if !author.is_empty() && !contacts.iter().any(|c| c == author) {
+                contacts.push(author.to_string());
+            }
+        }
         UpstreamInfo {
             name,
-            contacts: authors.to_vec(),
+            contacts,
             source: repo.to_string(),
+            files_excluded: files_excluded.to_vec(),
         }
     }
 }
@@ -274,14 +352,13 @@ fn get_licenses(license: &str) -> Result<Vec<License>> {
     .into_iter()
     .collect::<BTreeMap<_, _>>();
 
-    let lses: Vec<&str> = sep.split(license).filter(|s| !s.is_empty()).collect();
+    let lses: Vec<&str> = sep
+        .split(license)
+        .map(|s| s.trim().trim_matches(|c| c == '(' || c == ')'))
+        .filter(|s| !s.is_empty())
+        .collect();
     for ls in lses {
-        let lname = ls
-            .trim()
-            .to_lowercase()
-            .trim_end_matches('+')
-            .trim_end_matches("-or-later")
-            .to_string();
+        let lname = license_id(ls);
         let text = match known_licenses.get(lname.as_str()) {
             Some(s) => s.to_string(),
             None => "FIXME (overlay): Unrecognized crate license, please find the \
@@ -302,18 +379,102 @@ fn get_licenses(license: &str) -> Result<Vec<License>> {
     Ok(lblocks)
 }
 
+/// Normalise a license name for comparisons, e.g. "Apache-2.0+" becomes
+/// "apache-2.0".
+fn license_id(name: &str) -> String {
+    name.trim()
+        .to_lowercase()
+        .trim_end_matches('+')
+        .trim_end_matches("-or-later")
+        .to_string()
+}
+
+/// Find the license files in the top-level directory of the crate, such as
+/// LICENSE-MIT or LICENSE-APACHE, and map them to the license they are named
+/// after. Files that don't name a license, like a plain LICENSE, are skipped.
+fn detect_license_files(srcdir: &Path) -> Result<BTreeMap<String, String>> {
+    let mut detected = BTreeMap::new();
+    for entry in fs::read_dir(srcdir)? {
+        let filename = entry?.file_name().to_string_lossy().to_string();
+        let upper = filename.to_uppercase();
+        let stem = upper.split('.').next().unwrap_or_default();
+        let suffix = match stem
+            .strip_prefix("LICENSE")
+            .or_else(|| stem.strip_prefix("LICENCE"))
+            .or_else(|| stem.strip_prefix("COPYING"))
+        {
+            Some(s) => s.trim_start_matches(['-', '_']),
+            None if stem == "UNLICENSE" => "UNLICENSE",
+            None => continue,
+        };
+        let id = match suffix {
+            "MIT" => "mit",
+            "APACHE" | "APACHE2" | "APACHE-2" | "APACHE-2.0" => "apache-2.0",
+            "BSD" | "BSD-3-CLAUSE" => "bsd-3-clause",
+            "BSD-2-CLAUSE" => "bsd-2-clause",
+            "ISC" => "isc",
+            "ZLIB" => "zlib",
+            "MPL" | "MPL-2.0" => "mpl-2.0",
+            "UNLICENSE" => "unlicense",
+            "CC0" | "CC0-1.0" => "cc0-1.0",
+            _ => continue,
+        };
+        detected.insert(filename, id.to_string());
+    }
+    Ok(detected)
+}
+
+/// Warn if the license files shipped by the crate don't match the licenses
+/// declared in its Cargo.toml.
+fn check_license_files(srcdir: &Path, licenses: &[License]) -> Result<()> {
+    let detected = detect_license_files(srcdir)?;
+    if detected.is_empty() {
+        return Ok(());
+    }
+    let declared: Vec<String> = licenses.iter().map(|l| license_id(&l.name)).collect();
+    for (filename, id) in &detected {
+        if !declared.contains(id) {
+            debcargo_warn!(
+                "License file {} does not match any license in Cargo.toml, please check d/copyright",
+                filename
+            );
+        }
+    }
+    for license in licenses {
+        let id = license_id(&license.name);
+        if !detected.values().any(|d| *d == id) {
+            debcargo_warn!(
+                "License {} from Cargo.toml has no matching LICENSE-* file, please check d/copyright",
+                license.name
+            );
+        }
+    }
+    Ok(())
+}
+
 fn copyright_fromgit(repo_url: &str) -> Result<String> {
     let tempdir = tempfile::Builder::new()
         .prefix("debcargo")
         .tempdir_in(".")?;
     let repo = Repository::clone(repo_url, tempdir.path())?;
+    copyright_years(&repo)
+}
 
+/// Years of the first and latest commit of the repository, as used in the
+/// Copyright: field.
+fn copyright_years(repo: &Repository) -> Result<String> {
     let mut revwalker = repo.revwalk()?;
     revwalker.push_head()?;
 
-    // Get the latest and first commit id. This is bit ugly
-    let latest_id = revwalker.next().unwrap()?;
-    let first_id = revwalker.last().unwrap()?; // revwalker ends here is consumed by last
+    // Get the latest and first commit id; with a single commit, they are the same
+    let latest_id = match revwalker.next() {
+        Some(id) => id?,
+        None => debcargo_bail!("repository has no commits"),
+    };
+    let first_id = match revwalker.last() {
+        Some(id) => id?,
+        None => latest_id,
+    };
 
     let first_commit = repo.find_commit(first_id)?;
     let latest_commit = repo.find_commit(latest_id)?;
@@ -340,53 +501,80 @@ fn copyright_fromgit(repo_url: &str) -> Result<String> {
     Ok(notice)
 }
 
+/// The crate that d/copyright is generated for.
+pub struct CopyrightInputs<'a> {
+    /// The unpacked crate.
+    pub srcdir: &'a Path,
+    pub manifest: &'a manifest::Manifest,
+    pub manifest_path: &'a Path,
+    /// Paths removed from the orig tarball, for the Files-Excluded field.
+    pub files_excluded: &'a [String],
+    /// The registry the crate came from, used when it has no repository.
+    pub registry: Option<&'a str>,
+    /// A local checkout of the crate, for the upstream copyright years.
+    pub crate_src: Option<&'a Path>,
+}
+
 pub fn debian_copyright(
-    srcdir: &Path,
-    manifest: &manifest::Manifest,
-    manifest_path: &Path,
+    inputs: &CopyrightInputs,
     maintainer: &str,
     uploaders: &[&str],
     year_range: (i32, i32),
     guess_harder: bool,
 ) -> Result<DebCopyright> {
+    let CopyrightInputs {
+        srcdir,
+        manifest,
+        manifest_path,
+        files_excluded,
+        registry,
+        crate_src,
+    } = *inputs;
     let meta = manifest.metadata().clone();
     let repository = match meta.repository {
-        None => "",
-        Some(ref r) => r,
+        None => "".to_string(),
+        Some(ref r) => match forge_repository(r) {
+            Some((url, _)) => url,
+            None => r.trim().trim_end_matches('/').to_string(),
+        },
+    };
+    // without a repository, the registry is the best source we know of
+    let source = match registry {
+        Some(r) if repository.is_empty() => r,
+        _ => &repository,
     };
 
     // The Authors field is optional according to
     // https://rust-lang.github.io/rfcs/3052-optional-authors-field.html
-    // and crates.io publishes crates without the field already.
-    let unknown_authors = vec!["FIXME (overlay) UNKNOWN-AUTHORS".to_string()];
-    let authors = if meta.authors.is_empty() {
-        &unknown_authors
-    } else {
-        &meta.authors
-    };
-
-    let upstream = UpstreamInfo::new(manifest.name().to_string(), authors, repository);
+    // and crates.io publishes crates without the field already, in which case
+    // Upstream-Contact is omitted.
+    let upstream = UpstreamInfo::new(
+        manifest.name().to_string(),
+        &meta.authors,
+        source,
+        files_excluded,
+    );
 
     let mut licenses: Vec<License> = Vec::new();
-    let mut crate_license: String = "".to_string();
+    let crate_license: String;
 
-    if let Some(ref license_file_name) = meta.license_file {
-        let license_file = manifest_path.with_file_name(license_file_name);
-        let mut text = Vec::new();
-        fs::File::open(license_file)?.read_to_end(&mut text)?;
-        licenses.reserve(1);
-        let stext = String::from_utf8(text)?;
-        licenses.push(License::new(
-            "UNKNOWN-LICENSE; FIXME (overlay)".to_string(),
-            stext,
-        ));
-    } else if let Some(ref license) = meta.license {
+    let license = meta.license.as_deref().map(str::trim).unwrap_or_default();
+    if !license.is_empty() {
         licenses = get_licenses(license).unwrap();
+        check_license_files(srcdir, &licenses)?;
         crate_license = license
-            .trim()
             .replace("/", " or ")
             .replace(" OR ", " or ")
             .replace(" AND ", " and ");
+    } else if let Some(ref license_file_name) = meta.license_file {
+        let license_file = manifest_path.with_file_name(license_file_name);
+        let mut text = Vec::new();
+        fs::File::open(license_file)?.read_to_end(&mut text)?;
+        crate_license = "UNKNOWN-LICENSE; FIXME (overlay)".to_string();
+        licenses.push(License::new(
+            crate_license.clone(),
+            String::from_utf8(text)?,
+        ));
     } else {
         debcargo_bail!("Crate has no license or license_file");
     }
@@ -404,9 +592,18 @@ pub fn debian_copyright(
     files.push(Files::new("debian/*", &deb_notice, &crate_license, ""));
 
     // Insert catch all block as the first block of copyright file. Capture
-    // copyright notice from git log of the upstream repository.
-    let years = if guess_harder && !repository.is_empty() {
-        match copyright_fromgit(repository) {
+    // copyright notice from git log of the upstream repository, preferring a
+    // local git checkout of the crate over cloning the repository.
+    let local_years = match crate_src {
+        Some(path) if guess_harder => Repository::discover(path)
+            .ok()
+            .and_then(|repo| copyright_years(&repo).ok()),
+        _ => None,
+    };
+    let years = if let Some(years) = local_years {
+        years
+    } else if guess_harder && !repository.is_empty() {
+        match copyright_fromgit(&repository) {
             Ok(x) => x,
             Err(e) => {
                 debcargo_warn!(
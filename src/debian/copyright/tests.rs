@@ -1,4 +1,7 @@
-use super::{debian_copyright, get_licenses};
+use super::{
+    debian_copyright, detect_license_files, get_licenses, CopyrightInputs, DebCopyright, Files,
+    License, UpstreamInfo,
+};
 
 use std::path::Path;
 use std::rc::Rc;
@@ -17,6 +20,10 @@ fn check_get_licenses() {
         ("AGPL-3.0-or-later", &[("AGPL-3.0-or-later", true)]),
         ("Apache-2.0/MIT", &[("Apache-2.0", true), ("MIT", true)]),
         ("Apache-2.0 or MIT", &[("Apache-2.0", true), ("MIT", true)]),
+        (
+            "(MIT OR Apache-2.0)",
+            &[("Apache-2.0", true), ("MIT", true)],
+        ),
         (
             "FooBar-1.0 AND MIT",
             &[("FooBar-1.0", false), ("MIT", true)],
@@ -56,9 +63,7 @@ fn check_debian_copyright_authors() {
         let package = build_package_with_authors(input);
         let srcdir = tempfile::tempdir().unwrap();
         let copyright = debian_copyright(
-            srcdir.path(),
-            package.manifest(),
-            package.manifest_path(),
+            &inputs(&package, srcdir.path()),
             "Jordan Doe",
             &[],
             (2000, 2020),
@@ -76,15 +81,39 @@ fn check_debian_copyright_authors() {
     }
 }
 
+fn inputs<'a>(package: &'a Package, srcdir: &'a Path) -> CopyrightInputs<'a> {
+    CopyrightInputs {
+        srcdir,
+        manifest: package.manifest(),
+        manifest_path: package.manifest_path(),
+        files_excluded: &[],
+        registry: None,
+        crate_src: None,
+    }
+}
+
 fn build_package_with_authors(authors: Vec<&str>) -> Package {
     let authors: Vec<String> = authors.into_iter().map(|s| s.to_string()).collect();
-    let toml = toml! {
+    build_package(toml! {
         [package]
         name = "mypackage"
         version = "1.2.3"
         authors = authors
         license = "AGPLv3"
-    };
+    })
+}
+
+fn build_package_with_repository(repository: &str) -> Package {
+    build_package(toml! {
+        [package]
+        name = "mypackage"
+        version = "1.2.3"
+        license = "AGPLv3"
+        repository = repository
+    })
+}
+
+fn build_package(toml: toml::Value) -> Package {
     let toml_manifest: Rc<TomlManifest> =
         Rc::new(toml::from_str(&toml::to_string(&toml).unwrap()).unwrap());
     let source_id = SourceId::for_path(Path::new("/path/to/mypackage")).unwrap();
@@ -95,3 +124,253 @@ fn build_package_with_authors(authors: Vec<&str>) -> Package {
         .0;
     Package::new(manifest, Path::new("/path/to/manifest"))
 }
+
+#[test]
+fn upstream_info_lists_files_excluded() {
+    let upstream = UpstreamInfo::new(
+        "foo".to_string(),
+        &["Jordan Doe".to_string()],
+        "https://example.org/foo",
+        &["tests/data/blob.bin".to_string(), "vendor".to_string()],
+    );
+    assert_eq!(
+        upstream.to_string(),
+        "Upstream-Name: foo
+Upstream-Contact: Jordan Doe
+Source: https://example.org/foo
+Files-Excluded:
+ tests/data/blob.bin
+ vendor
+"
+    );
+}
+
+#[test]
+fn identical_licenses_are_deduplicated() {
+    let upstream = UpstreamInfo::new("foo".to_string(), &["Jordan Doe".to_string()], "", &[]);
+    let notice = ["2020 Jordan Doe"];
+    let files = [
+        Files::new("src/a.rs", &notice, "MIT", ""),
+        Files::new("src/b.rs", &notice, "Expat", ""),
+        Files::new("src/c.rs", &notice, "Apache-2.0", ""),
+    ];
+    let licenses = [
+        License::new("MIT".to_string(), "Permission is granted.".to_string()),
+        License::new("Expat".to_string(), "Permission is granted.\n".to_string()),
+        License::new(
+            "Apache-2.0".to_string(),
+            "Licensed under Apache.".to_string(),
+        ),
+    ];
+    let copyright = DebCopyright::new(upstream, &files, &licenses);
+    let found: Vec<_> = copyright
+        .files
+        .iter()
+        .map(|f| (f.files(), f.license()))
+        .collect();
+    assert_eq!(
+        found,
+        vec![("src/a.rs src/b.rs", "MIT"), ("src/c.rs", "Apache-2.0")]
+    );
+    let names: Vec<_> = copyright.licenses.iter().map(|l| l.name.as_str()).collect();
+    assert_eq!(names, vec!["MIT", "Apache-2.0"]);
+}
+
+#[test]
+fn overlapping_files_keep_their_order() {
+    let upstream = UpstreamInfo::new("foo".to_string(), &["Jordan Doe".to_string()], "", &[]);
+    let notice = ["2020 Jordan Doe"];
+    let files = [
+        Files::new("src/*", &notice, "MIT", ""),
+        Files::new("src/gen/*", &notice, "Apache-2.0", ""),
+        Files::new("src/gen/mit.rs", &notice, "MIT", ""),
+    ];
+    let licenses = [
+        License::new("MIT".to_string(), "Permission is granted.".to_string()),
+        License::new(
+            "Apache-2.0".to_string(),
+            "Licensed under Apache.".to_string(),
+        ),
+    ];
+    let copyright = DebCopyright::new(upstream, &files, &licenses);
+    let found: Vec<_> = copyright
+        .files
+        .iter()
+        .map(|f| (f.files(), f.license()))
+        .collect();
+    // merging src/gen/mit.rs into src/* would put it under Apache-2.0
+    assert_eq!(
+        found,
+        vec![
+            ("src/*", "MIT"),
+            ("src/gen/*", "Apache-2.0"),
+            ("src/gen/mit.rs", "MIT")
+        ]
+    );
+}
+
+#[test]
+fn check_detect_license_files() {
+    let srcdir = tempfile::tempdir().unwrap();
+    for name in &[
+        "LICENSE-MIT",
+        "LICENSE-APACHE",
+        "LICENSE",
+        "LICENSE-ZLIB.txt",
+        "README.md",
+    ] {
+        std::fs::write(srcdir.path().join(name), "").unwrap();
+    }
+    let detected = detect_license_files(srcdir.path()).unwrap();
+    let found: Vec<_> = detected
+        .iter()
+        .map(|(f, l)| (f.as_str(), l.as_str()))
+        .collect();
+    assert_eq!(
+        found,
+        vec![
+            ("LICENSE-APACHE", "apache-2.0"),
+            ("LICENSE-MIT", "mit"),
+            ("LICENSE-ZLIB.txt", "zlib"),
+        ]
+    );
+}
+
+#[test]
+fn check_debian_copyright_license_file() {
+    let toml = toml! {
+        [package]
+        name = "mypackage"
+        version = "1.2.3"
+        license-file = "LICENSE.txt"
+    };
+    let toml_manifest: Rc<TomlManifest> =
+        Rc::new(toml::from_str(&toml::to_string(&toml).unwrap()).unwrap());
+    let srcdir = tempfile::tempdir().unwrap();
+    let source_id = SourceId::for_path(srcdir.path()).unwrap();
+    let config = Config::default().unwrap();
+    let manifest =
+        TomlManifest::to_real_manifest(&toml_manifest, source_id, srcdir.path(), &config)
+            .unwrap()
+            .0;
+    let manifest_path = srcdir.path().join("Cargo.toml");
+    std::fs::write(srcdir.path().join("LICENSE.txt"), "All rights reversed.\n").unwrap();
+
+    let inputs = CopyrightInputs {
+        srcdir: srcdir.path(),
+        manifest: &manifest,
+        manifest_path: &manifest_path,
+        files_excluded: &[],
+        registry: None,
+        crate_src: None,
+    };
+    let copyright = debian_copyright(&inputs, "Jordan Doe", &[], (2000, 2020), false).unwrap();
+    for file in &copyright.files {
+        assert_eq!(file.license(), "UNKNOWN-LICENSE; FIXME (overlay)");
+    }
+    let text = copyright.to_string();
+    assert!(text.contains("License: UNKNOWN-LICENSE; FIXME (overlay)\n All rights reversed.\n"));
+    assert!(!text.contains("License: \n"));
+}
+
+#[test]
+fn check_debian_copyright_years_from_local_git() {
+    let gitdir = tempfile::tempdir().unwrap();
+    let repo = git2::Repository::init(gitdir.path()).unwrap();
+    let sig = git2::Signature::new(
+        "Jordan Doe",
+        "jordan@example.org",
+        &git2::Time::new(1262304000, 0), // 2010-01-01
+    )
+    .unwrap();
+    let tree = repo
+        .find_tree(repo.index().unwrap().write_tree().unwrap())
+        .unwrap();
+    repo.commit(Some("HEAD"), &sig, &sig, "initial", &tree, &[])
+        .unwrap();
+
+    let package = build_package_with_authors(vec!["Jordan Doe"]);
+    let srcdir = tempfile::tempdir().unwrap();
+    let inputs = CopyrightInputs {
+        crate_src: Some(gitdir.path()),
+        ..inputs(&package, srcdir.path())
+    };
+    let copyright = debian_copyright(&inputs, "Jordan Doe", &[], (2020, 2020), true).unwrap();
+    let catch_all = copyright.files.iter().find(|f| f.files == "*").unwrap();
+    assert_eq!(catch_all.copyright, vec!["2010 Jordan Doe"]);
+}
+
+#[test]
+fn check_upstream_contact() {
+    let checks = vec![
+        (vec![], "Upstream-Name: mypackage\n"),
+        (
+            vec!["Jordan Doe <jordan@example.org>"],
+            "Upstream-Name: mypackage\nUpstream-Contact: Jordan Doe <jordan@example.org>\n",
+        ),
+        (
+            vec![" Jordan Doe", "Jane Doe", "Jordan Doe "],
+            "Upstream-Name: mypackage\nUpstream-Contact:\n Jordan Doe\n Jane Doe\n",
+        ),
+    ];
+    for (input, expected_output) in checks.into_iter() {
+        let package = build_package_with_authors(input);
+        let srcdir = tempfile::tempdir().unwrap();
+        let copyright = debian_copyright(
+            &inputs(&package, srcdir.path()),
+            "Jordan Doe",
+            &[],
+            (2000, 2020),
+            false,
+        )
+        .unwrap();
+        assert_eq!(copyright.upstream.to_string(), expected_output);
+    }
+}
+
+#[test]
+fn check_upstream_source() {
+    let checks = vec![
+        (
+            Some("https://github.com/rust-lang/cargo.git"),
+            Some("https://github.com/rust-lang/cargo"),
+        ),
+        (
+            Some("https://github.com/rust-lang/cargo/tree/master/crates/cargo-util/"),
+            Some("https://github.com/rust-lang/cargo"),
+        ),
+        (
+            Some("https://gitlab.com/foo/bar/"),
+            Some("https://gitlab.com/foo/bar"),
+        ),
+        (
+            Some("https://example.org/foo.git"),
+            Some("https://example.org/foo.git"),
+        ),
+        (None, None),
+    ];
+    for (repository, expected) in checks.into_iter() {
+        let package = match repository {
+            Some(r) => build_package_with_repository(r),
+            None => build_package_with_authors(vec![]),
+        };
+        let srcdir = tempfile::tempdir().unwrap();
+        let copyright = debian_copyright(
+            &inputs(&package, srcdir.path()),
+            "Jordan Doe",
+            &[],
+            (2000, 2020),
+            false,
+        )
+        .unwrap();
+        let header = copyright.upstream.to_string();
+        match expected {
+            Some(source) => assert!(
+                header.contains(&format!("\nSource: {}\n", source)),
+                "{}",
+                header
+            ),
+            None => assert!(!header.contains("Source:"), "{}", header),
+        }
+    }
+}
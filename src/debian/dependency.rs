@@ -1,11 +1,18 @@
-use cargo::core::Dependency;
+use cargo::core::{dependency::DepKind, Dependency};
 use itertools::Itertools;
+use regex::Regex;
 
 use std::cmp;
+use std::collections::BTreeSet;
 use std::fmt;
 
 use crate::config::{testing_ignore_debpolv, Config};
-use crate::debian::{self, control::base_deb_name, Package};
+use crate::crates::show_dep;
+use crate::debian::{
+    self,
+    control::{base_deb_name, deb_feature_base_name},
+    Package,
+};
 use crate::errors::*;
 
 #[derive(Eq, Clone)]
@@ -164,13 +171,7 @@ impl VRange {
     }
 }
 
-fn coerce_unacceptable_predicate<'a>(
-    dep: &Dependency,
-    p: &'a semver::Comparator,
-    allow_prerelease_deps: bool,
-) -> Result<&'a semver::Op> {
-    let mmp = &V::new(p)?;
-
+fn check_prerelease(name: &str, p: &semver::Comparator, allow_prerelease_deps: bool) -> Result<()> {
     // Cargo/semver and Debian handle pre-release versions quite
     // differently, so a versioned Debian dependency cannot properly
     // handle pre-release crates. This might be OK most of the time,
@@ -179,58 +180,35 @@ fn coerce_unacceptable_predicate<'a>(
         if allow_prerelease_deps || testing_ignore_debpolv() {
             debcargo_warn!(
                 "Coercing removal of prerelease part of dependency: {} {:?}",
-                dep.package_name(),
+                name,
                 p
             )
         } else {
             debcargo_bail!(
                 "Cannot represent prerelease part of dependency: {} {:?}",
-                dep.package_name(),
+                name,
                 p
             )
         }
     }
-
-    use debian::dependency::V::M;
-    use semver::Op::*;
-    match (&p.op, mmp) {
-        (&Greater, &M(0)) => Ok(&p.op),
-        (&GreaterEq, &M(0)) => {
-            debcargo_warn!(
-                "Coercing unrepresentable dependency version predicate 'GtEq 0' to 'Gt 0': {} {:?}",
-                dep.package_name(),
-                p
-            );
-            Ok(&Greater)
-        }
-        // TODO: This will prevent us from handling wildcard dependencies with
-        // 0.0.0* so for now commenting this out.
-        // (_, &M(0)) => debcargo_bail!(
-        //     "Unrepresentable dependency version predicate: {} {:?}",
-        //     dep.package_name(),
-        //     p
-        // ),
-        (_, _) => Ok(&p.op),
-    }
+    Ok(())
 }
 
-fn generate_version_constraints(
-    vr: &mut VRange,
-    dep: &Dependency,
-    p: &semver::Comparator,
-    op: &semver::Op,
-) -> Result<()> {
+fn generate_version_constraints(vr: &mut VRange, name: &str, p: &semver::Comparator) -> Result<()> {
     let mmp = V::new(p)?;
     use debian::dependency::V::*;
     use semver::Op::*;
     // see https://doc.rust-lang.org/cargo/reference/specifying-dependencies.html
     // and https://docs.rs/semver/1/semver/enum.Op.html for semantics
-    match (*op, &mmp) {
+    match (p.op, &mmp) {
         (Less, &M(0)) | (Less, &MM(0, 0)) | (Less, &MMP(0, 0, 0)) => debcargo_bail!(
             "Unrepresentable dependency version predicate: {} {:?}",
-            dep.package_name(),
+            name,
             p
         ),
+        (GreaterEq, &M(0)) | (GreaterEq, &MM(0, 0)) | (GreaterEq, &MMP(0, 0, 0)) => {
+            // every version satisfies this, no constraint needed
+        }
         (Less, _) => {
             vr.constrain_lt(mmp);
         }
@@ -278,34 +256,258 @@ fn generate_version_constraints(
     Ok(())
 }
 
+/// The Debian architectures that a Cargo target platform applies to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum ArchRestriction {
+    /// All Debian architectures.
+    Any,
+    /// Only the given architectures, in Debian architecture wildcard syntax.
+    Only(BTreeSet<&'static str>),
+    /// No Debian architectures.
+    Never,
+}
+
+fn only(archs: &[&'static str]) -> Option<ArchRestriction> {
+    Some(ArchRestriction::Only(archs.iter().copied().collect()))
+}
+
+/// Debian architecture wildcards for a Rust target_arch.
+fn deb_cpu_wildcards(arch: &str) -> Option<ArchRestriction> {
+    match arch {
+        "x86_64" => only(&["any-amd64"]),
+        "x86" | "i386" | "i586" | "i686" => only(&["any-i386"]),
+        "aarch64" => only(&["any-arm64"]),
+        "arm" | "armv7" => only(&["any-arm"]),
+        "loongarch64" => only(&["any-loong64"]),
+        "m68k" => only(&["any-m68k"]),
+        "mips" => only(&["any-mips", "any-mipsel"]),
+        "mips64" => only(&["any-mips64", "any-mips64el"]),
+        "powerpc" => only(&["any-powerpc"]),
+        "powerpc64" => only(&["any-ppc64", "any-ppc64el"]),
+        "riscv64" | "riscv64gc" => only(&["any-riscv64"]),
+        "s390x" => only(&["any-s390x"]),
+        "sparc64" => only(&["any-sparc64"]),
+        _ => None,
+    }
+}
+
+/// Evaluate a single cfg() predicate. None means we don't know, i.e. it
+/// might or might not hold on any Debian architecture.
+fn eval_cfg_atom(key: &str, value: Option<&str>) -> Option<ArchRestriction> {
+    use self::ArchRestriction::*;
+    match (key, value) {
+        ("unix", None) => Some(Any),
+        ("windows", None) => Some(Never),
+        ("target_family", Some("unix")) => Some(Any),
+        ("target_family", Some(_)) => Some(Never),
+        ("target_os", Some("linux")) => only(&["linux-any"]),
+        ("target_os", Some("hurd")) => only(&["hurd-any"]),
+        ("target_os", Some(_)) => Some(Never),
+        ("target_vendor", Some("apple")) | ("target_vendor", Some("fortanix")) => Some(Never),
+        ("target_env", Some("msvc")) | ("target_env", Some("sgx")) => Some(Never),
+        ("target_arch", Some(arch)) => deb_cpu_wildcards(arch).or(Some(Never)),
+        (_, _) => None,
+    }
+}
+
+fn union(a: ArchRestriction, b: ArchRestriction) -> ArchRestriction {
+    use self::ArchRestriction::*;
+    match (a, b) {
+        (Any, _) | (_, Any) => Any,
+        (Never, x) | (x, Never) => x,
+        (Only(mut a), Only(b)) => {
+            a.extend(b);
+            Only(a)
+        }
+    }
+}
+
+/// Parse and evaluate a cfg() expression, starting at tokens[*i].
+fn eval_cfg_expr(tokens: &[&str], i: &mut usize) -> Result<Option<ArchRestriction>> {
+    use self::ArchRestriction::*;
+    let key = match tokens.get(*i) {
+        Some(k) => *k,
+        None => debcargo_bail!("unexpected end of cfg expression"),
+    };
+    *i += 1;
+    match tokens.get(*i) {
+        Some(&"(") => {
+            *i += 1;
+            let mut args = vec![];
+            while tokens.get(*i) != Some(&")") {
+                args.push(eval_cfg_expr(tokens, i)?);
+                if tokens.get(*i) == Some(&",") {
+                    *i += 1;
+                }
+            }
+            *i += 1;
+            Ok(match key {
+                "not" => match args.pop().flatten() {
+                    Some(Any) => Some(Never),
+                    Some(Never) => Some(Any),
+                    _ => None,
+                },
+                "any" => {
+                    // unknown might mean Any, so we can't restrict anything
+                    if args.iter().any(Option::is_none) {
+                        None
+                    } else {
+                        Some(args.into_iter().flatten().fold(Never, union))
+                    }
+                }
+                "all" => {
+                    if args.contains(&Some(Never)) {
+                        Some(Never)
+                    } else {
+                        // over-approximate the intersection by the union
+                        let restricted = args
+                            .into_iter()
+                            .flatten()
+                            .filter(|a| a != &Any)
+                            .reduce(union);
+                        Some(restricted.unwrap_or(Any))
+                    }
+                }
+                _ => debcargo_bail!("unknown cfg operator: {}", key),
+            })
+        }
+        Some(&"=") => {
+            *i += 1;
+            let value = match tokens.get(*i) {
+                Some(v) => v.trim_matches('"'),
+                None => debcargo_bail!("unexpected end of cfg expression"),
+            };
+            *i += 1;
+            Ok(eval_cfg_atom(key, Some(value)))
+        }
+        _ => Ok(eval_cfg_atom(key, None)),
+    }
+}
+
+/// Map a Cargo target platform, either a cfg() expression or a target
+/// triple, onto the Debian architectures it applies to.
+fn arch_restriction(platform: &str) -> ArchRestriction {
+    use self::ArchRestriction::*;
+    let platform = platform.trim();
+    if let Some(expr) = platform
+        .strip_prefix("cfg(")
+        .and_then(|p| p.strip_suffix(')'))
+    {
+        let token_re = Regex::new(r#"\w+|"[^"]*"|[(),=]"#).unwrap();
+        let tokens = token_re
+            .find_iter(expr)
+            .map(|m| m.as_str())
+            .collect::<Vec<_>>();
+        let mut i = 0;
+        match eval_cfg_expr(&tokens, &mut i) {
+            Ok(r) => r.unwrap_or(Any),
+            Err(e) => {
+                debcargo_warn!("Could not parse target platform {}: {}", platform, e);
+                Any
+            }
+        }
+    } else {
+        // a target triple
+        let parts = platform.split('-').collect::<Vec<_>>();
+        if parts.contains(&"linux") && !parts.contains(&"android") {
+            deb_cpu_wildcards(parts[0]).unwrap_or(Any)
+        } else {
+            Never
+        }
+    }
+}
+
+fn restrict_arch(clause: &str, restriction: &ArchRestriction) -> String {
+    match restriction {
+        ArchRestriction::Only(archs) => clause
+            .split(" | ")
+            .map(|x| format!("{} [{}]", x, archs.iter().join(" ")))
+            .join(" | "),
+        _ => clause.to_string(),
+    }
+}
+
+/// Intersect all comparators of a Cargo version requirement into a single
+/// range `>= ge, << lt`. Comparators that are not already of this form are
+/// translated onto it, e.g. `<= 1.5` is `<< 1.6` and `> 1.2.3` is `>= 1.2.4`,
+/// and an exact `= 1.2.3` is `>= 1.2.3, << 1.2.4`. When rendered, each bound
+/// gets a `-~~` Debian revision, so that the range includes all Debian
+/// revisions and pre-releases of the lower bound, and none of the upper one.
+fn version_range(
+    name: &str,
+    req: &semver::VersionReq,
+    allow_prerelease_deps: bool,
+) -> Result<VRange> {
+    let mut vr = VRange::new();
+    for p in &req.comparators {
+        check_prerelease(name, p, allow_prerelease_deps)?;
+        generate_version_constraints(&mut vr, name, p)?;
+    }
+    Ok(vr)
+}
+
 /// Translates a Cargo dependency into a Debian package dependency.
 pub fn deb_dep(config: &Config, dep: &Dependency) -> Result<Vec<String>> // result is a AND-clause
 {
+    let restriction = match dep.platform() {
+        None => ArchRestriction::Any,
+        Some(platform) => arch_restriction(&platform.to_string()),
+    };
+    if restriction == ArchRestriction::Never {
+        log::debug!(
+            "dropping dependency {} for non-Debian target {}",
+            show_dep(dep),
+            dep.platform().unwrap()
+        );
+        return Ok(vec![]);
+    }
     let dep_dashed = base_deb_name(&dep.package_name());
     let mut suffixes = Vec::new();
     if dep.uses_default_features() {
         suffixes.push("+default-dev".to_string());
     }
     for feature in dep.features() {
-        suffixes.push(format!("+{}-dev", base_deb_name(feature)));
+        suffixes.push(format!("+{}-dev", deb_feature_base_name(feature)));
     }
     if suffixes.is_empty() {
         suffixes.push("-dev".to_string());
     }
     let req = semver::VersionReq::parse(&dep.version_req().to_string()).unwrap();
+    let vr = version_range(
+        &dep.package_name(),
+        &req,
+        config.allow_prerelease_deps.allows(&dep.package_name()),
+    )?;
+    let strict_caret = config.strict_semver_deps
+        && !req.comparators.is_empty()
+        && req.comparators.iter().all(|p| p.op == semver::Op::Caret);
     let mut deps = Vec::new();
     for suffix in suffixes {
         let base = format!("{}-{}", Package::pkg_prefix(), dep_dashed);
-        let mut vr = VRange::new();
-        for p in &req.comparators {
-            let op = coerce_unacceptable_predicate(dep, p, config.allow_prerelease_deps)?;
-            generate_version_constraints(&mut vr, dep, p, op)?;
+        let clause = vr.to_deb_or_clause(&base, &suffix)?;
+        deps.push(restrict_arch(&clause, &restriction));
+        if strict_caret {
+            if let Some(upper) = upper_bound_clause(&vr, &clause) {
+                deps.push(restrict_arch(&upper, &restriction));
+            }
         }
-        deps.push(vr.to_deb_or_clause(&base, &suffix)?);
     }
     Ok(deps)
 }
 
+/// The upper bound of `vr` on the package of `clause`, as a clause of its
+/// own, since a Debian relation only takes one version constraint. None if
+/// there is no upper bound, or if `clause` has several alternatives, which
+/// already have their bounds.
+fn upper_bound_clause(vr: &VRange, clause: &str) -> Option<String> {
+    if clause.contains(" | ") {
+        return None;
+    }
+    let lt = vr.lt.as_ref()?;
+    let package = clause.split(' ').next()?;
+    Some(format!("{} (<< {}-~~)", package, lt))
+}
+
 pub fn deb_deps(config: &Config, cdeps: &[Dependency]) -> Result<Vec<String>> // result is a AND-clause
 {
     let mut deps = Vec::new();
@@ -317,6 +519,40 @@ pub fn deb_deps(config: &Config, cdeps: &[Dependency]) -> Result<Vec<String>> //
     Ok(deps)
 }
 
+/// Like deb_deps, but for Build-Depends: crates that are only
+/// build-dependencies get the :native qualifier, since build scripts run on
+/// the build architecture.
+pub fn deb_build_deps(config: &Config, cdeps: &[Dependency]) -> Result<Vec<String>> {
+    let build_only = |dep: &Dependency| {
+        dep.kind() == DepKind::Build
+            && !cdeps
+                .iter()
+                .any(|d| d.kind() != DepKind::Build && d.package_name() == dep.package_name())
+    };
+    let mut deps = Vec::new();
+    for dep in cdeps {
+        let clause = deb_dep(config, dep)?;
+        if build_only(dep) {
+            deps.extend(clause.iter().map(|d| deb_dep_add_native(d)));
+        } else {
+            deps.extend(clause.iter().map(String::to_string));
+        }
+    }
+    deps.sort();
+    deps.dedup();
+    Ok(deps)
+}
+
+/// Qualify every alternative of a dependency with :native.
+pub fn deb_dep_add_native(x: &str) -> String {
+    x.split('|')
+        .map(|x| match x.trim().split_once(' ') {
+            Some((name, constraint)) => format!("{}:native {}", name, constraint),
+            None => format!("{}:native", x.trim()),
+        })
+        .join(" | ")
+}
+
 pub fn deb_dep_add_nocheck(x: &str) -> String {
     x.to_string()
         .split('|')
@@ -325,3 +561,6 @@ pub fn deb_dep_add_nocheck(x: &str) -> String {
         .trim_end()
         .to_string()
 }
+
+#[cfg(test)]
+mod tests;
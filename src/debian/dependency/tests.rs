@@ -0,0 +1,178 @@
+use super::ArchRestriction::*;
+use super::{
+    arch_restriction, deb_dep, deb_dep_add_nocheck, restrict_arch, version_range, ArchRestriction,
+};
+use crate::config::{AllowPrereleaseDeps, Config};
+
+fn only(archs: &[&'static str]) -> ArchRestriction {
+    Only(archs.iter().copied().collect())
+}
+
+#[test]
+fn check_arch_restriction() {
+    let checks = vec![
+        ("cfg(unix)", Any),
+        ("cfg(windows)", Never),
+        ("cfg(not(windows))", Any),
+        ("cfg(not(unix))", Never),
+        ("cfg(target_os = \"linux\")", only(&["linux-any"])),
+        ("cfg(target_os = \"macos\")", Never),
+        (
+            "cfg(any(target_os = \"linux\", target_os = \"android\"))",
+            only(&["linux-any"]),
+        ),
+        (
+            "cfg(all(unix, target_arch = \"x86_64\"))",
+            only(&["any-amd64"]),
+        ),
+        (
+            "cfg(any(target_arch = \"x86\", target_arch = \"x86_64\"))",
+            only(&["any-amd64", "any-i386"]),
+        ),
+        ("cfg(all(windows, target_arch = \"x86_64\"))", Never),
+        ("cfg(any(windows, feature = \"foo\"))", Any),
+        ("cfg(not(target_os = \"linux\"))", Any),
+        ("cfg(test)", Any),
+        ("x86_64-unknown-linux-gnu", only(&["any-amd64"])),
+        ("x86_64-pc-windows-msvc", Never),
+        ("aarch64-linux-android", Never),
+    ];
+    for (platform, expected) in checks {
+        assert_eq!(arch_restriction(platform), expected, "{}", platform);
+    }
+}
+
+#[test]
+fn check_restrict_arch() {
+    assert_eq!(
+        restrict_arch(
+            "librust-foo-1+default-dev | librust-foo-0.9+default-dev (>= 0.9.2-~~)",
+            &only(&["linux-any"])
+        ),
+        "librust-foo-1+default-dev [linux-any] | librust-foo-0.9+default-dev (>= 0.9.2-~~) [linux-any]"
+    );
+    assert_eq!(restrict_arch("librust-foo-dev", &Any), "librust-foo-dev");
+}
+
+#[test]
+fn check_deb_dep_add_nocheck() {
+    assert_eq!(
+        deb_dep_add_nocheck("librust-ring-dev | librust-aws-lc-dev (>= 1)"),
+        "librust-ring-dev <!nocheck> | librust-aws-lc-dev (>= 1) <!nocheck>"
+    );
+}
+
+fn deb_version_clause(req: &str) -> crate::errors::Result<String> {
+    let req = semver::VersionReq::parse(req).unwrap();
+    version_range("foo", &req, false)?.to_deb_or_clause("librust-foo", "+default-dev")
+}
+
+#[test]
+fn check_version_requirements() {
+    let checks = vec![
+        ("*", "librust-foo+default-dev"),
+        (">=0", "librust-foo+default-dev"),
+        ("=1", "librust-foo-1+default-dev"),
+        ("=1.2", "librust-foo-1.2+default-dev"),
+        ("=1.2.3", "librust-foo-1.2.3+default-dev"),
+        (">=1.2.3, <=1.2.3", "librust-foo-1.2.3+default-dev"),
+        ("<2", "librust-foo+default-dev (<< 2-~~)"),
+        ("<2.3.4", "librust-foo+default-dev (<< 2.3.4-~~)"),
+        ("<=1.5", "librust-foo+default-dev (<< 1.6-~~)"),
+        ("<=1.5.3", "librust-foo+default-dev (<< 1.5.4-~~)"),
+        (">1.2", "librust-foo+default-dev (>= 1.3-~~)"),
+        (">1.2.3", "librust-foo+default-dev (>= 1.2.4-~~)"),
+        (">=1.2.3", "librust-foo+default-dev (>= 1.2.3-~~)"),
+        ("^1.2.3", "librust-foo-1+default-dev (>= 1.2.3-~~)"),
+        ("~1.2.3", "librust-foo-1.2+default-dev (>= 1.2.3-~~)"),
+        (
+            ">=1.2, <1.5",
+            "librust-foo-1.4+default-dev | librust-foo-1.3+default-dev | librust-foo-1.2+default-dev",
+        ),
+        (
+            ">=1.2.3, <3",
+            "librust-foo-2+default-dev | librust-foo-1+default-dev (>= 1.2.3-~~)",
+        ),
+        (
+            ">1.0, <=1.2.5",
+            "librust-foo-1.2+default-dev (<< 1.2.6-~~) | librust-foo-1.1+default-dev",
+        ),
+    ];
+    for (req, expected) in checks {
+        assert_eq!(deb_version_clause(req).unwrap(), expected, "{}", req);
+    }
+
+    assert!(deb_version_clause("<0").is_err());
+    assert!(deb_version_clause(">=2, <1").is_err());
+    assert!(deb_version_clause("=1.0.0-alpha.1").is_err());
+}
+
+#[test]
+fn check_strict_semver_deps() {
+    use cargo::core::{Dependency, SourceId};
+    let source_id = SourceId::for_path(std::path::Path::new("/nonexistent")).unwrap();
+    let checks = vec![
+        (
+            "^1.2",
+            vec!["librust-foo-1+default-dev (>= 1.2-~~)"],
+            vec![
+                "librust-foo-1+default-dev (>= 1.2-~~)",
+                "librust-foo-1+default-dev (<< 2-~~)",
+            ],
+        ),
+        (
+            "^0.3",
+            vec!["librust-foo-0.3+default-dev"],
+            vec![
+                "librust-foo-0.3+default-dev",
+                "librust-foo-0.3+default-dev (<< 0.4-~~)",
+            ],
+        ),
+        (
+            "^0.0.4",
+            vec!["librust-foo-0.0.4+default-dev"],
+            vec![
+                "librust-foo-0.0.4+default-dev",
+                "librust-foo-0.0.4+default-dev (<< 0.0.5-~~)",
+            ],
+        ),
+        (
+            ">= 1.2, < 3",
+            vec!["librust-foo-2+default-dev | librust-foo-1+default-dev (>= 1.2-~~)"],
+            vec!["librust-foo-2+default-dev | librust-foo-1+default-dev (>= 1.2-~~)"],
+        ),
+    ];
+    let mut config = Config::default();
+    for (req, loose, strict) in checks {
+        let dep = Dependency::parse("foo", Some(req), source_id).unwrap();
+        config.strict_semver_deps = false;
+        assert_eq!(deb_dep(&config, &dep).unwrap(), loose, "{}", req);
+        config.strict_semver_deps = true;
+        assert_eq!(deb_dep(&config, &dep).unwrap(), strict, "{}", req);
+    }
+}
+
+#[test]
+fn check_allow_prerelease_deps() {
+    use cargo::core::{Dependency, SourceId};
+    let source_id = SourceId::for_path(std::path::Path::new("/nonexistent")).unwrap();
+    let foo = Dependency::parse("foo", Some("^1.2.0-alpha.1"), source_id).unwrap();
+    let bar = Dependency::parse("bar", Some("^1.2.0-alpha.1"), source_id).unwrap();
+    let mut config = Config::default();
+    assert!(deb_dep(&config, &foo).is_err());
+    assert!(deb_dep(&config, &bar).is_err());
+
+    config.allow_prerelease_deps = AllowPrereleaseDeps::Crates(vec!["foo".to_string()]);
+    assert_eq!(
+        deb_dep(&config, &foo).unwrap(),
+        vec!["librust-foo-1+default-dev (>= 1.2.0-~~)"]
+    );
+    assert!(deb_dep(&config, &bar).is_err());
+
+    config.allow_prerelease_deps = AllowPrereleaseDeps::All(true);
+    assert!(deb_dep(&config, &foo).is_ok());
+    assert_eq!(
+        deb_dep(&config, &bar).unwrap(),
+        vec!["librust-bar-1+default-dev (>= 1.2.0-~~)"]
+    );
+}
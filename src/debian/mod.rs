@@ -1,36 +1,46 @@
+use std::cell::RefCell;
 use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt::Write as FmtWrite;
 use std::fs;
-use std::io::{self, ErrorKind, Read, Seek, Write as IoWrite};
+use std::io::{self, Read, Seek, Write as IoWrite};
 use std::os::unix::fs::PermissionsExt;
 use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::rc::Rc;
 use std::str::FromStr;
 
-use anyhow::format_err;
+use anyhow::{format_err, Context};
 use chrono::{self, Datelike};
-use flate2::read::GzDecoder;
 use flate2::write::GzEncoder;
-use flate2::Compression;
+use flate2::Compression as GzCompression;
 use regex::Regex;
 use tar::{Archive, Builder};
 use tempfile;
+use walkdir::WalkDir;
+use xz2::write::XzEncoder;
 
-use crate::config::{package_field_for_feature, testing_ignore_debpolv, Config, PackageKey};
+use crate::config::{
+    package_field_for_feature, testing_ignore_debpolv, BrokenTestPolicy, Compression, Config,
+    GbpConfig, PackageKey, WatchMode,
+};
 use crate::crates::{show_dep, transitive_deps, CrateDepInfo, CrateInfo};
 use crate::errors::*;
-use crate::util::{self, copy_tree, expect_success, get_transitive_val, traverse_depth};
+use crate::util::{self, copy_tree, get_transitive_val, traverse_depth};
 
 use self::changelog::{ChangelogEntry, ChangelogIterator};
-use self::control::{base_deb_name, deb_upstream_version};
+use self::control::{base_deb_name, deb_data_name, deb_name, deb_upstream_version, is_deb_version};
 use self::control::{Description, Package, PkgTest, Source};
-use self::copyright::debian_copyright;
-pub use self::dependency::{deb_dep_add_nocheck, deb_deps};
+use self::copyright::{debian_copyright, CopyrightInputs};
+pub use self::dependency::{deb_build_deps, deb_dep_add_nocheck, deb_deps};
 
 pub mod changelog;
 pub mod control;
 pub mod copyright;
 mod dependency;
 
+/// Appended to the upstream version when files were removed from the crate.
+const REPACK_SUFFIX: &str = "+dfsg1";
+
 pub struct DebInfo {
     upstream_name: String,
     /// Debian package name without rust- prefix or any semver suffix
@@ -42,16 +52,25 @@ pub struct DebInfo {
     /// Debian package name without rust- prefix
     package_name: String,
     deb_upstream_version: String,
+    epoch: Option<u64>,
+    /// Paths removed from the crate tarball, relative to the crate root
+    files_excluded: Vec<String>,
     debcargo_version: String,
     package_source_dir: PathBuf,
+    orig_compression: Compression,
     orig_tarball_path: PathBuf,
 }
 
 impl DebInfo {
-    pub fn new(crate_info: &CrateInfo, debcargo_version: &str, semver_suffix: bool) -> Self {
+    pub fn new(
+        crate_info: &CrateInfo,
+        debcargo_version: &str,
+        semver_suffix: bool,
+        epoch: Option<u64>,
+        orig_compression: Compression,
+    ) -> Result<Self> {
         let upstream_name = crate_info.package_id().name().to_string();
-        let name_dashed = base_deb_name(&upstream_name);
-        let base_package_name = name_dashed.to_lowercase();
+        let base_package_name = base_package_name(&upstream_name)?;
         let (name_suffix, uscan_version_pattern, package_name) = if semver_suffix {
             let semver = crate_info.semver();
             let name_suffix = format!("-{}", &semver);
@@ -63,7 +82,12 @@ impl DebInfo {
         } else {
             (None, None, base_package_name.clone())
         };
-        let deb_upstream_version = deb_upstream_version(crate_info.version());
+        let files_excluded = crate_info.excluded_files()?;
+        let mut deb_upstream_version = deb_upstream_version(crate_info.version());
+        if !files_excluded.is_empty() {
+            deb_upstream_version.push_str(REPACK_SUFFIX);
+        }
+        // The epoch is never part of file names, see deb-version(7).
         let package_source_dir = PathBuf::from(format!(
             "{}-{}-{}",
             Source::pkg_prefix(),
@@ -71,23 +95,27 @@ impl DebInfo {
             deb_upstream_version
         ));
         let orig_tarball_path = PathBuf::from(format!(
-            "{}-{}_{}.orig.tar.gz",
+            "{}-{}_{}.orig.tar.{}",
             Source::pkg_prefix(),
             package_name,
-            deb_upstream_version
+            deb_upstream_version,
+            orig_compression.extension()
         ));
 
-        DebInfo {
+        Ok(DebInfo {
             upstream_name,
             base_package_name,
             name_suffix,
             uscan_version_pattern,
             package_name,
             deb_upstream_version,
+            epoch,
+            files_excluded,
             debcargo_version: debcargo_version.to_string(),
             package_source_dir,
+            orig_compression,
             orig_tarball_path,
-        }
+        })
     }
 
     pub fn upstream_name(&self) -> &str {
@@ -110,6 +138,22 @@ impl DebInfo {
         self.deb_upstream_version.as_str()
     }
 
+    pub fn epoch(&self) -> Option<u64> {
+        self.epoch
+    }
+
+    /// The Debian version without the revision, i.e. including the epoch.
+    pub fn deb_epoch_upstream_version(&self) -> String {
+        match self.epoch {
+            Some(epoch) => format!("{}:{}", epoch, self.deb_upstream_version),
+            None => self.deb_upstream_version.clone(),
+        }
+    }
+
+    pub fn files_excluded(&self) -> &[String] {
+        &self.files_excluded
+    }
+
     pub fn debcargo_version(&self) -> &str {
         self.debcargo_version.as_str()
     }
@@ -118,6 +162,10 @@ impl DebInfo {
         self.package_source_dir.as_ref()
     }
 
+    pub fn orig_compression(&self) -> Compression {
+        self.orig_compression
+    }
+
     pub fn orig_tarball_path(&self) -> &Path {
         self.orig_tarball_path.as_ref()
     }
@@ -126,6 +174,7 @@ impl DebInfo {
 pub fn prepare_orig_tarball(
     crate_info: &CrateInfo,
     tarball: &Path,
+    compression: Compression,
     src_modified: bool,
     output_dir: &Path,
 ) -> Result<()> {
@@ -138,55 +187,368 @@ pub fn prepare_orig_tarball(
     let mut create = fs::OpenOptions::new();
     create.write(true).create_new(true);
 
-    if src_modified {
-        debcargo_info!("crate tarball was modified; repacking for debian");
-        let mut f = crate_file.file();
-        f.seek(io::SeekFrom::Start(0))?;
-        let mut archive = Archive::new(GzDecoder::new(f));
-        let mut new_archive = Builder::new(GzEncoder::new(
-            create.open(&temp_archive_path)?,
-            Compression::best(),
-        ));
-
-        for entry in archive.entries()? {
-            let entry = entry?;
-            let path = entry.path()?.into_owned();
-            if path.ends_with("Cargo.toml") && path.iter().count() == 2 {
-                // Put the rewritten and original Cargo.toml back into the orig tarball
-                let mut new_archive_append = |name: &str| {
-                    let mut header = entry.header().clone();
-                    let srcpath = output_dir.join(name);
-                    header.set_path(path.parent().unwrap().join(name))?;
-                    header.set_size(fs::metadata(&srcpath)?.len());
-                    header.set_cksum();
-                    new_archive.append(&header, fs::File::open(&srcpath)?)
-                };
-                new_archive_append("Cargo.toml")?;
-                new_archive_append("Cargo.toml.orig")?;
-            } else {
-                match crate_info.filter_path(&entry.path()?) {
-                    Err(e) => debcargo_bail!(e),
-                    Ok(r) => {
-                        if !r {
-                            new_archive.append(&entry.header().clone(), entry)?;
-                        } else {
-                            writeln!(
-                                io::stderr(),
-                                "Filtered out files from .orig.tar.gz: {:?}",
-                                &entry.path()?
-                            )?;
-                        }
-                    }
+    let crate_src_dir = crate_info.crate_src_dir();
+    let (crate_compression, _) = crate_info.crate_tarball()?;
+    if crate_src_dir.is_some() || src_modified || crate_compression != Some(compression) {
+        if let Some(src_dir) = crate_src_dir {
+            debcargo_info!(
+                "creating orig tarball from local crate at {}",
+                src_dir.display()
+            );
+        } else if src_modified {
+            debcargo_info!("crate tarball was modified; repacking for debian");
+        } else {
+            debcargo_info!(
+                "recompressing crate tarball with {}",
+                compression.dpkg_name()
+            );
+        }
+        let filter_path = |p: &Path| crate_info.filter_path(p);
+        let fill = |new_archive: &mut Builder<&mut dyn IoWrite>| -> Result<()> {
+            match crate_src_dir {
+                Some(src_dir) => {
+                    let pkgid = crate_info.package_id();
+                    let prefix = format!("{}-{}", pkgid.name(), pkgid.version());
+                    pack_orig_dir(
+                        &filter_path,
+                        src_dir,
+                        Path::new(&prefix),
+                        &crate_tarball_paths(crate_info)?,
+                        new_archive,
+                        output_dir,
+                    )
+                }
+                None => {
+                    let (_, tarball) = crate_info.crate_tarball()?;
+                    let mut archive = Archive::new(tarball);
+                    repack_orig_tarball(&filter_path, &mut archive, new_archive, output_dir)
                 }
             }
+        };
+        let out = create.open(&temp_archive_path)?;
+        match compression {
+            Compression::Gzip => {
+                let mut encoder = GzEncoder::new(out, GzCompression::best());
+                fill(&mut Builder::new(&mut encoder as &mut dyn IoWrite))?;
+                encoder.finish()?;
+            }
+            Compression::Xz => {
+                let mut encoder = XzEncoder::new(out, 9);
+                fill(&mut Builder::new(&mut encoder as &mut dyn IoWrite))?;
+                encoder.finish()?;
+            }
+            Compression::Zstd => {
+                let mut encoder = zstd::Encoder::new(out, 19)?;
+                fill(&mut Builder::new(&mut encoder as &mut dyn IoWrite))?;
+                encoder.finish()?;
+            }
         }
-
-        new_archive.finish()?;
     } else {
         fs::copy(crate_file.path(), &temp_archive_path)?;
     }
 
-    fs::rename(temp_archive_path, &tarball)?;
+    util::rename_or_copy(&temp_archive_path, tarball)?;
+    Ok(())
+}
+
+/// Contents of an orig tarball entry. Large files are only read while they
+/// are written to the orig tarball, so they are never held in memory.
+enum OrigData {
+    /// Small data, e.g. a rewritten Cargo.toml, or none for directories and links
+    Bytes(Vec<u8>),
+    /// A file of a local crate
+    File { path: PathBuf, len: u64 },
+    /// A range of the spool file that the crate tarball entries are copied to
+    Spooled { offset: u64, len: u64 },
+}
+
+impl OrigData {
+    fn len(&self) -> u64 {
+        match self {
+            OrigData::Bytes(data) => data.len() as u64,
+            OrigData::File { len, .. } | OrigData::Spooled { len, .. } => *len,
+        }
+    }
+
+    fn reader<'a>(&'a self, spool: Option<&'a fs::File>) -> Result<Box<dyn Read + 'a>> {
+        Ok(match self {
+            OrigData::Bytes(data) => Box::new(data.as_slice()),
+            OrigData::File { path, len } => Box::new(fs::File::open(path)?.take(*len)),
+            OrigData::Spooled { offset, len } => {
+                let mut spool = spool.expect("spooled orig entry without a spool file");
+                spool.seek(io::SeekFrom::Start(*offset))?;
+                Box::new(spool.take(*len))
+            }
+        })
+    }
+}
+
+struct OrigEntry {
+    header: tar::Header,
+    /// Target of a symlink or hardlink
+    link_name: Option<PathBuf>,
+    data: OrigData,
+}
+
+impl OrigEntry {
+    fn new(header: tar::Header, data: OrigData) -> Self {
+        OrigEntry {
+            header,
+            link_name: None,
+            data,
+        }
+    }
+}
+
+type OrigEntries = BTreeMap<PathBuf, OrigEntry>;
+
+/// Add the rewritten and original Cargo.toml from the unpacked crate at
+/// `output_dir` to the orig tarball entries, under the top-level `prefix`.
+fn add_orig_cargo_toml(
+    entries: &mut OrigEntries,
+    header: &tar::Header,
+    prefix: &Path,
+    output_dir: &Path,
+) -> Result<()> {
+    for name in ["Cargo.toml", "Cargo.toml.orig"] {
+        let data = OrigData::Bytes(fs::read(output_dir.join(name))?);
+        entries.insert(prefix.join(name), OrigEntry::new(header.clone(), data));
+    }
+    Ok(())
+}
+
+/// Copy the entries of the crate tarball into the orig tarball, dropping any
+/// filtered-out files and taking Cargo.toml from the (possibly rewritten)
+/// unpacked crate.
+///
+/// The output is reproducible: entries are sorted by path, ownership is reset
+/// to root, and every mtime is set to the newest mtime in the input tarball.
+/// To sort the entries without holding them in memory, their contents are
+/// first copied to a temporary spool file.
+///
+/// Excluding the target of a hardlink that is kept is an error, since the
+/// link would need the excluded contents.
+fn repack_orig_tarball<R: Read, W: IoWrite>(
+    filter_path: &dyn Fn(&Path) -> std::result::Result<bool, String>,
+    archive: &mut Archive<R>,
+    new_archive: &mut Builder<W>,
+    output_dir: &Path,
+) -> Result<()> {
+    let mut spool = tempfile::tempfile()?;
+    let mut entries = BTreeMap::new();
+    let mut excluded = HashSet::new();
+    let mut last_mtime = 0;
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+        let header = entry.header().clone();
+        if let Ok(mtime) = header.mtime() {
+            last_mtime = last_mtime.max(mtime);
+        }
+        if path.ends_with("Cargo.toml") && path.iter().count() == 2 {
+            // Put the rewritten and original Cargo.toml back into the orig tarball
+            add_orig_cargo_toml(&mut entries, &header, path.parent().unwrap(), output_dir)?;
+        } else if path.ends_with("Cargo.toml.orig") && path.iter().count() == 2 {
+            // already added above, together with Cargo.toml
+            continue;
+        } else if keep_orig_path(filter_path, &path)? {
+            let link_name = entry.link_name()?.map(|l| l.into_owned());
+            let offset = spool.seek(io::SeekFrom::End(0))?;
+            let len = io::copy(&mut entry, &mut spool)?;
+            entries.insert(
+                path,
+                OrigEntry {
+                    header,
+                    link_name,
+                    data: OrigData::Spooled { offset, len },
+                },
+            );
+        } else {
+            excluded.insert(path);
+        }
+    }
+    for (path, entry) in &entries {
+        match &entry.link_name {
+            Some(target)
+                if entry.header.entry_type().is_hard_link() && excluded.contains(target) =>
+            {
+                debcargo_bail!(
+                    "{:?} is a hardlink to the excluded {:?}, exclude it too",
+                    path,
+                    target
+                )
+            }
+            _ => (),
+        }
+    }
+
+    write_orig_entries(entries, Some(&spool), last_mtime, new_archive)
+}
+
+/// Pack an unpacked crate directory into the orig tarball, with the same
+/// filtering and normalisation as `repack_orig_tarball`.
+///
+/// Only the paths in `packaged`, i.e. what `cargo package` put into the
+/// crate, are included, so that the orig tarball honours the `include` and
+/// `exclude` keys of Cargo.toml just like the unpacked crate does. The
+/// top-level `target` directory and VCS metadata are never even walked.
+fn pack_orig_dir<W: IoWrite>(
+    filter_path: &dyn Fn(&Path) -> std::result::Result<bool, String>,
+    src_dir: &Path,
+    prefix: &Path,
+    packaged: &BTreeSet<PathBuf>,
+    new_archive: &mut Builder<W>,
+    output_dir: &Path,
+) -> Result<()> {
+    let mut entries = BTreeMap::new();
+    let mut last_mtime = 0;
+    let walker = WalkDir::new(src_dir)
+        .min_depth(1)
+        .into_iter()
+        .filter_entry(|e| {
+            e.depth() > 1 || !matches!(e.file_name().to_str(), Some("target" | ".git" | ".hg"))
+        });
+    for entry in walker {
+        let entry = entry?;
+        let rel = entry.path().strip_prefix(src_dir)?;
+        let path = prefix.join(rel);
+        // .crate files usually have no directory entries, so keep a
+        // directory if anything below it was packaged
+        let is_packaged = packaged
+            .range(path.clone()..)
+            .next()
+            .map_or(false, |p| p.starts_with(&path));
+        if !is_packaged {
+            continue;
+        }
+        let metadata = entry.metadata()?;
+        let mtime = metadata
+            .modified()?
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        last_mtime = u64::max(last_mtime, mtime);
+
+        let mut header = tar::Header::new_gnu();
+        header.set_mode(metadata.permissions().mode());
+        if rel == Path::new("Cargo.toml") {
+            header.set_entry_type(tar::EntryType::Regular);
+            add_orig_cargo_toml(&mut entries, &header, prefix, output_dir)?;
+        } else if rel == Path::new("Cargo.toml.orig") || !keep_orig_path(filter_path, &path)? {
+            continue;
+        } else if metadata.is_dir() {
+            header.set_entry_type(tar::EntryType::Directory);
+            entries.insert(path, OrigEntry::new(header, OrigData::Bytes(Vec::new())));
+        } else if metadata.is_file() {
+            header.set_entry_type(tar::EntryType::Regular);
+            let data = OrigData::File {
+                path: entry.path().to_path_buf(),
+                len: metadata.len(),
+            };
+            entries.insert(path, OrigEntry::new(header, data));
+        } else if entry.path_is_symlink() {
+            header.set_entry_type(tar::EntryType::Symlink);
+            let mut symlink = OrigEntry::new(header, OrigData::Bytes(Vec::new()));
+            symlink.link_name = Some(fs::read_link(entry.path())?);
+            entries.insert(path, symlink);
+        } else {
+            debcargo_warn!("Skipping special file in local crate: {:?}", entry.path());
+        }
+    }
+    entries.insert(prefix.to_path_buf(), {
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Directory);
+        header.set_mode(0o755);
+        OrigEntry::new(header, OrigData::Bytes(Vec::new()))
+    });
+
+    write_orig_entries(entries, None, last_mtime, new_archive)
+}
+
+/// Paths of all the entries of the crate tarball.
+fn crate_tarball_paths(crate_info: &CrateInfo) -> Result<BTreeSet<PathBuf>> {
+    let (_, tarball) = crate_info.crate_tarball()?;
+    let mut paths = BTreeSet::new();
+    for entry in Archive::new(tarball).entries()? {
+        paths.insert(entry?.path()?.into_owned());
+    }
+    Ok(paths)
+}
+
+fn keep_orig_path(
+    filter_path: &dyn Fn(&Path) -> std::result::Result<bool, String>,
+    path: &Path,
+) -> Result<bool> {
+    match filter_path(path) {
+        Err(e) => debcargo_bail!(e),
+        Ok(true) => {
+            writeln!(
+                io::stderr(),
+                "Filtered out files from .orig.tar: {:?}",
+                path
+            )?;
+            Ok(false)
+        }
+        Ok(false) => Ok(true),
+    }
+}
+
+/// Write out the collected entries, sorted by path and with normalised
+/// metadata. Symlinks are kept as-is; hardlinks are only kept if their target
+/// comes earlier in the tarball, and are otherwise stored as regular files so
+/// that the tarball always unpacks to a well-formed tree.
+fn write_orig_entries<W: IoWrite>(
+    entries: OrigEntries,
+    spool: Option<&fs::File>,
+    last_mtime: u64,
+    new_archive: &mut Builder<W>,
+) -> Result<()> {
+    for (path, entry) in &entries {
+        let mut entry_type = entry.header.entry_type();
+        let mut data = &entry.data;
+        let mut link_name = entry.link_name.as_deref();
+        if entry_type.is_hard_link() {
+            let target = link_name.and_then(|l| entries.get_key_value(l));
+            match target {
+                Some((target_path, _)) if target_path < path => {}
+                Some((_, target)) if target.header.entry_type().is_file() => {
+                    entry_type = tar::EntryType::Regular;
+                    data = &target.data;
+                    link_name = None;
+                }
+                _ => {
+                    debcargo_warn!("Dropping hardlink with missing target: {:?}", path);
+                    continue;
+                }
+            }
+        }
+
+        let executable = entry.header.mode().map_or(false, |m| m & 0o111 != 0);
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(entry_type);
+        header.set_mode(if entry_type.is_symlink() {
+            0o777
+        } else if entry_type.is_dir() || executable {
+            0o755
+        } else {
+            0o644
+        });
+        header.set_mtime(last_mtime);
+        header.set_uid(0);
+        header.set_gid(0);
+        header.set_username("")?;
+        header.set_groupname("")?;
+        match link_name {
+            Some(link_name) => {
+                header.set_size(0);
+                new_archive.append_link(&mut header, path, link_name)?;
+            }
+            None => {
+                header.set_size(data.len());
+                new_archive.append_data(&mut header, path, data.reader(spool)?)?;
+            }
+        }
+    }
+    new_archive.finish()?;
     Ok(())
 }
 
@@ -219,6 +581,13 @@ it's a maintenance burden. Use debcargo.toml instead."
         )
     }
     if tempdir.path().join("patches").join("series").exists() {
+        if config.source_format.is_native() {
+            debcargo_bail!(
+                "source_format is \"{}\", but the overlay has patches/series; \
+                native packages cannot carry quilt patches",
+                config.source_format.as_str()
+            );
+        }
         // apply patches to Cargo.toml in case they exist, and re-read it
         let output_dir = &fs::canonicalize(&output_dir)?;
         let stderr = || {
@@ -228,27 +597,71 @@ it's a maintenance burden. Use debcargo.toml instead."
                 .open("/dev/stderr")
                 .unwrap()
         };
-        expect_success(
+        run_quilt(
             Command::new("quilt")
                 .stdout(stderr())
                 .current_dir(&output_dir)
                 .env("QUILT_PATCHES", tempdir.path().join("patches"))
                 .args(&["push", "--quiltrc=-", "-a"]),
             "failed to apply patches using quilt",
-        );
+        )?;
         crate_info.replace_manifest(&output_dir.join("Cargo.toml"))?;
-        expect_success(
+        // cargo verifies the checksums against the sources that are built
+        let checksums = cargo_checksum_files(crate_info, output_dir)?;
+        crate_info.set_patched_checksums(checksums);
+        run_quilt(
             Command::new("quilt")
                 .stdout(stderr())
                 .current_dir(&output_dir)
                 .env("QUILT_PATCHES", tempdir.path().join("patches"))
                 .args(&["pop", "--quiltrc=-", "-a"]),
             "failed to unapply patches",
-        );
+        )?;
     }
     Ok(tempdir)
 }
 
+fn run_quilt(cmd: &mut Command, err: &str) -> Result<()> {
+    match cmd.status() {
+        Ok(status) if status.success() => Ok(()),
+        Ok(status) => Err(DebcargoError::Quilt(format!("{} ({})", err, status)).into()),
+        Err(e) => Err(DebcargoError::Quilt(format!("{}\n{}", err, e)).into()),
+    }
+}
+
+/// Generated files under debian/, keyed by their path relative to debian/.
+pub type DebianFiles = BTreeMap<PathBuf, Vec<u8>>;
+
+/// Machine-readable summary of the generated packages, for automation. Only
+/// written on request, since it is not meant to be shipped.
+pub const SUMMARY_FILE: &str = ".debcargo-summary.json";
+
+/// Files under debian/ that must be executable.
+const EXECUTABLE_FILES: &[&str] = &["rules", "debcargo_testing_bin/env"];
+
+/// A file in a `DebianFiles` map that is being written to.
+struct DebianFile {
+    files: Rc<RefCell<DebianFiles>>,
+    name: PathBuf,
+}
+
+impl IoWrite for DebianFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut files = self.files.borrow_mut();
+        files
+            .entry(self.name.clone())
+            .or_default()
+            .extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Write the generated debian/ folder into `output_dir`, or with `diff_only`
+/// return a unified diff against the existing one instead.
 #[allow(clippy::too_many_arguments)]
 pub fn prepare_debian_folder(
     crate_info: &mut CrateInfo,
@@ -260,10 +673,222 @@ pub fn prepare_debian_folder(
     changelog_ready: bool,
     copyright_guess_harder: bool,
     overlay_write_back: bool,
-) -> Result<()> {
-    let mut create = fs::OpenOptions::new();
-    create.write(true).create_new(true);
+    write_summary: bool,
+    regenerate_only: &[String],
+    diff_only: bool,
+) -> Result<Option<String>> {
+    let author = if changelog_ready {
+        None
+    } else {
+        Some(control::get_deb_author()?)
+    };
+    let mut files = generate_debian_files(
+        crate_info,
+        deb_info,
+        config_path,
+        config,
+        output_dir,
+        tempdir.path(),
+        author.as_deref(),
+        copyright_guess_harder,
+        write_summary,
+    )?;
+    select_debian_files(&mut files, regenerate_only)?;
+
+    // Files that already exist in the overlay are kept, and the generated
+    // version is written next to them as a hint instead.
+    let mut new_hints = vec![];
+    let mut written = vec![];
+    for (name, data) in &files {
+        let name = name.to_str().unwrap();
+        let mut path = tempdir.path().join(name);
+        fs::create_dir_all(path.parent().unwrap())?;
+        // d/changelog is generated from the overlay one, so it replaces it
+        if path.exists() && name != "changelog" {
+            let hintname = name.to_owned() + util::HINT_SUFFIX;
+            path = tempdir.path().join(&hintname);
+            written.push(hintname.clone());
+            new_hints.push(hintname);
+        } else {
+            written.push(name.to_string());
+        }
+        fs::write(&path, data)?;
+        if EXECUTABLE_FILES.contains(&name) {
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o777))?;
+        }
+    }
+
+    if diff_only {
+        let names = if regenerate_only.is_empty() {
+            None
+        } else {
+            Some(written.as_slice())
+        };
+        let diff = diff_debian_dirs(&output_dir.join("debian"), tempdir.path(), names)?;
+        return Ok(Some(diff));
+    }
+
+    if overlay_write_back {
+        let overlay = config.overlay_dir(config_path);
+        if let Some(p) = overlay.as_ref() {
+            if !changelog_ready && files.contains_key(Path::new("changelog")) {
+                // Special-case d/changelog:
+                // Always write it back, this is safe because of our prepending logic
+                new_hints.push("changelog".to_string());
+            }
+            for hint in &new_hints {
+                let newpath = tempdir.path().join(hint);
+                let oldpath = p.join(hint);
+                fs::copy(newpath, oldpath).expect("could not write back");
+                debcargo_info!("Wrote back file to overlay: {}", hint);
+            }
+        }
+    }
+
+    if regenerate_only.is_empty() {
+        util::rename_or_copy(tempdir.path(), &output_dir.join("debian"))?;
+    } else {
+        // leave everything else in the existing debian/ alone
+        for name in &written {
+            let path = output_dir.join("debian").join(name);
+            fs::create_dir_all(path.parent().unwrap())?;
+            fs::copy(tempdir.path().join(name), &path)?;
+            debcargo_info!("Regenerated debian/{}", name);
+        }
+    }
+    Ok(None)
+}
+
+/// If `result` is an error, keep `tempdir` instead of removing it on drop, so
+/// that the files generated so far can be inspected, and print its location.
+pub fn keep_tempdir_on_error<T>(
+    tempdir: &mut Option<tempfile::TempDir>,
+    result: Result<T>,
+) -> Result<T> {
+    if result.is_err() {
+        if let Some(tempdir) = tempdir.take() {
+            let path = tempdir.into_path();
+            debcargo_warn!("Kept the partially generated debian/ in {}", path.display());
+        }
+    }
+    result
+}
+
+/// A unified diff from the files under `old` to those under `new`, or only
+/// the given ones, with paths as in the package directory.
+fn diff_debian_dirs(old: &Path, new: &Path, names: Option<&[String]>) -> Result<String> {
+    let names = match names {
+        Some(names) => names.iter().cloned().collect(),
+        None => {
+            let mut names = BTreeSet::new();
+            for dir in [old, new].iter().filter(|d| d.is_dir()) {
+                for entry in WalkDir::new(dir) {
+                    let entry = entry?;
+                    if !entry.file_type().is_dir() {
+                        names.insert(util::rel_p(entry.path(), dir).to_string());
+                    }
+                }
+            }
+            names
+        }
+    };
+    let mut diff = String::new();
+    for name in names {
+        let output = Command::new("diff")
+            .arg("-Nu")
+            .arg("--label")
+            .arg(format!("a/debian/{}", name))
+            .arg("--label")
+            .arg(format!("b/debian/{}", name))
+            .arg(old.join(&name))
+            .arg(new.join(&name))
+            .output()?;
+        // 1 means that the files differ
+        match output.status.code() {
+            Some(0 | 1) => diff.push_str(&String::from_utf8_lossy(&output.stdout)),
+            _ => debcargo_bail!(
+                "failed to diff debian/{}: {}",
+                name,
+                String::from_utf8_lossy(&output.stderr)
+            ),
+        }
+    }
+    Ok(diff)
+}
+
+/// A uscan stanza that tracks the tags of the upstream git repository, for
+/// watch_mode = "git". Tags may be prefixed by "v" or the crate name, as is
+/// common for crates in a workspace. With pgp, the tags must be signed.
+fn git_watch(
+    repository: &str,
+    upstream_name: &str,
+    version_pattern: &str,
+    repack: bool,
+    pgp: bool,
+) -> String {
+    let pgpmode = if pgp {
+        "pgpmode=gittag"
+    } else {
+        "pgpmode=none"
+    };
+    let mut opts = vec!["mode=git".to_string(), pgpmode.to_string()];
+    if repack {
+        opts.push(r"dversionmangle=s/\+dfsg\d*$//".to_string());
+        opts.push(format!("repacksuffix={}", REPACK_SUFFIX));
+    }
+    opts.push(
+        r"uversionmangle=s/(\d)[_\.\-\+]?((RC|rc|pre|dev|beta|alpha)\d*)$/$1~$2/".to_string(),
+    );
+    format!(
+        "version=4\nopts=\"{}\" \\\n{} \\\nrefs/tags/(?:{}-)?v?{}\n",
+        opts.join(",\\\n"),
+        repository,
+        upstream_name,
+        version_pattern
+    )
+}
+
+/// Keep only the files to regenerate, if any are given.
+fn select_debian_files(files: &mut DebianFiles, regenerate_only: &[String]) -> Result<()> {
+    if regenerate_only.is_empty() {
+        return Ok(());
+    }
+    for name in regenerate_only {
+        if !files.contains_key(Path::new(name)) {
+            debcargo_bail!(
+                "cannot regenerate debian/{}: debcargo does not generate this file, expected one of: {}",
+                name,
+                files
+                    .keys()
+                    .map(|k| k.display().to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        }
+    }
+    files.retain(|name, _| regenerate_only.iter().any(|r| Path::new(r) == name));
+    Ok(())
+}
 
+/// Generate the contents of the debian/ folder in memory.
+///
+/// `output_dir` is the unpacked crate and `overlay_dir` holds the files that
+/// are already present, i.e. the overlay, from which the existing
+/// d/changelog is read. A new d/changelog entry is made by `changelog_author`,
+/// if one is given. Nothing is written to disk.
+#[allow(clippy::too_many_arguments)]
+pub fn generate_debian_files(
+    crate_info: &CrateInfo,
+    deb_info: &DebInfo,
+    config_path: Option<&Path>,
+    config: &Config,
+    output_dir: &Path,
+    overlay_dir: &Path,
+    changelog_author: Option<&str>,
+    copyright_guess_harder: bool,
+    write_summary: bool,
+) -> Result<DebianFiles> {
+    let changelog_ready = changelog_author.is_none();
     let crate_name = crate_info.package_id().name();
     let crate_version = crate_info.package_id().version();
     let upstream_name = deb_info.upstream_name();
@@ -276,22 +901,13 @@ pub fn prepare_debian_folder(
         .map(String::as_str)
         .collect();
 
-    let mut new_hints = vec![];
-    let mut file = |name: &str| {
-        let path = tempdir.path();
-        let f = path.join(name);
-        fs::create_dir_all(f.parent().unwrap())?;
-        create.open(&f).or_else(|e| match e.kind() {
-            ErrorKind::AlreadyExists => {
-                let hintname = name.to_owned() + util::HINT_SUFFIX;
-                let hint = path.join(&hintname);
-                if hint.exists() {
-                    fs::remove_file(&hint)?;
-                }
-                new_hints.push(hintname);
-                create.open(&hint)
-            }
-            _ => Err(e),
+    let files = Rc::new(RefCell::new(DebianFiles::new()));
+    let mut file = |name: &str| -> io::Result<DebianFile> {
+        let name = PathBuf::from(name);
+        files.borrow_mut().insert(name.clone(), Vec::new());
+        Ok(DebianFile {
+            files: files.clone(),
+            name,
         })
     };
 
@@ -300,11 +916,15 @@ pub fn prepare_debian_folder(
         let checksum = crate_info
             .checksum()
             .unwrap_or("Could not get crate checksum");
+        let files = match crate_info.patched_checksums() {
+            Some(files) => files.clone(),
+            None => cargo_checksum_files(crate_info, output_dir)?,
+        };
         let mut cargo_checksum_json = file("cargo-checksum.json")?;
         writeln!(
             cargo_checksum_json,
-            r#"{{"package":"{}","files":{{}}}}"#,
-            checksum
+            "{}",
+            serde_json::json!({ "package": checksum, "files": files })
         )?;
     }
 
@@ -319,19 +939,27 @@ pub fn prepare_debian_folder(
         let mut copyright = io::BufWriter::new(file("copyright")?);
         let year_range = if changelog_ready {
             // if changelog is ready, unconditionally read the year range from it
-            changelog_first_last(tempdir.path())?
+            changelog_first_last(overlay_dir)?
         } else {
             // otherwise use the first date if it exists
             let last = chrono::Local::now().year();
-            match changelog_first_last(tempdir.path()) {
+            match changelog_first_last(overlay_dir) {
                 Ok((first, _)) => (first, last),
                 Err(_) => (last, last),
             }
         };
+        let registry_url = crate_info.registry_url();
+        let crate_src = config.crate_src_path(config_path);
+        let inputs = CopyrightInputs {
+            srcdir: output_dir,
+            manifest: crate_info.manifest(),
+            manifest_path: crate_info.manifest_path(),
+            files_excluded: deb_info.files_excluded(),
+            registry: registry_url.as_deref(),
+            crate_src: crate_src.as_deref(),
+        };
         let dep5_copyright = debian_copyright(
-            output_dir,
-            crate_info.manifest(),
-            crate_info.manifest_path(),
+            &inputs,
             maintainer,
             &uploaders,
             year_range,
@@ -343,50 +971,182 @@ pub fn prepare_debian_folder(
     // debian/watch
     {
         let mut watch = file("watch")?;
-        match config.crate_src_path(config_path) {
-            Some(_) => write!(watch, "FIXME add uscan directive for local crate")?,
-            None => {
+        match (
+            config.crate_src_path(config_path),
+            crate_info.registry_url(),
+        ) {
+            (Some(_), _) => write!(watch, "FIXME add uscan directive for local crate")?,
+            (None, Some(url)) => write!(
+                watch,
+                "FIXME add uscan directive for crate from registry {}",
+                url
+            )?,
+            (None, None) => {
                 let uscan_version_pattern = deb_info
                     .uscan_version_pattern
                     .as_ref()
                     .map_or_else(|| "@ANY_VERSION@".to_string(), |ref s| s.to_string());
-                writeln!(watch, "version=4")?;
-                writeln!(
-                    watch,
-                    r"opts=filenamemangle=s/.*\/(.*)\/download/{name}-$1\.tar\.gz/g,\",
-                    name = upstream_name
-                )?;
-                writeln!(
-                    watch,
-                    r"uversionmangle=s/(\d)[_\.\-\+]?((RC|rc|pre|dev|beta|alpha)\d*)$/$1~$2/ \"
-                )?;
-                writeln!(
-                    watch,
-                    "https://qa.debian.org/cgi-bin/fakeupstream.cgi?upstream=crates.io/{name} \
-                     .*/crates/{name}/{version_pattern}/download",
-                    name = upstream_name,
-                    version_pattern = uscan_version_pattern
-                )?;
+                let repository = match config.watch_mode {
+                    WatchMode::CratesIo => None,
+                    WatchMode::Git => {
+                        let repository = crate_info.metadata().repository.as_deref();
+                        if repository.is_none() {
+                            debcargo_warn!(
+                                "watch_mode is \"git\" but the crate has no repository, \
+                                 falling back to crates.io in d/watch"
+                            );
+                        }
+                        repository
+                    }
+                };
+                if let Some(repository) = repository {
+                    write!(
+                        watch,
+                        "{}",
+                        git_watch(
+                            repository,
+                            upstream_name,
+                            &uscan_version_pattern,
+                            !deb_info.files_excluded().is_empty(),
+                            config.watch_pgp,
+                        )
+                    )?;
+                } else {
+                    writeln!(watch, "version=4")?;
+                    writeln!(
+                        watch,
+                        r"opts=filenamemangle=s/.*\/(.*)\/download/{name}-$1\.tar\.gz/g,\",
+                        name = upstream_name
+                    )?;
+                    if config.watch_pgp {
+                        writeln!(watch, r"pgpmode=mangle,\")?;
+                        writeln!(
+                            watch,
+                            r"pgpsigurlmangle={},\",
+                            config.watch_pgpsigurlmangle()
+                        )?;
+                    }
+                    if !deb_info.files_excluded().is_empty() {
+                        writeln!(
+                            watch,
+                            r"dversionmangle=s/\+dfsg\d*$//,repacksuffix={},\",
+                            REPACK_SUFFIX
+                        )?;
+                    }
+                    writeln!(
+                        watch,
+                        r"uversionmangle=s/(\d)[_\.\-\+]?((RC|rc|pre|dev|beta|alpha)\d*)$/$1~$2/ \"
+                    )?;
+                    writeln!(
+                        watch,
+                        "https://qa.debian.org/cgi-bin/fakeupstream.cgi?upstream=crates.io/{name} \
+                         .*/crates/{name}/{version_pattern}/download",
+                        name = upstream_name,
+                        version_pattern = uscan_version_pattern
+                    )?;
+                }
             }
         };
     }
 
+    // debian/clean
+    {
+        let clean = clean_files(config, output_dir);
+        if !clean.is_empty() {
+            let mut clean_file = file("clean")?;
+            for path in clean {
+                writeln!(clean_file, "{}", path)?;
+            }
+        }
+    }
+
     // debian/source/format
     {
-        fs::create_dir_all(tempdir.path().join("source"))?;
         let mut source_format = file("source/format")?;
-        writeln!(source_format, "3.0 (quilt)")?;
+        writeln!(source_format, "{}", config.source_format.as_str())?;
+        let options = source_options(config, deb_info.orig_compression())?;
+        if !options.is_empty() {
+            let mut source_options = file("source/options")?;
+            write!(source_options, "{}", options)?;
+        }
+    }
+
+    // debian/gbp.conf
+    if let Some(gbp) = config.gbp.as_ref() {
+        let mut gbp_conf = file("gbp.conf")?;
+        write!(gbp_conf, "{}", gbp_conf_contents(gbp))?;
+    }
+
+    // debian/upstream/signing-key.asc
+    if config.watch_pgp {
+        match config.upstream_signing_key(config_path) {
+            Some(path) => {
+                let key = fs::read(&path).with_context(|| {
+                    format!("failed to read upstream_signing_key {}", path.display())
+                })?;
+                file("upstream/signing-key.asc")?.write_all(&key)?;
+            }
+            None if !overlay_dir.join("upstream/signing-key.asc").exists() => {
+                debcargo_warn!(
+                    "watch_pgp is set, but there is no upstream signing key; set \
+                     upstream_signing_key or add debian/upstream/signing-key.asc to \
+                     the overlay"
+                );
+            }
+            None => {}
+        }
+    }
+
+    // debian/upstream/metadata
+    if let Some(repository) = crate_info.metadata().repository.as_deref() {
+        let mut metadata = file("upstream/metadata")?;
+        write!(
+            metadata,
+            "{}",
+            upstream_metadata(repository, crate_info.registry_url().is_none())
+        )?;
     }
 
     // debian/control & debian/tests/control
-    let (source, has_dev_depends, default_test_broken) =
-        prepare_debian_control(deb_info, crate_info, config, &mut file)?;
+    let mut packages_summary = Vec::new();
+    let existing_control = existing_control(output_dir)?;
+    let previous_packages =
+        previous_feature_packages(config, deb_info.package_name(), existing_control.as_deref());
+    let (source, has_dev_depends, default_test_policy) = prepare_debian_control(
+        deb_info,
+        crate_info,
+        config,
+        &mut file,
+        &previous_packages,
+        &mut packages_summary,
+    )?;
+
+    if let Some(existing_control) = existing_control.as_deref() {
+        let existing_packages = control::binary_packages_in_control(existing_control);
+        for package in dropped_packages(&existing_packages, &packages_summary) {
+            debcargo_warn!(
+                "Package {} of the existing debian/control is neither generated nor provided \
+                 any more, packages that depend on it will become uninstallable",
+                package
+            );
+        }
+    }
+
+    // debian/.debcargo-summary.json, not part of the source package
+    if write_summary {
+        let mut summary = file(SUMMARY_FILE)?;
+        let summary_json = serde_json::json!({
+            "source": source.name(),
+            "version": crate_version.to_string(),
+            "deb_upstream_version": deb_info.deb_upstream_version(),
+            "packages": packages_summary,
+        });
+        writeln!(summary, "{}", serde_json::to_string_pretty(&summary_json)?)?;
+    }
 
     // for testing only, debian/debcargo_testing_bin/env
     if testing_ignore_debpolv() {
-        fs::create_dir_all(tempdir.path().join("debcargo_testing_bin"))?;
         let mut env_hack = file("debcargo_testing_bin/env")?;
-        env_hack.set_permissions(fs::Permissions::from_mode(0o777))?;
         // intercept calls to dh-cargo-built-using
         writeln!(
             env_hack,
@@ -401,63 +1161,140 @@ echo "debcargo testing: suppressing dh-cargo-built-using";;
     // debian/rules
     {
         let mut rules = file("rules")?;
-        rules.set_permissions(fs::Permissions::from_mode(0o777))?;
-        if has_dev_depends || testing_ignore_debpolv() {
+        write!(
+            rules,
+            "{}",
+            concat!(
+                "#!/usr/bin/make -f\n",
+                "%:\n",
+                "\tdh $@ --buildsystem cargo\n"
+            )
+        )?;
+        // some crates need nightly to compile, annoyingly. only do this in
+        // testing; outside of testing the user should explicitly override
+        // debian/rules to do this
+        if testing_ignore_debpolv() {
+            writeln!(rules, "export RUSTC_BOOTSTRAP := 1")?;
+            writeln!(
+                rules,
+                "export PATH := $(CURDIR)/debian/debcargo_testing_bin:$(PATH)"
+            )?;
+        }
+        if !config.test {
+            write!(
+                rules,
+                "{}",
+                concat!(
+                    "\n",
+                    "override_dh_auto_test:\n",
+                    "# tests are disabled with test = false in debcargo.toml\n",
+                )
+            )?;
+        } else if has_dev_depends || testing_ignore_debpolv() {
             // don't run any tests, we don't want extra B-D on dev-depends
             // this could potentially cause B-D cycles so we avoid it
             //
             // also don't run crate tests during integration testing since some
             // of them are brittle and fail; the purpose is to test debcargo
             // not the actual crates
+        } else {
             write!(
                 rules,
-                "{}",
-                concat!(
-                    "#!/usr/bin/make -f\n",
-                    "%:\n",
-                    "\tdh $@ --buildsystem cargo\n"
-                )
+                "\noverride_dh_auto_test:\n{}",
+                // if another feature "provides" the default feature, this
+                // takes test_is_broken from its package too
+                dh_auto_test_command(config, default_test_policy),
             )?;
-            // some crates need nightly to compile, annoyingly. only do this in
-            // testing; outside of testing the user should explicitly override
-            // debian/rules to do this
-            if testing_ignore_debpolv() {
-                writeln!(rules, "export RUSTC_BOOTSTRAP := 1")?;
-                writeln!(
-                    rules,
-                    "export PATH := $(CURDIR)/debian/debcargo_testing_bin:$(PATH)"
-                )?;
+        }
+        let mut install_cmds = vec![];
+        if config.data_package && crate_info.is_lib() {
+            // move the data files out of the -dev package, into the same
+            // location in the -data package which it depends on
+            let registry = format!("usr/share/cargo/registry/{}-{}", crate_name, crate_version);
+            let dev_dir = format!("debian/{}/{}", deb_name(deb_info.package_name()), registry);
+            let data_dir = format!(
+                "debian/{}/{}",
+                deb_data_name(deb_info.package_name()),
+                registry
+            );
+            install_cmds.push(format!("mkdir -p {}", data_dir));
+            for path in config.data_package_paths() {
+                install_cmds.push(format!("mv {}/{} {}/", dev_dir, path, data_dir));
             }
+        }
+        let (bins, examples) = binary_targets(crate_info, config);
+        let all_bins: Vec<&str> = bins.iter().chain(&examples).copied().collect();
+        let suffix = deb_info.name_suffix().unwrap_or_default();
+        let bin_dirs: Vec<(String, &str)> = if config.split_bins && all_bins.len() > 1 {
+            split_bin_packages(&all_bins)
+                .into_iter()
+                .map(|(basename, bin)| (format!("debian/{}{}/usr/bin", basename, suffix), bin))
+                .collect()
         } else {
-            write!(
+            let bin_name = bin_package_name(config, deb_info);
+            let bin_dir = format!("debian/{}{}/usr/bin", bin_name, suffix);
+            all_bins.iter().map(|bin| (bin_dir.clone(), *bin)).collect()
+        };
+        // dh-cargo installs all binaries into the first binary package, move
+        // the others into their own packages
+        for (bin_dir, bin) in bin_dirs.iter().take(bins.len()).skip(1) {
+            if *bin_dir != bin_dirs[0].0 {
+                install_cmds.push(format!("mkdir -p {}", bin_dir));
+                install_cmds.push(format!("mv {}/{} {}/", bin_dirs[0].0, bin, bin_dir));
+            }
+        }
+        // dh-cargo doesn't know about examples, so build and install them
+        // ourselves
+        for (bin_dir, example) in bin_dirs.iter().skip(bins.len()) {
+            install_cmds.push(format!(
+                "install -Dm755 target/$(DEB_HOST_RUST_TYPE)/release/examples/{} {}/{}",
+                example, bin_dir, example
+            ));
+        }
+        // build the binaries with the versions in the Cargo.lock that the
+        // crate ships, or fail if they are not available
+        let locked = config.keep_cargo_lock == Some(true)
+            && !bins.is_empty()
+            && output_dir.join("Cargo.lock").exists();
+        let locked_arg = if locked { " --locked" } else { "" };
+        if !examples.is_empty() {
+            writeln!(rules, "\ninclude /usr/share/rustc/architecture.mk")?;
+        }
+        if locked || !examples.is_empty() {
+            writeln!(rules, "\noverride_dh_auto_build:")?;
+            if locked {
+                writeln!(rules, "\tdh_auto_build -- build --locked")?;
+            } else {
+                writeln!(rules, "\tdh_auto_build")?;
+            }
+        }
+        if !examples.is_empty() {
+            writeln!(
                 rules,
-                "{}{}",
-                concat!(
-                    "#!/usr/bin/make -f\n",
-                    "%:\n",
-                    "\tdh $@ --buildsystem cargo\n",
-                    "\n",
-                    "override_dh_auto_test:\n",
-                ),
-                // TODO: this logic is slightly brittle if another feature
-                // "provides" the default feature. In this case, you need to
-                // set test_is_broken explicitly on package."lib+default" and
-                // not package."lib+theotherfeature".
-                if default_test_broken {
-                    "\tdh_auto_test -- test --all || true\n"
-                } else {
-                    "\tdh_auto_test -- test --all\n"
-                },
+                "\tdh_auto_build -- build{}{}",
+                locked_arg,
+                examples
+                    .iter()
+                    .map(|e| format!(" --example {}", e))
+                    .collect::<String>()
             )?;
         }
+        if !install_cmds.is_empty() {
+            writeln!(rules, "\noverride_dh_auto_install:")?;
+            writeln!(rules, "\tdh_auto_install")?;
+            for cmd in install_cmds {
+                writeln!(rules, "\t{}", cmd)?;
+            }
+        }
     }
 
     // debian/changelog
-    if !changelog_ready {
-        let author = control::get_deb_author()?;
-        let crate_src = match config.crate_src_path(config_path) {
-            Some(_) => "local source",
-            None => "crates.io",
+    if let Some(author) = changelog_author {
+        let registry_url = crate_info.registry_url();
+        let crate_src = match (config.crate_src_path(config_path), &registry_url) {
+            (Some(_), _) => "local source",
+            (None, Some(url)) => url.as_str(),
+            (None, None) => "crates.io",
         };
         let autogenerated_item = format!(
             "  * Package {} {} from {} using debcargo {}",
@@ -468,19 +1305,19 @@ echo "debcargo testing: suppressing dh-cargo-built-using";;
         );
         let autogenerated_re = Regex::new(&format!(
             r"^  \* Package (.*) (.*) from {} using debcargo (.*)$",
-            &crate_src
+            regex::escape(crate_src)
         ))
         .unwrap();
 
         // Special-case d/changelog:
-        let (mut changelog, changelog_data) = changelog_or_new(tempdir.path())?;
-        let (changelog_old, mut changelog_items, deb_version_suffix) = {
+        let changelog_data = changelog_or_new(overlay_dir)?;
+        let (changelog_old, mut changelog_items, changelog_sections, deb_version_suffix) = {
             let ver_bump = &|e: &Option<&str>| -> Result<Option<String>> {
                 Ok(match e {
                     Some(x) => {
                         let e = ChangelogEntry::from_str(x)?;
-                        if e.version_parts().0 == deb_info.deb_upstream_version() {
-                            Some(e.deb_version_suffix_bump())
+                        if e.version_parts().0 == deb_info.deb_epoch_upstream_version() {
+                            e.deb_version_suffix_bump()
                         } else {
                             None
                         }
@@ -493,38 +1330,40 @@ echo "debcargo testing: suppressing dh-cargo-built-using";;
             match e1 {
                 // If the first entry has changelog::DEFAULT_DIST then write over it smartly
                 Some(x) if x.contains(changelog::DEFAULT_DIST) => {
-                    let mut e = ChangelogEntry::from_str(x)?;
-                    if author == e.maintainer {
-                        if let Some(pos) = e.items.iter().position(|x| autogenerated_re.is_match(x))
-                        {
-                            e.items[pos] = autogenerated_item;
-                        } else {
-                            e.items.push(autogenerated_item);
-                        }
-                    } else {
-                        // If unreleased changelog is by someone else, preserve their entries
-                        e.items.insert(0, autogenerated_item);
-                        e.items.insert(1, "".to_string());
-                        let ename = e.maintainer_name();
-                        e.items.insert(2, format!("  [ {} ]", ename));
-                    }
-                    (&changelog_data[x.len()..], e.items, ver_bump(&chit.next())?)
+                    let e = ChangelogEntry::from_str(x)?;
+                    let (items, sections) =
+                        e.merge_items(author, autogenerated_item, &autogenerated_re);
+                    (
+                        &changelog_data[x.len()..],
+                        items,
+                        sections,
+                        ver_bump(&chit.next())?,
+                    )
                 }
                 // Otherwise prepend a new entry to the existing entries
                 _ => (
                     changelog_data.as_str(),
                     vec![autogenerated_item],
+                    vec![],
                     ver_bump(&e1)?,
                 ),
             }
         };
 
-        let source_deb_version = format!(
-            "{}-{}",
-            deb_info.deb_upstream_version(),
-            &deb_version_suffix.unwrap_or_else(|| "1".to_string())
-        );
-        if !uploaders.contains(&author.as_str()) {
+        // native packages have no Debian revision
+        let source_deb_version = if config.source_format.is_native() {
+            deb_info.deb_epoch_upstream_version()
+        } else {
+            let revision = match deb_version_suffix {
+                Some(suffix) => suffix,
+                None => {
+                    changelog::check_debian_revision(config.debian_revision())?;
+                    config.debian_revision().to_string()
+                }
+            };
+            format!("{}-{}", deb_info.deb_epoch_upstream_version(), revision)
+        };
+        if !uploaders.contains(&author) {
             debcargo_warn!(
                 "You ({}) are not in Uploaders; adding \"Team upload\" to d/changelog",
                 author
@@ -533,79 +1372,182 @@ echo "debcargo testing: suppressing dh-cargo-built-using";;
                 changelog_items.insert(0, changelog::COMMENT_TEAM_UPLOAD.to_string());
             }
         }
+        let distribution = config.changelog_distribution();
+        let urgency = config.changelog_urgency();
+        changelog::check_dist_urgency(distribution, urgency)?;
         let changelog_new_entry = ChangelogEntry::new(
             source.name().to_string(),
             source_deb_version,
-            changelog::DEFAULT_DIST.to_string(),
-            "urgency=medium".to_string(),
-            author,
+            distribution.to_string(),
+            format!("urgency={}", urgency),
+            author.to_string(),
             changelog::local_now(),
             changelog_items,
+            changelog_sections,
         );
 
-        changelog.seek(io::SeekFrom::Start(0))?;
+        let mut changelog = file("changelog")?;
         if changelog_old.is_empty() {
             write!(changelog, "{}", changelog_new_entry)?;
         } else {
             write!(changelog, "{}\n{}", changelog_new_entry, changelog_old)?;
         }
-        // the new file might be shorter, truncate it to the current cursor position
-        let pos = changelog.seek(io::SeekFrom::Current(0))?;
-        changelog.set_len(pos)?;
     }
 
-    if overlay_write_back {
-        let overlay = config.overlay_dir(config_path);
-        if let Some(p) = overlay.as_ref() {
-            if !changelog_ready {
-                // Special-case d/changelog:
-                // Always write it back, this is safe because of our prepending logic
-                new_hints.push("changelog".to_string());
-            }
-            for hint in &new_hints {
-                let newpath = tempdir.path().join(hint);
-                let oldpath = p.join(hint);
-                fs::copy(newpath, oldpath).expect("could not write back");
-                debcargo_info!("Wrote back file to overlay: {}", hint);
-            }
+    Ok(Rc::try_unwrap(files)
+        .map_err(|_| format_err!("generated debian files are still in use"))?
+        .into_inner())
+}
+
+/// Files for debian/clean: build artifacts that would otherwise show up as
+/// changes to the upstream source, followed by those listed in the config.
+fn clean_files(config: &Config, output_dir: &Path) -> Vec<String> {
+    let mut clean = Vec::new();
+    match config.keep_cargo_lock {
+        // we stripped it from the orig tarball, but the build creates it again
+        Some(false) => clean.push("Cargo.lock".to_string()),
+        Some(true) if !output_dir.join("Cargo.lock").exists() => {
+            debcargo_warn!("keep_cargo_lock is set, but the crate does not ship a Cargo.lock");
+        }
+        _ => (),
+    }
+    for path in config.clean() {
+        if !clean.contains(path) {
+            clean.push(path.to_string());
+        }
+    }
+    clean
+}
+
+/// For a repository URL on a well-known forge, the URL of the repository
+/// itself, without a .git suffix or a path into it, and the forge host.
+fn forge_repository(repository: &str) -> Option<(String, String)> {
+    let repository = repository.trim().trim_end_matches('/');
+    [
+        r"^(https?://(github\.com)/[^/]+/[^/]+?)(\.git)?(/.*)?$",
+        // GitLab projects can be in nested subgroups, and paths into a
+        // project start with /-/
+        r"^(https?://(gitlab\.com|salsa\.debian\.org)(?:/[^/]+?){2,}?)(\.git)?(/-/.*)?$",
+    ]
+    .iter()
+    .find_map(|re| {
+        let m = Regex::new(re).unwrap().captures(repository)?;
+        Some((m[1].to_string(), m[2].to_string()))
+    })
+}
+
+/// Generate the contents of debian/upstream/metadata (DEP-12) from the crate
+/// repository URL. Bug tracker fields are only given for well-known forges.
+/// The Archive field is only given for crates from crates.io.
+fn upstream_metadata(repository: &str, crates_io: bool) -> String {
+    let repository = repository.trim().trim_end_matches('/');
+    let mut metadata = "---\n".to_string();
+    if crates_io {
+        metadata.push_str("Archive: crates.io\n");
+    }
+    match forge_repository(repository) {
+        Some((browse, host)) => {
+            let issues = if host == "github.com" {
+                format!("{}/issues", browse)
+            } else {
+                format!("{}/-/issues", browse)
+            };
+            writeln!(metadata, "Bug-Database: {}", issues).unwrap();
+            writeln!(metadata, "Bug-Submit: {}/new", issues).unwrap();
+            writeln!(metadata, "Repository: {}.git", browse).unwrap();
+            writeln!(metadata, "Repository-Browse: {}", browse).unwrap();
+        }
+        None => writeln!(metadata, "Repository: {}", repository).unwrap(),
+    }
+    metadata
+}
+
+/// Compute the "files" map of cargo-checksum.json, i.e. the SHA-256 of every
+/// regular file in the unpacked crate, keyed by its path relative to srcdir.
+/// The patches must be applied to srcdir, if there are any.
+fn cargo_checksum_files(crate_info: &CrateInfo, srcdir: &Path) -> Result<BTreeMap<String, String>> {
+    let pkgid = crate_info.package_id();
+    let crate_dir = PathBuf::from(format!("{}-{}", pkgid.name(), pkgid.version()));
+    let mut files = BTreeMap::new();
+    for entry in WalkDir::new(srcdir)
+        .into_iter()
+        .filter_entry(|e| e.depth() != 1 || (e.file_name() != ".pc" && e.file_name() != "debian"))
+    {
+        let entry = entry?;
+        if !entry.file_type().is_file() {
+            continue;
         }
+        let rel_path = entry.path().strip_prefix(srcdir)?;
+        // filter_path patterns expect the top-level crate directory
+        if crate_info.is_excluded(&crate_dir.join(rel_path)) {
+            continue;
+        }
+        let checksum = cargo_util::Sha256::new()
+            .update_path(entry.path())?
+            .finish_hex();
+        files.insert(util::rel_p(entry.path(), srcdir).to_string(), checksum);
     }
+    Ok(files)
+}
 
-    fs::rename(tempdir.path(), output_dir.join("debian"))?;
+/// Warn about feature package names that are very long, and fail if several
+/// features map to the same name after normalization, since the generated
+/// packages would clash.
+fn check_feature_package_names<'a>(
+    pkgbase: &str,
+    features: impl Iterator<Item = &'a str> + Clone,
+) -> Result<()> {
+    for feature in features.clone().filter(|f| !f.is_empty()) {
+        let name = control::deb_feature_name(pkgbase, feature);
+        if name.len() > control::MAX_PACKAGE_NAME_LEN {
+            debcargo_warn!(
+                "Package name for feature \"{}\" is {} characters long: {}",
+                feature,
+                name.len(),
+                name
+            );
+        }
+    }
+    if let Some((name, features)) = control::feature_name_collisions(pkgbase, features)
+        .into_iter()
+        .next()
+    {
+        debcargo_bail!(
+            "Features \"{}\" all map to the same Debian package {}, \
+             patch the crate to rename all but one",
+            features.join("\", \""),
+            name
+        );
+    }
     Ok(())
 }
 
-fn prepare_debian_control<F: FnMut(&str) -> std::result::Result<std::fs::File, std::io::Error>>(
+fn prepare_debian_control<F: FnMut(&str) -> io::Result<DebianFile>>(
     deb_info: &DebInfo,
     crate_info: &CrateInfo,
     config: &Config,
     mut file: F,
-) -> Result<(Source, bool, bool)> {
+    previous_packages: &[String],
+    packages_summary: &mut Vec<serde_json::Value>,
+) -> Result<(Source, bool, Option<BrokenTestPolicy>)> {
     let crate_name = crate_info.crate_name();
-    let deb_upstream_version = deb_info.deb_upstream_version();
+    // the tests run against the crate as published, which has no REPACK_SUFFIX
+    let test_version = deb_upstream_version(crate_info.version());
     let base_pkgname = deb_info.base_package_name();
     let name_suffix = deb_info.name_suffix();
 
     let lib = crate_info.is_lib();
-    let mut bins = crate_info.get_binary_targets();
-    if lib && !bins.is_empty() && !config.build_bin_package() {
-        bins.clear();
+    let (bins, examples) = binary_targets(crate_info, config);
+    let has_bins = !bins.is_empty() || !examples.is_empty();
+    let bin_name = bin_package_name(config, deb_info);
+    if has_bins && config.bin_name.eq(&Config::default().bin_name) {
+        debcargo_info!(
+            "Generate binary crate with default name '{}', set bin_name to override or bin = false to disable.",
+            bin_name
+        );
     }
-    let bin_name = if config.bin_name.eq(&Config::default().bin_name) {
-        let default_bin_name = deb_info.base_package_name();
-        if !bins.is_empty() {
-            debcargo_info!(
-                "Generate binary crate with default name '{}', set bin_name to override or bin = false to disable.",
-                &default_bin_name
-            );
-        }
-        default_bin_name
-    } else {
-        config.bin_name.as_str()
-    };
 
     let maintainer = config.maintainer();
-    let requires_root = config.requires_root();
     let uploaders: Vec<&str> = config
         .uploaders()
         .into_iter()
@@ -615,6 +1557,7 @@ fn prepare_debian_control<F: FnMut(&str) -> std::result::Result<std::fs::File, s
 
     let features_with_deps = crate_info.all_dependencies_and_features();
     let dev_depends = deb_deps(config, &crate_info.dev_dependencies())?;
+    let common_test_depends = common_test_depends(config, &dev_depends);
     log::trace!(
         "features_with_deps: {:?}",
         features_with_deps
@@ -628,24 +1571,49 @@ fn prepare_debian_control<F: FnMut(&str) -> std::result::Result<std::fs::File, s
 
     // debian/tests/control, preparation
     let test_is_marked_broken = |f: &str| config.package_test_is_broken(PackageKey::feature(f));
-    let test_is_broken = |f: &str| {
-        let getparents = |f: &str| features_with_deps.get(f).map(|(d, _)| d);
-        match get_transitive_val(&getparents, &test_is_marked_broken, f) {
+    let test_is_broken =
+        |provider: &str, f: &str| provided_test_is_broken(config, &features_with_deps, provider, f);
+    // set when generating the package that provides the default feature
+    let mut default_test_broken = None;
+    // None if the tests of the feature are not broken, otherwise what to do
+    // with them. "@" stands for all features, and so inherits from all of them.
+    let all_features = features_with_deps.keys().copied().collect::<Vec<_>>();
+    let broken_test_policy = |f: &str, broken: bool| {
+        if !broken {
+            return Ok(None);
+        }
+        let getparents = |f: &str| match f {
+            "@" => Some(&all_features),
+            _ => features_with_deps.get(f).map(|(d, _)| d),
+        };
+        let marked_policy = |f: &str| config.package_broken_test_policy(PackageKey::feature(f));
+        match get_transitive_val(&getparents, &marked_policy, f) {
             Err((k, vv)) => debcargo_bail!(
                 "{} {}: {}: {:?}",
-                "error trying to recursively determine test_is_broken for",
+                "error trying to recursively determine broken_test_policy for",
                 k,
                 "dependencies have inconsistent config values",
                 vv
             ),
-            Ok(v) => Ok(v.unwrap_or(false)),
+            Ok(v) => Ok(Some(v.unwrap_or_default())),
         }
     };
 
+    let links_fixme;
+    let script_fixme;
     let build_deps = {
-        let build_deps = ["debhelper (>= 12)", "dh-cargo (>= 25)"]
-            .iter()
-            .map(|x| x.to_string());
+        let dh_cargo_version = config.dh_cargo_version();
+        if !is_deb_version(dh_cargo_version) {
+            debcargo_bail!(
+                "dh_cargo_version is not a valid Debian version: {}",
+                dh_cargo_version
+            );
+        }
+        let build_deps = [
+            "debhelper (>= 12)".to_string(),
+            format!("dh-cargo (>= {})", dh_cargo_version),
+        ]
+        .into_iter();
         // note: please keep this in sync with build_order::dep_features
         let (default_features, default_deps) = transitive_deps(&features_with_deps, "default");
         //debcargo_info!("default_features: {:?}", default_features);
@@ -655,12 +1623,20 @@ fn prepare_debian_control<F: FnMut(&str) -> std::result::Result<std::fs::File, s
             PackageKey::feature("default"),
             &default_features,
         );
-        let rustc = rustc_dep(&crate_info.rust_version());
-        let build_deps_extra = ["cargo:native".into(), rustc, "libstd-rust-dev".into()]
-            .into_iter()
-            .chain(deb_deps(config, &default_deps)?)
-            .chain(extra_override_deps);
-        if !bins.is_empty() {
+        let rust_version = crate_info.rust_version();
+        let (links_deps, fixme) = links_build_deps(config, crate_info.links());
+        links_fixme = fixme;
+        script_fixme = build_script_fixme(config, crate_info.has_build_script());
+        let build_deps_extra = [
+            "cargo:native".into(),
+            rustc_dep(&rust_version),
+            libstd_rust_dep(&rust_version),
+        ]
+        .into_iter()
+        .chain(deb_build_deps(config, &default_deps)?)
+        .chain(links_deps)
+        .chain(extra_override_deps);
+        if has_bins {
             build_deps.chain(build_deps_extra).collect()
         } else {
             assert!(lib);
@@ -681,19 +1657,21 @@ fn prepare_debian_control<F: FnMut(&str) -> std::result::Result<std::fs::File, s
         lib,
         maintainer.to_string(),
         uploaders.iter().map(|s| s.to_string()).collect(),
+        config.vcs_base(),
         build_deps,
-        if requires_root.is_some() {
-            requires_root.as_ref().unwrap().to_string()
-        } else {
-            "no".to_string()
-        },
     )?;
 
     // If source overrides are present update related parts.
-    source.apply_overrides(config);
+    source.apply_overrides(config)?;
 
     let mut control = io::BufWriter::new(file("control")?);
     write!(control, "{}", source)?;
+    if let Some(fixme) = links_fixme {
+        writeln!(control, "\n{}", fixme)?;
+    }
+    if let Some(fixme) = script_fixme {
+        writeln!(control, "\n{}", fixme)?;
+    }
 
     // Summary and description generated from Cargo.toml
     let (crate_summary, crate_description) = crate_info.get_summary_description();
@@ -713,6 +1691,7 @@ fn prepare_debian_control<F: FnMut(&str) -> std::result::Result<std::fs::File, s
             .into_iter()
             .chain(features_with_deps.keys())
             .any(|f| test_is_marked_broken(f).unwrap_or(false));
+        let all_features_test_policy = broken_test_policy("@", all_features_test_broken)?;
         let all_features_test_depends = Some(&"@")
             .into_iter()
             .chain(features_with_deps.keys())
@@ -724,26 +1703,53 @@ fn prepare_debian_control<F: FnMut(&str) -> std::result::Result<std::fs::File, s
             })
             .flatten()
             .map(|s| s.to_string())
-            .chain(dev_depends.clone())
+            .chain(common_test_depends.clone())
             .collect::<Vec<_>>();
-        let mut testctl = io::BufWriter::new(file("tests/control")?);
-        write!(
-            testctl,
-            "{}",
-            PkgTest::new(
-                source.name(),
-                crate_name,
-                "@",
-                deb_upstream_version,
-                vec!["--all-features"],
-                &all_features_test_depends,
-                if all_features_test_broken {
-                    vec!["flaky"]
-                } else {
-                    vec![]
-                },
-            )?
+        let all_features_test_restrictions = test_restrictions(
+            config,
+            Some("@")
+                .into_iter()
+                .chain(features_with_deps.keys().copied()),
+            all_features_test_policy,
         )?;
+        let mut testctl: Box<dyn IoWrite> = if config.test {
+            Box::new(io::BufWriter::new(file("tests/control")?))
+        } else {
+            Box::new(io::sink())
+        };
+        // separates the stanzas, unless the ones before were all skipped
+        let mut testctl_sep = "";
+        if all_features_test_policy != Some(BrokenTestPolicy::Skip) {
+            write!(
+                testctl,
+                "{}",
+                PkgTest::new(
+                    source.name(),
+                    crate_name,
+                    "@",
+                    &test_version,
+                    vec!["--all-features"],
+                    &all_features_test_depends,
+                    all_features_test_restrictions,
+                )?
+            )?;
+            testctl_sep = "\n";
+        }
+
+        if let Some(cycle) = feature_cycle(&features_with_deps) {
+            if config.collapse_features {
+                debcargo_warn!(
+                    "Feature cycle {}; collapsing features anyway",
+                    cycle.join(" -> ")
+                );
+            } else {
+                debcargo_bail!(
+                    "Feature cycle {}, which cannot be represented by separate packages.\n\
+                     Set collapse_features = true or patch the features of the crate.",
+                    cycle.join(" -> ")
+                );
+            }
+        }
 
         // begin transforming dependencies
         let working_features_with_deps = features_with_deps.clone();
@@ -804,6 +1810,18 @@ fn prepare_debian_control<F: FnMut(&str) -> std::result::Result<std::fs::File, s
             }
             working_features_with_deps
         };
+        check_feature_package_names(
+            &match name_suffix {
+                None => base_pkgname.to_string(),
+                Some(suf) => format!("{}{}", base_pkgname, suf),
+            },
+            working_features_with_deps.keys().copied(),
+        )?;
+        let working_features_with_deps = if config.omit_empty_feature_packages {
+            fold_empty_features(working_features_with_deps)
+        } else {
+            working_features_with_deps
+        };
         log::trace!(
             "working_features_with_deps: {:?}",
             working_features_with_deps
@@ -886,17 +1904,10 @@ fn prepare_debian_control<F: FnMut(&str) -> std::result::Result<std::fs::File, s
         // end transforming dependencies
 
         log::trace!("provides: {:?}", provides);
-        let mut recommends = vec![];
-        let mut suggests = vec![];
-        for (&feature, features) in provides.iter() {
-            if feature.is_empty() {
-                continue;
-            } else if feature == "default" || features.contains(&"default") {
-                recommends.push(feature);
-            } else {
-                suggests.push(feature);
-            }
-        }
+        let mut obsoleted =
+            obsoleted_feature_packages(previous_packages, deb_info.package_name(), &provides);
+        let (recommends, suggests, optional_dep_features) =
+            classify_feature_packages(&features_with_deps, &provides);
 
         for (feature, (f_deps, o_deps)) in reduced_features_with_deps.into_iter() {
             let pk = PackageKey::feature(feature);
@@ -960,7 +1971,16 @@ fn prepare_debian_control<F: FnMut(&str) -> std::result::Result<std::fs::File, s
                     Some(feature)
                 },
                 f_deps,
-                deb_deps(config, &o_deps)?,
+                if feature.is_empty() && config.data_package {
+                    let mut deps = vec![format!(
+                        "{} (= ${{source:Version}})",
+                        deb_data_name(deb_info.package_name())
+                    )];
+                    deps.extend(deb_deps(config, &o_deps)?);
+                    deps
+                } else {
+                    deb_deps(config, &o_deps)?
+                },
                 f_provides.clone(),
                 if feature.is_empty() {
                     recommends.clone()
@@ -973,23 +1993,45 @@ fn prepare_debian_control<F: FnMut(&str) -> std::result::Result<std::fs::File, s
                     vec![]
                 },
             )?;
+            // proc-macros are built for and run on the build architecture, so
+            // the same-arch requirement of Multi-Arch: same does not apply
+            if crate_info.is_proc_macro() {
+                package.set_multi_arch("allowed");
+            }
+            if optional_dep_features.contains(feature) {
+                package.enhance(&deb_name(deb_info.package_name()));
+            }
+            for old in obsoleted.remove(feature).into_iter().flatten() {
+                package.break_and_replace(&old, &deb_info.deb_epoch_upstream_version());
+            }
             // If any overrides present for this package it will be taken care.
-            package.apply_overrides(config, pk, f_provides);
+            package.apply_overrides(config, pk, f_provides)?;
 
             match package.summary_check_len() {
-                Err(()) => writeln!(
+                Err(len) => writeln!(
                     control,
                     concat!(
                         "\n",
-                        "# FIXME (packages.\"(name)\".section) debcargo ",
-                        "auto-generated summary for {} is very long, consider overriding"
+                        "# FIXME (packages.\"(name)\".summary) debcargo ",
+                        "auto-generated summary for {} is {} characters long, ",
+                        "consider overriding it to at most {}"
                     ),
                     package.name(),
+                    len,
+                    control::MAX_SYNOPSIS_LEN,
                 )?,
                 Ok(()) => {}
             };
 
             write!(control, "\n{}", package)?;
+            let mut package_test_broken = false;
+            for f in &crate_features {
+                package_test_broken |= test_is_broken(feature, f)?;
+            }
+            if crate_features.contains(&"default") {
+                default_test_broken = Some(test_is_broken(feature, "default")?);
+            }
+            packages_summary.push(package_summary(&package, package_test_broken));
 
             // Override pointless overzealous warnings from lintian
             if !feature.is_empty() {
@@ -1004,6 +2046,10 @@ fn prepare_debian_control<F: FnMut(&str) -> std::result::Result<std::fs::File, s
 
             // Generate tests for all features in this package
             for f in crate_features {
+                let test_policy = broken_test_policy(f, test_is_broken(feature, f)?)?;
+                if test_policy == Some(BrokenTestPolicy::Skip) {
+                    continue;
+                }
                 let (feature_deps, _) = transitive_deps(&features_with_deps, f);
 
                 // args
@@ -1020,6 +2066,11 @@ fn prepare_debian_control<F: FnMut(&str) -> std::result::Result<std::fs::File, s
                 }
 
                 // deps
+                let restrictions = test_restrictions(
+                    config,
+                    Some(f).into_iter().chain(feature_deps.iter().copied()),
+                    test_policy,
+                )?;
                 let test_depends = Some(f)
                     .into_iter()
                     .chain(feature_deps)
@@ -1031,39 +2082,94 @@ fn prepare_debian_control<F: FnMut(&str) -> std::result::Result<std::fs::File, s
                     })
                     .flatten()
                     .map(|s| s.to_string())
-                    .chain(dev_depends.clone())
+                    .chain(common_test_depends.clone())
                     .collect::<Vec<_>>();
                 let pkgtest = PkgTest::new(
                     package.name(),
                     crate_name,
                     f,
-                    deb_upstream_version,
+                    &test_version,
                     args,
                     &test_depends,
-                    if test_is_broken(f)? {
-                        vec!["flaky"]
-                    } else {
-                        vec![]
-                    },
+                    restrictions,
                 )?;
-                write!(testctl, "\n{}", pkgtest)?;
+                write!(testctl, "{}{}", testctl_sep, pkgtest)?;
+                testctl_sep = "\n";
             }
         }
         assert!(provides.is_empty());
         // reduced_features_with_deps consumed by into_iter, no longer usable
+
+        if config.data_package {
+            let data_pkg = Package::new_data(
+                base_pkgname,
+                name_suffix,
+                Description {
+                    prefix: summary_prefix.clone(),
+                    suffix: " - data files".to_string(),
+                },
+                Description {
+                    prefix: description_prefix.clone(),
+                    suffix: format!(
+                        "This package contains the architecture-independent data \
+                         files of the Rust {} crate, shared by its -dev packages.",
+                        crate_name
+                    ),
+                },
+            );
+            write!(control, "\n{}", data_pkg)?;
+            packages_summary.push(package_summary(&data_pkg, false));
+        }
     }
 
-    if !bins.is_empty() {
+    let all_bins: Vec<&str> = bins.iter().chain(&examples).copied().collect();
+    let bin_pkgs = if config.split_bins && all_bins.len() > 1 {
+        split_bin_packages(&all_bins)
+            .into_iter()
+            .map(|(basename, bin)| {
+                let summary_suffix = format!(" - {}", bin);
+                let kind = if examples.contains(&bin) {
+                    "example program"
+                } else {
+                    "binary"
+                };
+                let description_suffix = format!(
+                    "This package contains the {} {} built from the Rust crate\n\"{}\".",
+                    bin, kind, crate_name
+                );
+                (basename, summary_suffix, description_suffix)
+            })
+            .collect()
+    } else if has_bins {
         // adding " - binaries" is a bit redundant for users, so just leave as-is
         let summary_suffix = "".to_string();
         let description_suffix = format!(
             "This package contains the following binaries built from the Rust crate\n\"{}\":\n - {}",
             crate_name,
-            bins.join("\n - ")
+            bins.iter()
+                .map(|b| b.to_string())
+                .chain(examples.iter().map(|e| format!("{} (example)", e)))
+                .collect::<Vec<_>>()
+                .join("\n - ")
         );
+        vec![(bin_name.to_string(), summary_suffix, description_suffix)]
+    } else {
+        vec![]
+    };
 
+    // split packages are named after their binary, otherwise after bin_name
+    let name_key = if config.split_bins && all_bins.len() > 1 {
+        "split_bins"
+    } else {
+        "bin_name"
+    };
+    for (basename, summary_suffix, description_suffix) in bin_pkgs {
+        check_bin_package_name(
+            &format!("{}{}", basename, name_suffix.unwrap_or_default()),
+            name_key,
+        )?;
         let mut bin_pkg = Package::new_bin(
-            bin_name,
+            &basename,
             name_suffix,
             // if not-a-lib then Source section is already FIXME
             if !lib {
@@ -1072,21 +2178,482 @@ fn prepare_debian_control<F: FnMut(&str) -> std::result::Result<std::fs::File, s
                 Some("FIXME-(packages.\"(name)\".section)")
             },
             Description {
-                prefix: summary_prefix,
+                prefix: summary_prefix.clone(),
                 suffix: summary_suffix,
             },
             Description {
-                prefix: description_prefix,
+                prefix: description_prefix.clone(),
                 suffix: description_suffix,
             },
         );
 
         // Binary package overrides.
-        bin_pkg.apply_overrides(config, PackageKey::Bin, vec![]);
+        bin_pkg.apply_overrides(config, PackageKey::Bin, vec![])?;
         write!(control, "\n{}", bin_pkg)?;
+        packages_summary.push(package_summary(&bin_pkg, false));
+    }
+
+    Ok((
+        source,
+        !dev_depends.is_empty(),
+        broken_test_policy(
+            "default",
+            match default_test_broken {
+                Some(broken) => broken,
+                None => test_is_broken("default", "default")?,
+            },
+        )?,
+    ))
+}
+
+/// The binary targets and the shipped example targets of the crate, or none
+/// if binary packages are disabled for a library crate.
+fn binary_targets<'a>(crate_info: &'a CrateInfo, config: &Config) -> (Vec<&'a str>, Vec<&'a str>) {
+    if crate_info.is_lib() && !config.build_bin_package() {
+        return (vec![], vec![]);
+    }
+    (
+        crate_info.get_binary_targets(),
+        crate_info.get_example_targets(&config.ship_examples),
+    )
+}
+
+/// The crate name as used in Debian package names. Crate names that start
+/// with a digit or are a single character are fine, since the package names
+/// always have a prefix, but a leading "_" or "-" would result in names like
+/// rust--foo, and dropping it could clash with the crate without it.
+fn base_package_name(crate_name: &str) -> Result<String> {
+    let name = base_deb_name(crate_name);
+    if !name.starts_with(|c: char| c.is_ascii_alphanumeric()) {
+        debcargo_bail!(
+            "Crate name \"{}\" does not start with a letter or digit, so it cannot be \
+             mapped to a Debian package name",
+            crate_name
+        );
+    }
+    let source_name = control::dsc_name(&name);
+    if !control::is_deb_package_name(&source_name) {
+        debcargo_bail!(
+            "Crate name \"{}\" results in the invalid Debian package name {}",
+            crate_name,
+            source_name
+        );
+    }
+    Ok(name)
+}
+
+/// Check that a binary package is validly named; unlike the library packages,
+/// these have no prefix, so a crate or binary named e.g. "x" is too short.
+/// `name_key` is the config setting that lets the user pick another name.
+fn check_bin_package_name(name: &str, name_key: &str) -> Result<()> {
+    if !control::is_deb_package_name(name) {
+        debcargo_bail!(
+            "\"{}\" is not a valid Debian package name, change {} in debcargo.toml to use another one",
+            name,
+            name_key
+        );
+    }
+    Ok(())
+}
+
+fn bin_package_name<'a>(config: &'a Config, deb_info: &'a DebInfo) -> &'a str {
+    if config.bin_name.eq(&Config::default().bin_name) {
+        deb_info.base_package_name()
+    } else {
+        config.bin_name.as_str()
+    }
+}
+
+/// With split_bins, the base name of the binary package for each binary
+/// target, in the order that the packages appear in debian/control.
+fn split_bin_packages<'a>(bins: &[&'a str]) -> Vec<(String, &'a str)> {
+    bins.iter()
+        .map(|bin| (bin.to_lowercase().replace('_', "-"), *bin))
+        .collect()
+}
+
+fn package_summary(package: &Package, test_is_broken: bool) -> serde_json::Value {
+    serde_json::json!({
+        "name": package.name(),
+        "provides": package.provides(),
+        "test_is_broken": test_is_broken,
+    })
+}
+
+/// Build-Depends for the native library that a crate links to, as set by
+/// the `links` key in its Cargo.toml. If the config does not map it to any
+/// Debian packages, warn and also return a FIXME comment for debian/control.
+fn links_build_deps(config: &Config, links: Option<&str>) -> (Vec<String>, Option<String>) {
+    let links = match links {
+        Some(links) => links,
+        None => return (vec![], None),
+    };
+    match config.links_build_depends(links) {
+        Some(deps) => (deps.clone(), None),
+        None => {
+            debcargo_warn!(
+                "Crate links to native library \"{}\"; set links_build_depends.{} \
+                 to the Debian packages needed to build against it.",
+                links,
+                links
+            );
+            let fixme = format!(
+                "# FIXME (links_build_depends.{}) crate links to native library \"{}\", \
+                 add its -dev package (e.g. lib{}-dev) to Build-Depends",
+                links, links, links
+            );
+            (vec![], Some(fixme))
+        }
+    }
+}
+
+/// debian/source/options, or an empty string if there are none.
+fn source_options(config: &Config, orig_compression: Compression) -> Result<String> {
+    let mut options = String::new();
+    // keep the debian tarball in the same format as the orig tarball
+    if orig_compression != Compression::Gzip {
+        writeln!(
+            options,
+            "compression = \"{}\"",
+            orig_compression.dpkg_name()
+        )?;
+    }
+    if let Some(level) = config.source_options_compression_level {
+        if !(1..=9).contains(&level) {
+            debcargo_bail!(
+                "source_options_compression_level must be from 1 to 9, not {}",
+                level
+            );
+        }
+        writeln!(options, "compression-level = {}", level)?;
+    }
+    for regex in config.source_options_extend_diff_ignore.iter().flatten() {
+        writeln!(options, "extend-diff-ignore = \"{}\"", regex)?;
+    }
+    Ok(options)
+}
+
+/// debian/gbp.conf for the [gbp] section of the config.
+fn gbp_conf_contents(gbp: &GbpConfig) -> String {
+    format!(
+        "[DEFAULT]\ndebian-branch = {}\nupstream-tag = {}\npristine-tar = {}\n",
+        gbp.debian_branch(),
+        gbp.upstream_tag(),
+        if gbp.pristine_tar() { "True" } else { "False" }
+    )
+}
+
+/// Restrictions of the autopkgtest stanza for the given features, besides
+/// the ones that are always set: flaky if the tests are broken and allowed to
+/// fail, and any test_restrictions of the features.
+fn test_restrictions<'a>(
+    config: &'a Config,
+    features: impl Iterator<Item = &'a str>,
+    test_policy: Option<BrokenTestPolicy>,
+) -> Result<Vec<&'a str>> {
+    let mut restrictions = vec![];
+    if test_policy == Some(BrokenTestPolicy::Flaky) {
+        restrictions.push("flaky");
+    }
+    for f in features {
+        for r in config
+            .package_test_restrictions(PackageKey::feature(f))
+            .into_iter()
+            .flatten()
+        {
+            if !control::TEST_RESTRICTIONS.contains(&r.as_str()) {
+                // autopkgtest skips tests with restrictions it doesn't know
+                debcargo_bail!(
+                    "Unknown autopkgtest restriction \"{}\" in test_restrictions of {}",
+                    r,
+                    f
+                );
+            }
+            if !restrictions.contains(&r.as_str())
+                && !control::DEFAULT_TEST_RESTRICTIONS.contains(&r.as_str())
+            {
+                restrictions.push(r);
+            }
+        }
+    }
+    Ok(restrictions)
+}
+
+/// Whether the tests of feature `f` are broken, as set for it or else as
+/// inherited from the features it depends on.
+fn feature_test_is_broken(
+    config: &Config,
+    features_with_deps: &CrateDepInfo,
+    f: &str,
+) -> Result<bool> {
+    let test_is_marked_broken = |f: &str| config.package_test_is_broken(PackageKey::feature(f));
+    let getparents = |f: &str| features_with_deps.get(f).map(|(d, _)| d);
+    match get_transitive_val(&getparents, &test_is_marked_broken, f) {
+        Err((k, vv)) => debcargo_bail!(
+            "{} {}: {}: {:?}",
+            "error trying to recursively determine test_is_broken for",
+            k,
+            "dependencies have inconsistent config values",
+            vv
+        ),
+        Ok(v) => Ok(v.unwrap_or(false)),
+    }
+}
+
+/// Whether the tests of feature `f` are broken, when it is provided by the
+/// package of feature `provider`. Unless set for `f` itself, they are broken
+/// if they are for `provider`, since it pulls in everything `f` depends on;
+/// otherwise they are inherited as usual.
+fn provided_test_is_broken(
+    config: &Config,
+    features_with_deps: &CrateDepInfo,
+    provider: &str,
+    f: &str,
+) -> Result<bool> {
+    if let Some(broken) = config.package_test_is_broken(PackageKey::feature(f)) {
+        return Ok(broken);
+    }
+    if provider != f && feature_test_is_broken(config, features_with_deps, provider)? {
+        return Ok(true);
+    }
+    feature_test_is_broken(config, features_with_deps, f)
+}
+
+/// Depends of every autopkgtest, besides the test_depends of its features:
+/// the dev-dependencies, and @builddeps@ and @recommends@ if enabled.
+fn common_test_depends(config: &Config, dev_depends: &[String]) -> Vec<String> {
+    let mut depends = dev_depends.to_vec();
+    if config.test_depends_builddeps {
+        depends.push("@builddeps@".to_string());
+    }
+    if config.test_depends_recommends {
+        depends.push("@recommends@".to_string());
+    }
+    depends
+}
+
+/// The override_dh_auto_test recipe, which runs cargo with test_args, unless
+/// the tests are broken and skipped, and ignores failures if they are broken
+/// and flaky.
+fn dh_auto_test_command(config: &Config, test_policy: Option<BrokenTestPolicy>) -> String {
+    if test_policy == Some(BrokenTestPolicy::Skip) {
+        return "# The tests are skipped with broken_test_policy = \"skip\" in debcargo.toml\n"
+            .to_string();
+    }
+    format!(
+        "\tdh_auto_test -- {}{}\n",
+        config.test_args().join(" "),
+        if test_policy == Some(BrokenTestPolicy::Flaky) {
+            " || true"
+        } else {
+            ""
+        }
+    )
+}
+
+/// Feature packages of the previously packaged version, from previous_features
+/// or else from the debian/control of an earlier run, together with the ones
+/// that debian/control already Breaks, so that they keep being broken.
+fn previous_feature_packages(
+    config: &Config,
+    pkgbase: &str,
+    existing_control: Option<&str>,
+) -> Vec<String> {
+    let mut packages = match (config.previous_features.as_ref(), existing_control) {
+        (Some(features), _) => features
+            .iter()
+            .map(|f| control::deb_feature_name(pkgbase, f))
+            .collect(),
+        (None, Some(control)) => control::feature_packages_in_control(control, pkgbase),
+        (None, None) => vec![],
+    };
+    for package in existing_control
+        .map(|c| control::broken_feature_packages_in_control(c, pkgbase))
+        .into_iter()
+        .flatten()
+    {
+        if !packages.contains(&package) {
+            packages.push(package);
+        }
+    }
+    packages
+}
+
+/// The debian/control of an earlier run, e.g. when regenerating a package that
+/// is already in the archive.
+fn existing_control(output_dir: &Path) -> Result<Option<String>> {
+    let path = output_dir.join("debian").join("control");
+    if !path.exists() {
+        return Ok(None);
+    }
+    Ok(Some(fs::read_to_string(path)?))
+}
+
+/// Packages of an existing debian/control that are neither generated nor
+/// provided by any generated package, given the packages summary.
+fn dropped_packages<'a>(
+    existing_packages: &'a [String],
+    packages_summary: &[serde_json::Value],
+) -> Vec<&'a str> {
+    let mut generated = HashSet::new();
+    for package in packages_summary {
+        generated.extend(package["name"].as_str());
+        for provides in package["provides"].as_array().into_iter().flatten() {
+            // strip the version constraint
+            generated.extend(provides.as_str().and_then(|p| p.split_whitespace().next()));
+        }
+    }
+    existing_packages
+        .iter()
+        .map(String::as_str)
+        .filter(|p| !generated.contains(p))
+        .collect()
+}
+
+/// Whether the only effect of `feature` is to enable one optional
+/// dependency, like the implicit features that cargo creates for those.
+fn is_optional_dep_feature(features_with_deps: &CrateDepInfo, feature: &str) -> bool {
+    match features_with_deps.get(feature) {
+        Some((ff, dd)) => {
+            ff.iter().all(|f| f.is_empty())
+                && !dd.is_empty()
+                && dd
+                    .iter()
+                    .all(|d| d.is_optional() && d.name_in_toml() == dd[0].name_in_toml())
+        }
+        None => false,
+    }
+}
+
+/// Sort the feature packages into those that the base package should
+/// Recommend and those it should Suggest. Packages that only pull in an
+/// optional dependency are suggested after the others, and are also
+/// returned separately so that they can Enhance the base package.
+fn classify_feature_packages<'a>(
+    features_with_deps: &CrateDepInfo,
+    provides: &BTreeMap<&'a str, Vec<&'a str>>,
+) -> (Vec<&'a str>, Vec<&'a str>, BTreeSet<&'a str>) {
+    let mut recommends = vec![];
+    let mut suggests = vec![];
+    let mut optional_dep_features = BTreeSet::new();
+    for (&feature, features) in provides.iter() {
+        if feature.is_empty() {
+            continue;
+        } else if feature == "default" || features.contains(&"default") {
+            recommends.push(feature);
+        } else if Some(&feature)
+            .into_iter()
+            .chain(features)
+            .all(|f| is_optional_dep_feature(features_with_deps, f))
+        {
+            optional_dep_features.insert(feature);
+        } else {
+            suggests.push(feature);
+        }
+    }
+    suggests.extend(optional_dep_features.iter().copied());
+    (recommends, suggests, optional_dep_features)
+}
+
+/// Previous feature packages that are not built any more, keyed by the
+/// feature whose package takes over from them: the one that now provides the
+/// feature, or the base package if the feature was removed.
+fn obsoleted_feature_packages(
+    previous_packages: &[String],
+    pkgbase: &str,
+    provides: &BTreeMap<&'static str, Vec<&'static str>>,
+) -> BTreeMap<&'static str, Vec<String>> {
+    let is_package = |f: &str, package: &str| control::deb_feature_name(pkgbase, f) == package;
+    let mut obsoleted: BTreeMap<&str, Vec<String>> = BTreeMap::new();
+    for old in previous_packages {
+        if provides.keys().any(|f| !f.is_empty() && is_package(f, old)) {
+            continue;
+        }
+        let provider = provides
+            .iter()
+            .find(|(_, ff)| ff.iter().any(|f| is_package(f, old)))
+            .map_or("", |(&f, _)| f);
+        obsoleted.entry(provider).or_default().push(old.clone());
+    }
+    obsoleted
+}
+
+/// A FIXME comment for debian/control if the crate has a build script that
+/// was not reviewed yet, since build scripts often need system libraries or
+/// tools that are missing from Build-Depends.
+fn build_script_fixme(config: &Config, has_build_script: bool) -> Option<String> {
+    if !has_build_script || config.build_script_reviewed {
+        return None;
+    }
+    debcargo_warn!(
+        "Crate has a build script; check it for system libraries and tools that \
+         need to be added to Build-Depends, then set build_script_reviewed = true."
+    );
+    Some(
+        "# FIXME (build_script_reviewed) crate has a build script, check it for \
+         system libraries and tools that need to be added to Build-Depends"
+            .to_string(),
+    )
+}
+
+/// Make every feature that does not pull in any dependencies beyond those of
+/// the bare library depend on the bare library only, so that it is provided
+/// by the base -dev package rather than getting its own package.
+fn fold_empty_features(mut features_with_deps: CrateDepInfo) -> CrateDepInfo {
+    let base_deps = &features_with_deps[""].1;
+    let empty = features_with_deps
+        .keys()
+        .filter(|f| !f.is_empty())
+        .filter(|f| {
+            let (_, deps) = transitive_deps(&features_with_deps, f);
+            let own_deps = &features_with_deps[*f].1;
+            own_deps.iter().chain(&deps).all(|d| base_deps.contains(d))
+        })
+        .copied()
+        .collect::<Vec<_>>();
+    for f in empty {
+        features_with_deps.insert(f, (vec![""], vec![]));
+    }
+    features_with_deps
+}
+
+/// A cycle in the feature dependencies, as the features along it with the
+/// first one repeated at the end, or None if there are no cycles.
+fn feature_cycle(features_with_deps: &CrateDepInfo) -> Option<Vec<&'static str>> {
+    fn visit(
+        features_with_deps: &CrateDepInfo,
+        f: &'static str,
+        path: &mut Vec<&'static str>,
+        done: &mut HashSet<&'static str>,
+    ) -> Option<Vec<&'static str>> {
+        if let Some(i) = path.iter().position(|&g| g == f) {
+            let mut cycle = path[i..].to_vec();
+            cycle.push(f);
+            return Some(cycle);
+        }
+        if done.contains(f) {
+            return None;
+        }
+        path.push(f);
+        for &g in features_with_deps
+            .get(f)
+            .map(|x| &x.0)
+            .into_iter()
+            .flatten()
+        {
+            if let Some(cycle) = visit(features_with_deps, g, path, done) {
+                return Some(cycle);
+            }
+        }
+        path.pop();
+        done.insert(f);
+        None
     }
 
-    Ok((source, !dev_depends.is_empty(), test_is_broken("default")?))
+    let mut done = HashSet::new();
+    features_with_deps
+        .keys()
+        .find_map(|&f| visit(features_with_deps, f, &mut vec![], &mut done))
 }
 
 fn collapse_features(
@@ -1114,8 +2681,9 @@ fn collapse_features(
 
 /// Calculate Provides: in an attempt to reduce the number of binaries.
 ///
-/// The algorithm is very simple and incomplete. e.g. it does not, yet
-/// simplify things like:
+/// A feature without external dependencies is folded into another feature
+/// that pulls in all of its dependencies. Besides the simple case of a single
+/// feature dependency, this also simplifies things like:
 ///   f1 depends on f2, f3
 ///   f2 depends on f4
 ///   f3 depends on f4
@@ -1140,7 +2708,8 @@ fn reduce_provides(
         }
     }
 
-    // Calculate provides by following 0- or 1-length dependency lists.
+    // Calculate provides by following dependency lists that reduce to a
+    // single feature.
     let mut provides = BTreeMap::new();
     let mut provided = Vec::new();
     for (&f, (ref ff, ref dd)) in features_with_deps.iter() {
@@ -1152,6 +2721,9 @@ fn reduce_provides(
         let k = if ff.len() == 1 {
             // if A depends on a single feature B, then B provides A.
             ff[0]
+        } else if let Some(k) = single_provider(&features_with_deps, f) {
+            // if everything A depends on is pulled in by B, then B provides A.
+            k
         } else {
             continue;
         };
@@ -1184,38 +2756,48 @@ fn reduce_provides(
     (provides, features_with_deps)
 }
 
+/// Find the transitive feature dependency of `f` that by itself pulls in all
+/// the external dependencies of `f`, if there is one.
+fn single_provider(features_with_deps: &CrateDepInfo, f: &'static str) -> Option<&'static str> {
+    let succ = |k: &&'static str| features_with_deps.get(k).map(|x| &x.0);
+    let has_deps = |k: &&'static str| {
+        k.is_empty() || features_with_deps.get(k).map_or(false, |x| !x.1.is_empty())
+    };
+    let candidates = traverse_depth(&succ, f)
+        .into_iter()
+        .filter(has_deps)
+        .collect::<BTreeSet<_>>();
+    candidates.iter().copied().find(|&g| {
+        let reachable = traverse_depth(&succ, g);
+        candidates.iter().all(|h| *h == g || reachable.contains(h))
+    })
+}
+
 fn rustc_dep(min_ver: &Option<&str>) -> String {
     if let Some(min_ver) = min_ver {
-        format!("rustc:native (>= {})", min_ver)
+        format!("rustc:native (>= {})", min_ver.trim())
     } else {
         "rustc:native".into()
     }
 }
 
-#[cfg(test)]
-mod test {
-    use super::rustc_dep;
-
-    #[test]
-    fn rustc_dep_includes_minver() {
-        assert_eq!("rustc:native (>= 1.65)", rustc_dep(&Some(&"1.65")));
-    }
-
-    #[test]
-    fn rustc_dep_excludes_minver() {
-        assert_eq!("rustc:native", rustc_dep(&None));
+fn libstd_rust_dep(min_ver: &Option<&str>) -> String {
+    if let Some(min_ver) = min_ver {
+        format!("libstd-rust-dev (>= {})", min_ver.trim())
+    } else {
+        "libstd-rust-dev".into()
     }
 }
 
-fn changelog_or_new(tempdir: &Path) -> Result<(fs::File, String)> {
-    let mut changelog = fs::OpenOptions::new()
-        .read(true)
-        .write(true)
-        .create(true)
-        .open(tempdir.join("changelog"))?;
-    let mut changelog_data = String::new();
-    changelog.read_to_string(&mut changelog_data)?;
-    Ok((changelog, changelog_data))
+#[cfg(test)]
+mod tests;
+
+fn changelog_or_new(tempdir: &Path) -> Result<String> {
+    let path = tempdir.join("changelog");
+    if !path.exists() {
+        return Ok(String::new());
+    }
+    Ok(fs::read_to_string(path)?)
 }
 
 fn changelog_first_last(tempdir: &Path) -> Result<(i32, i32)> {
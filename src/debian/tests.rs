@@ -0,0 +1,1533 @@
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression as GzCompression;
+use tar::{Archive, Builder, Header};
+
+use cargo::core::{Dependency, SourceId};
+
+use super::{
+    apply_overlay_and_patches, base_package_name, build_script_fixme, check_bin_package_name,
+    clean_files, control, dh_auto_test_command, diff_debian_dirs, dropped_packages,
+    existing_control, feature_test_is_broken, fold_empty_features, forge_repository,
+    gbp_conf_contents, generate_debian_files, git_watch, libstd_rust_dep, links_build_deps,
+    obsoleted_feature_packages, pack_orig_dir, package_summary, previous_feature_packages,
+    provided_test_is_broken, reduce_provides, repack_orig_tarball, rustc_dep, select_debian_files,
+    source_options, split_bin_packages, test_restrictions, upstream_metadata, DebInfo, DebianFiles,
+    REPACK_SUFFIX,
+};
+use crate::config::{BrokenTestPolicy, Compression, Config};
+use crate::crates::{decompressed_tarball, transitive_deps, CrateDepInfo, CrateInfo};
+
+/// Parse a debcargo.toml fixture, shared with the submodule tests.
+pub(super) fn parse_config(toml: &str) -> Config {
+    toml::from_str(toml).unwrap()
+}
+
+fn dep(name: &str) -> Dependency {
+    let source_id = SourceId::for_path(Path::new("/nonexistent")).unwrap();
+    Dependency::parse(name, Some("1"), source_id).unwrap()
+}
+
+fn features(input: Vec<(&'static str, Vec<&'static str>, Vec<&str>)>) -> CrateDepInfo {
+    input
+        .into_iter()
+        .map(|(f, ff, dd)| (f, (ff, dd.into_iter().map(dep).collect())))
+        .collect()
+}
+
+#[test]
+fn reduce_provides_diamond() {
+    let (provides, reduced) = reduce_provides(features(vec![
+        ("", vec![], vec!["a"]),
+        ("f1", vec!["f2", "f3"], vec![]),
+        ("f2", vec!["f4"], vec![]),
+        ("f3", vec!["f4"], vec![]),
+        ("f4", vec![""], vec!["b"]),
+    ]));
+    assert_eq!(reduced.keys().copied().collect::<Vec<_>>(), vec!["", "f4"]);
+    assert_eq!(provides["f4"], vec!["f1", "f2", "f3"]);
+    assert!(provides[""].is_empty());
+}
+
+#[test]
+fn reduce_provides_chain() {
+    let (provides, reduced) = reduce_provides(features(vec![
+        ("", vec![], vec!["a"]),
+        ("f1", vec!["f2"], vec![]),
+        ("f2", vec!["f3"], vec![]),
+        ("f3", vec![""], vec!["b"]),
+    ]));
+    assert_eq!(reduced.keys().copied().collect::<Vec<_>>(), vec!["", "f3"]);
+    assert_eq!(provides["f3"], vec!["f1", "f2"]);
+}
+
+#[test]
+fn reduce_provides_keeps_external_deps() {
+    let (provides, reduced) = reduce_provides(features(vec![
+        ("", vec![], vec!["a"]),
+        ("f1", vec!["f2", "f3"], vec!["c"]),
+        ("f2", vec!["f4"], vec![]),
+        ("f3", vec!["f4"], vec![]),
+        ("f4", vec![""], vec!["b"]),
+    ]));
+    assert_eq!(
+        reduced.keys().copied().collect::<Vec<_>>(),
+        vec!["", "f1", "f4"]
+    );
+    assert!(provides["f1"].is_empty());
+    assert_eq!(provides["f4"], vec!["f2", "f3"]);
+}
+
+#[test]
+fn default_test_broken_via_provider() {
+    let input = features(vec![
+        ("", vec![], vec!["a"]),
+        ("alloc", vec![""], vec![]),
+        ("std", vec!["alloc"], vec!["b"]),
+        ("default", vec!["std", "alloc"], vec![]),
+    ]);
+    let (provides, _) = reduce_provides(input.clone());
+    assert_eq!(provides["std"], vec!["default"]);
+
+    let config = parse_config(
+        "[packages.\"lib+std\"]\ntest_is_broken = true\n\
+             [packages.\"lib+alloc\"]\ntest_is_broken = false\n",
+    );
+    // default inherits inconsistent values from std and alloc
+    assert!(feature_test_is_broken(&config, &input, "default").is_err());
+    // but its tests are in the package of std, which is broken
+    let default_test_broken = provided_test_is_broken(&config, &input, "std", "default").unwrap();
+    assert!(default_test_broken);
+    assert!(!provided_test_is_broken(&config, &input, "", "alloc").unwrap());
+    assert_eq!(
+        dh_auto_test_command(&config, default_test_broken.then(BrokenTestPolicy::default)),
+        "\tdh_auto_test -- test --all || true\n"
+    );
+
+    // setting it on default itself still takes precedence
+    let config = parse_config(
+        "[packages.\"lib+std\"]\ntest_is_broken = true\n\
+             [packages.\"lib+default\"]\ntest_is_broken = false\n",
+    );
+    assert!(!provided_test_is_broken(&config, &input, "std", "default").unwrap());
+}
+
+#[test]
+fn feature_cycles() {
+    assert_eq!(
+        super::feature_cycle(&features(vec![
+            ("", vec![], vec!["a"]),
+            ("default", vec!["std"], vec![]),
+            ("std", vec![""], vec![]),
+        ])),
+        None
+    );
+    assert_eq!(
+        super::feature_cycle(&features(vec![
+            ("", vec![], vec!["a"]),
+            ("default", vec!["x"], vec![]),
+            ("x", vec!["y"], vec![]),
+            ("y", vec!["z", ""], vec!["b"]),
+            ("z", vec!["x"], vec![]),
+        ])),
+        Some(vec!["x", "y", "z", "x"])
+    );
+}
+
+#[test]
+fn base_package_provides_folded_features() {
+    let input = features(vec![
+        ("", vec![], vec!["a"]),
+        ("default", vec!["std"], vec![]),
+        ("std", vec![""], vec![]),
+        ("alloc", vec![""], vec![]),
+        ("nightly", vec!["alloc"], vec![]),
+    ]);
+    let version = semver::Version::parse("1.2.3").unwrap();
+    let description = || super::Description {
+        prefix: "".into(),
+        suffix: "".into(),
+    };
+    for (mut provides, _) in [
+        reduce_provides(input.clone()),
+        super::collapse_features(input.clone()),
+    ] {
+        let f_provides = provides.remove("").unwrap();
+        assert_eq!(f_provides.len(), 4);
+        let package = super::Package::new(
+            "foo",
+            None,
+            &version,
+            description(),
+            description(),
+            None,
+            vec![],
+            vec![],
+            f_provides,
+            vec![],
+            vec![],
+        )
+        .unwrap()
+        .to_string();
+        for f in &["alloc", "default", "nightly", "std"] {
+            for suffix in &["", "-1", "-1.2", "-1.2.3"] {
+                assert!(
+                    package.contains(&format!(
+                        " librust-foo{}+{}-dev (= ${{binary:Version}})",
+                        suffix, f
+                    )),
+                    "{} not provided in\n{}",
+                    f,
+                    package
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn package_summary_json() {
+    let package = super::Package::new_bin(
+        "foo",
+        None,
+        None,
+        super::Description {
+            prefix: "Summary".into(),
+            suffix: "".into(),
+        },
+        super::Description {
+            prefix: "".into(),
+            suffix: "".into(),
+        },
+    );
+    assert_eq!(
+        package_summary(&package, false),
+        serde_json::json!({
+            "name": "foo",
+            "provides": ["${cargo:Provides}"],
+            "test_is_broken": false,
+        })
+    );
+}
+
+#[test]
+fn links_build_deps_from_config() {
+    let mut config = Config::default();
+    assert_eq!(links_build_deps(&config, None), (vec![], None));
+
+    let (deps, fixme) = links_build_deps(&config, Some("git2"));
+    assert!(deps.is_empty());
+    assert!(fixme
+        .unwrap()
+        .starts_with("# FIXME (links_build_depends.git2)"));
+
+    config.links_build_depends = Some(
+        [("git2".to_string(), vec!["libgit2-dev".to_string()])]
+            .into_iter()
+            .collect(),
+    );
+    assert_eq!(
+        links_build_deps(&config, Some("git2")),
+        (vec!["libgit2-dev".to_string()], None)
+    );
+}
+
+#[test]
+fn build_script_fixme_until_reviewed() {
+    let mut config = Config::default();
+    assert_eq!(build_script_fixme(&config, false), None);
+    assert!(build_script_fixme(&config, true)
+        .unwrap()
+        .starts_with("# FIXME (build_script_reviewed)"));
+
+    config.build_script_reviewed = true;
+    assert_eq!(build_script_fixme(&config, true), None);
+}
+
+#[test]
+fn test_restrictions_from_config() {
+    let config = parse_config(
+        "[packages.\"lib+a\"]\ntest_restrictions = [\"needs-root\", \"allow-stderr\"]\n\
+             [packages.\"lib+b\"]\ntest_restrictions = [\"needs-root\", \"superficial\"]\n\
+             [packages.\"lib+typo\"]\ntest_restrictions = [\"needs-rot\"]\n",
+    );
+    assert!(test_restrictions(&config, vec![""].into_iter(), None)
+        .unwrap()
+        .is_empty());
+    assert_eq!(
+        test_restrictions(
+            &config,
+            vec!["a"].into_iter(),
+            Some(BrokenTestPolicy::Flaky)
+        )
+        .unwrap(),
+        vec!["flaky", "needs-root"]
+    );
+    // e.g. the test of b, which depends on a
+    assert_eq!(
+        test_restrictions(&config, vec!["b", "a"].into_iter(), None).unwrap(),
+        vec!["needs-root", "superficial"]
+    );
+    assert!(test_restrictions(&config, vec!["typo"].into_iter(), None).is_err());
+}
+
+#[test]
+fn test_depends_builddeps_and_recommends() {
+    let dev_depends = vec!["librust-quickcheck-1+default-dev".to_string()];
+    let mut config = Config::default();
+    assert_eq!(
+        super::common_test_depends(&config, &dev_depends),
+        dev_depends
+    );
+
+    config.test_depends_builddeps = true;
+    let depends = super::common_test_depends(&config, &dev_depends);
+    assert_eq!(
+        depends,
+        vec!["librust-quickcheck-1+default-dev", "@builddeps@"]
+    );
+    let test_depends = vec!["pkg-config".to_string()]
+        .into_iter()
+        .chain(depends)
+        .collect::<Vec<_>>();
+    let pkgtest = super::PkgTest::new(
+        "librust-foo-dev",
+        "foo",
+        "",
+        "1.0",
+        vec![],
+        &test_depends,
+        vec![],
+    )
+    .unwrap();
+    assert!(pkgtest.to_string().contains(
+        "\nDepends: dh-cargo (>= 18), pkg-config, librust-quickcheck-1+default-dev, \
+             @builddeps@, @\n"
+    ));
+
+    config.test_depends_recommends = true;
+    assert_eq!(
+        super::common_test_depends(&config, &[]),
+        vec!["@builddeps@", "@recommends@"]
+    );
+}
+
+#[test]
+fn test_restrictions_of_two_level_feature_deps() {
+    // cli depends on client, which depends on net
+    let config = parse_config(
+        "[packages.\"lib+net\"]\ntest_restrictions = [\"needs-internet\"]\n\
+             [packages.\"lib+client\"]\ntest_restrictions = [\"allow-stderr\"]\n",
+    );
+    let features_with_deps = features(vec![
+        ("", vec![], vec![]),
+        ("net", vec![""], vec!["a"]),
+        ("client", vec!["net"], vec!["b"]),
+        ("cli", vec!["client"], vec!["c"]),
+    ]);
+    let restrictions = |f: &'static str, policy| {
+        let (feature_deps, _) = transitive_deps(&features_with_deps, f);
+        test_restrictions(&config, Some(f).into_iter().chain(feature_deps), policy).unwrap()
+    };
+    assert!(restrictions("", None).is_empty());
+    assert_eq!(restrictions("net", None), vec!["needs-internet"]);
+    // allow-stderr is always set, so it is not repeated
+    assert_eq!(restrictions("client", None), vec!["needs-internet"]);
+    assert_eq!(
+        restrictions("cli", Some(BrokenTestPolicy::Flaky)),
+        vec!["flaky", "needs-internet"]
+    );
+}
+
+#[test]
+fn broken_test_policies() {
+    let config = Config::default();
+    let restrictions =
+        |policy| test_restrictions(&config, Some("default").into_iter(), policy).unwrap();
+    assert!(restrictions(None).is_empty());
+    assert_eq!(restrictions(Some(BrokenTestPolicy::Flaky)), vec!["flaky"]);
+    assert!(restrictions(Some(BrokenTestPolicy::Fail)).is_empty());
+
+    assert_eq!(
+        dh_auto_test_command(&config, Some(BrokenTestPolicy::Flaky)),
+        "\tdh_auto_test -- test --all || true\n"
+    );
+    assert_eq!(
+        dh_auto_test_command(&config, Some(BrokenTestPolicy::Fail)),
+        "\tdh_auto_test -- test --all\n"
+    );
+    // an empty recipe, so that the tests are not run at all
+    let skip = dh_auto_test_command(&config, Some(BrokenTestPolicy::Skip));
+    assert!(skip.starts_with('#'));
+    assert!(!skip.contains("dh_auto_test"));
+}
+
+#[test]
+fn tempdir_kept_on_error() {
+    let mut tempdir = Some(tempfile::tempdir().unwrap());
+    let path = tempdir.as_ref().unwrap().path().to_path_buf();
+    fs::write(path.join("control"), "Source: rust-foo\n").unwrap();
+
+    assert!(super::keep_tempdir_on_error(&mut tempdir, Ok(())).is_ok());
+    assert!(tempdir.is_some());
+
+    let result: crate::errors::Result<()> = Err(anyhow::format_err!("failed halfway"));
+    assert!(super::keep_tempdir_on_error(&mut tempdir, result).is_err());
+    assert!(tempdir.is_none());
+    assert!(path.join("control").exists());
+    fs::remove_dir_all(&path).unwrap();
+}
+
+#[test]
+fn diff_against_existing_debian_dir() {
+    let old = tempfile::tempdir().unwrap();
+    let new = tempfile::tempdir().unwrap();
+    fs::write(
+        old.path().join("control"),
+        "Source: rust-foo\nSection: rust\n",
+    )
+    .unwrap();
+    fs::write(
+        new.path().join("control"),
+        "Source: rust-foo\nSection: FIXME\n",
+    )
+    .unwrap();
+    fs::write(old.path().join("compat"), "12\n").unwrap();
+    fs::write(new.path().join("compat"), "12\n").unwrap();
+    fs::create_dir(new.path().join("source")).unwrap();
+    fs::write(new.path().join("source").join("format"), "3.0 (quilt)\n").unwrap();
+
+    let diff = diff_debian_dirs(old.path(), new.path(), None).unwrap();
+    let headers = diff
+        .lines()
+        .filter(|l| l.starts_with("--- ") || l.starts_with("+++ "))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        headers,
+        vec![
+            "--- a/debian/control",
+            "+++ b/debian/control",
+            "--- a/debian/source/format",
+            "+++ b/debian/source/format",
+        ]
+    );
+    assert!(diff.contains("\n-Section: rust\n+Section: FIXME\n"));
+    assert!(diff.contains("\n+3.0 (quilt)\n"));
+
+    let only = ["compat".to_string()];
+    assert_eq!(
+        diff_debian_dirs(old.path(), new.path(), Some(&only)).unwrap(),
+        ""
+    );
+}
+
+#[test]
+fn unusual_crate_names() {
+    assert_eq!(base_package_name("4-byte").unwrap(), "4-byte");
+    assert_eq!(base_package_name("x").unwrap(), "x");
+    assert_eq!(base_package_name("Foo_Bar").unwrap(), "foo-bar");
+    let err = base_package_name("_internal").unwrap_err();
+    assert!(err.to_string().contains("\"_internal\""), "{}", err);
+
+    // binary packages have no prefix
+    assert!(check_bin_package_name("4-byte", "bin_name").is_ok());
+    assert!(check_bin_package_name("xy", "bin_name").is_ok());
+    let err = check_bin_package_name("x", "bin_name").unwrap_err();
+    assert!(err.to_string().contains("change bin_name "), "{}", err);
+}
+
+#[test]
+fn source_options_from_config() {
+    let mut config = Config::default();
+    assert_eq!(source_options(&config, Compression::Gzip).unwrap(), "");
+    assert_eq!(
+        source_options(&config, Compression::Zstd).unwrap(),
+        "compression = \"zstd\"\n"
+    );
+
+    config.source_options_compression_level = Some(9);
+    config.source_options_extend_diff_ignore = Some(vec![
+        "^src/generated\\.rs$".to_string(),
+        "^target/".to_string(),
+    ]);
+    assert_eq!(
+        source_options(&config, Compression::Xz).unwrap(),
+        "compression = \"xz\"\n\
+             compression-level = 9\n\
+             extend-diff-ignore = \"^src/generated\\.rs$\"\n\
+             extend-diff-ignore = \"^target/\"\n"
+    );
+
+    config.source_options_compression_level = Some(0);
+    assert!(source_options(&config, Compression::Gzip).is_err());
+}
+
+#[test]
+fn gbp_conf_from_config() {
+    let config = Config::default();
+    assert!(config.gbp.is_none());
+
+    let config = parse_config("[gbp]\n");
+    assert_eq!(
+        gbp_conf_contents(config.gbp.as_ref().unwrap()),
+        "[DEFAULT]\n\
+             debian-branch = debian/latest\n\
+             upstream-tag = upstream/%(version)s\n\
+             pristine-tar = False\n"
+    );
+
+    let config = parse_config(
+        "[gbp]\ndebian_branch = \"debian/sid\"\nupstream_tag = \"v%(version)s\"\n\
+             pristine_tar = true\n",
+    );
+    assert_eq!(
+        gbp_conf_contents(config.gbp.as_ref().unwrap()),
+        "[DEFAULT]\n\
+             debian-branch = debian/sid\n\
+             upstream-tag = v%(version)s\n\
+             pristine-tar = True\n"
+    );
+}
+
+#[test]
+fn dh_auto_test_with_test_args() {
+    let mut config = Config::default();
+    assert_eq!(
+        dh_auto_test_command(&config, None),
+        "\tdh_auto_test -- test --all\n"
+    );
+    assert_eq!(
+        dh_auto_test_command(&config, Some(BrokenTestPolicy::Flaky)),
+        "\tdh_auto_test -- test --all || true\n"
+    );
+
+    config.test_args = Some(
+        ["test", "--no-default-features", "--", "--test-threads=1"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect(),
+    );
+    assert_eq!(
+        dh_auto_test_command(&config, Some(BrokenTestPolicy::Flaky)),
+        "\tdh_auto_test -- test --no-default-features -- --test-threads=1 || true\n"
+    );
+}
+
+#[test]
+fn optional_dep_feature_packages() {
+    let mut input = features(vec![
+        ("", vec![], vec!["a"]),
+        ("default", vec!["std"], vec![]),
+        ("std", vec![""], vec!["c"]),
+        ("extra", vec![""], vec!["b"]),
+        ("serde", vec![""], vec!["serde"]),
+        ("log", vec![""], vec!["log"]),
+        ("all", vec!["serde", "log"], vec![]),
+    ]);
+    for f in &["serde", "log"] {
+        for d in input.get_mut(f).unwrap().1.iter_mut() {
+            d.set_optional(true);
+        }
+    }
+    assert!(super::is_optional_dep_feature(&input, "serde"));
+    assert!(!super::is_optional_dep_feature(&input, "extra"));
+    assert!(!super::is_optional_dep_feature(&input, "all"));
+
+    let (provides, _) = reduce_provides(input.clone());
+    let (recommends, suggests, optional_dep_features) =
+        super::classify_feature_packages(&input, &provides);
+    assert_eq!(recommends, vec!["std"]);
+    assert_eq!(suggests, vec!["all", "extra", "log", "serde"]);
+    assert_eq!(
+        optional_dep_features.into_iter().collect::<Vec<_>>(),
+        vec!["log", "serde"]
+    );
+}
+
+#[test]
+fn feature_package_renames() {
+    // version 1 had packages for "a", "b", "c" and "old"; in version 2,
+    // "b" depends on "a" only, so it is provided by the "a" package now,
+    // and "old" was removed
+    let previous = ["a", "b", "c", "old"]
+        .iter()
+        .map(|f| super::control::deb_feature_name("foo", f))
+        .collect::<Vec<_>>();
+    let (provides, _) = reduce_provides(features(vec![
+        ("", vec![], vec![]),
+        ("a", vec![""], vec!["x"]),
+        ("b", vec!["a"], vec![]),
+        ("c", vec![""], vec!["y"]),
+    ]));
+    let obsoleted = obsoleted_feature_packages(&previous, "foo", &provides);
+    assert_eq!(
+        obsoleted.into_iter().collect::<Vec<_>>(),
+        vec![
+            ("", vec!["librust-foo+old-dev".to_string()]),
+            ("a", vec!["librust-foo+b-dev".to_string()]),
+        ]
+    );
+}
+
+#[test]
+fn previous_feature_packages_from_config_or_control() {
+    let mut config = Config::default();
+    assert!(previous_feature_packages(&config, "foo", None).is_empty());
+
+    let control = "Source: rust-foo\n\nPackage: librust-foo-dev\n\n\
+                       Package: librust-foo+std-dev\n\nPackage: librust-foobar+std-dev\n";
+    assert_eq!(
+        previous_feature_packages(&config, "foo", Some(control)),
+        vec!["librust-foo+std-dev"]
+    );
+
+    config.previous_features = Some(vec!["serde".to_string()]);
+    assert_eq!(
+        previous_feature_packages(&config, "foo", Some(control)),
+        vec!["librust-foo+serde-dev"]
+    );
+
+    // packages dropped by an earlier run are still dropped
+    let control = concat!(
+        "Source: rust-foo\n",
+        "\n",
+        "Package: librust-foo-dev\n",
+        "Breaks:\n",
+        " librust-foo+old-dev (<< 1:1.0.0-~~)\n",
+        "\n",
+        "Package: librust-foo+std-dev\n",
+    );
+    config.previous_features = None;
+    assert_eq!(
+        previous_feature_packages(&config, "foo", Some(control)),
+        vec!["librust-foo+std-dev", "librust-foo+old-dev"]
+    );
+    config.previous_features = Some(vec!["std".to_string()]);
+    assert_eq!(
+        previous_feature_packages(&config, "foo", Some(control)),
+        vec!["librust-foo+std-dev", "librust-foo+old-dev"]
+    );
+}
+
+#[test]
+fn packages_dropped_from_existing_control() {
+    let output_dir = tempfile::tempdir().unwrap();
+    assert_eq!(existing_control(output_dir.path()).unwrap(), None);
+    fs::create_dir(output_dir.path().join("debian")).unwrap();
+    fs::write(
+        output_dir.path().join("debian").join("control"),
+        "Source: rust-foo\n\n\
+             Package: librust-foo-dev\nArchitecture: any\n\n\
+             Package: librust-foo+std-dev\nArchitecture: any\n\n\
+             Package: librust-foo+old-dev\nArchitecture: any\n\n\
+             Package: foo-cli\nArchitecture: any\n",
+    )
+    .unwrap();
+    let existing =
+        control::binary_packages_in_control(&existing_control(output_dir.path()).unwrap().unwrap());
+    assert_eq!(
+        existing,
+        vec![
+            "librust-foo-dev",
+            "librust-foo+std-dev",
+            "librust-foo+old-dev",
+            "foo-cli"
+        ]
+    );
+
+    // std is now provided by the base package, and the binary is gone
+    let summary = vec![serde_json::json!({
+        "name": "librust-foo-dev",
+        "provides": ["librust-foo+std-dev (= ${binary:Version})"],
+        "test_is_broken": false,
+    })];
+    assert_eq!(
+        dropped_packages(&existing, &summary),
+        vec!["librust-foo+old-dev", "foo-cli"]
+    );
+}
+
+#[test]
+fn fold_empty_features_into_base() {
+    let (provides, reduced) = reduce_provides(fold_empty_features(features(vec![
+        ("", vec![], vec!["a"]),
+        ("default", vec!["f1", "f2"], vec![]),
+        ("f1", vec![""], vec![]),
+        ("f2", vec!["f1"], vec!["a"]),
+        ("f3", vec![""], vec!["b"]),
+        ("f4", vec!["f3"], vec![]),
+    ])));
+    assert_eq!(reduced.keys().copied().collect::<Vec<_>>(), vec!["", "f3"]);
+    assert_eq!(provides[""], vec!["default", "f1", "f2"]);
+    assert_eq!(provides["f3"], vec!["f4"]);
+}
+
+fn crate_tarball() -> Vec<u8> {
+    let mut builder = Builder::new(GzEncoder::new(Vec::new(), GzCompression::default()));
+    for (i, (path, data)) in [
+        ("foo-1.0/src/lib.rs", "pub fn foo() {}\n"),
+        ("foo-1.0/Cargo.toml", "[package]\n"),
+        ("foo-1.0/Cargo.toml.orig", "[package]\n"),
+        ("foo-1.0/README.md", "foo\n"),
+    ]
+    .iter()
+    .enumerate()
+    {
+        let mut header = Header::new_gnu();
+        header.set_mode(0o664);
+        header.set_mtime(1_000_000 + i as u64);
+        header.set_uid(1000);
+        header.set_gid(1000);
+        header.set_size(data.len() as u64);
+        builder
+            .append_data(&mut header, path, data.as_bytes())
+            .unwrap();
+    }
+    builder.into_inner().unwrap().finish().unwrap()
+}
+
+fn repack(input: &[u8], output_dir: &Path) -> Vec<u8> {
+    let filter_path = |p: &Path| Ok::<_, String>(p.ends_with("README.md"));
+    let (_, tarball) = decompressed_tarball(input).unwrap();
+    let mut archive = Archive::new(tarball);
+    let mut new_archive = Builder::new(GzEncoder::new(Vec::new(), GzCompression::best()));
+    repack_orig_tarball(&filter_path, &mut archive, &mut new_archive, output_dir).unwrap();
+    new_archive.into_inner().unwrap().finish().unwrap()
+}
+
+#[test]
+fn repack_xz_crate_tarball() {
+    let output_dir = tempfile::tempdir().unwrap();
+    fs::write(
+        output_dir.path().join("Cargo.toml"),
+        "[package]\nname = \"foo\"\n",
+    )
+    .unwrap();
+    fs::write(output_dir.path().join("Cargo.toml.orig"), "[package]\n").unwrap();
+
+    let gz_input = crate_tarball();
+    let mut tar = vec![];
+    GzDecoder::new(gz_input.as_slice())
+        .read_to_end(&mut tar)
+        .unwrap();
+    let mut encoder = xz2::write::XzEncoder::new(Vec::new(), 6);
+    io::Write::write_all(&mut encoder, &tar).unwrap();
+    let xz_input = encoder.finish().unwrap();
+
+    for (input, compression) in [
+        (&gz_input, Some(Compression::Gzip)),
+        (&xz_input, Some(Compression::Xz)),
+        (&tar, None),
+    ] {
+        assert_eq!(
+            decompressed_tarball(input.as_slice()).unwrap().0,
+            compression
+        );
+    }
+    let expected = repack(&gz_input, output_dir.path());
+    assert_eq!(repack(&xz_input, output_dir.path()), expected);
+    assert_eq!(repack(&tar, output_dir.path()), expected);
+}
+
+#[test]
+fn repack_orig_tarball_is_reproducible() {
+    let output_dir = tempfile::tempdir().unwrap();
+    fs::write(
+        output_dir.path().join("Cargo.toml"),
+        "[package]\nname = \"foo\"\n",
+    )
+    .unwrap();
+    fs::write(output_dir.path().join("Cargo.toml.orig"), "[package]\n").unwrap();
+
+    let input = crate_tarball();
+    let first = repack(&input, output_dir.path());
+    let second = repack(&input, output_dir.path());
+    assert_eq!(first, second);
+
+    let mut archive = Archive::new(GzDecoder::new(first.as_slice()));
+    let mut paths = vec![];
+    for entry in archive.entries().unwrap() {
+        let entry = entry.unwrap();
+        let header = entry.header();
+        assert_eq!(header.mtime().unwrap(), 1_000_003);
+        assert_eq!(header.uid().unwrap(), 0);
+        assert_eq!(header.gid().unwrap(), 0);
+        assert_eq!(header.mode().unwrap(), 0o644);
+        paths.push(entry.path().unwrap().to_str().unwrap().to_string());
+    }
+    assert_eq!(
+        paths,
+        vec![
+            "foo-1.0/Cargo.toml",
+            "foo-1.0/Cargo.toml.orig",
+            "foo-1.0/src/lib.rs"
+        ]
+    );
+}
+
+/// A reader that records the largest read asked of it, which grows with
+/// the size of the data if the reader is read into memory as a whole.
+struct MaxRead<R> {
+    inner: R,
+    max: usize,
+}
+
+impl<R: Read> Read for MaxRead<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.max = self.max.max(buf.len());
+        self.inner.read(buf)
+    }
+}
+
+#[test]
+fn repack_orig_tarball_streams_large_entries() {
+    const SIZE: u64 = 16 * 1024 * 1024;
+    let output_dir = tempfile::tempdir().unwrap();
+    fs::write(output_dir.path().join("Cargo.toml"), "[package]\n").unwrap();
+    fs::write(output_dir.path().join("Cargo.toml.orig"), "[package]\n").unwrap();
+
+    let mut header = Header::new_gnu();
+    header.set_path("foo-1.0/data.bin").unwrap();
+    header.set_mode(0o644);
+    header.set_size(SIZE);
+    header.set_cksum();
+    let mut input = MaxRead {
+        inner: (&header.as_bytes()[..])
+            .chain(io::repeat(0).take(SIZE))
+            .chain(&[0; 1024][..]),
+        max: 0,
+    };
+
+    let filter_path = |_: &Path| Ok::<_, String>(false);
+    let mut new_archive = Builder::new(io::sink());
+    repack_orig_tarball(
+        &filter_path,
+        &mut Archive::new(&mut input),
+        &mut new_archive,
+        output_dir.path(),
+    )
+    .unwrap();
+    new_archive.finish().unwrap();
+    assert!(input.max <= 1024 * 1024, "read {} bytes at once", input.max);
+}
+
+#[test]
+fn repack_orig_tarball_keeps_links() {
+    let output_dir = tempfile::tempdir().unwrap();
+    fs::write(output_dir.path().join("Cargo.toml"), "[package]\n").unwrap();
+    fs::write(output_dir.path().join("Cargo.toml.orig"), "[package]\n").unwrap();
+
+    let mut builder = Builder::new(Vec::new());
+    let mut header = Header::new_gnu();
+    header.set_entry_type(tar::EntryType::Directory);
+    header.set_size(0);
+    builder
+        .append_data(&mut header, "foo-1.0/src", std::io::empty())
+        .unwrap();
+    let mut header = Header::new_gnu();
+    header.set_size(16);
+    builder
+        .append_data(&mut header, "foo-1.0/src/lib.rs", &b"pub fn foo() {}\n"[..])
+        .unwrap();
+    let mut header = Header::new_gnu();
+    header.set_entry_type(tar::EntryType::Symlink);
+    header.set_size(0);
+    builder
+        .append_link(&mut header, "foo-1.0/src/link.rs", "lib.rs")
+        .unwrap();
+    for name in ["foo-1.0/a.rs", "foo-1.0/src/z.rs"] {
+        let mut header = Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Link);
+        header.set_size(0);
+        builder
+            .append_link(&mut header, name, "foo-1.0/src/lib.rs")
+            .unwrap();
+    }
+    let input = builder.into_inner().unwrap();
+
+    let filter_path = |_: &Path| Ok::<_, String>(false);
+    let mut new_archive = Builder::new(Vec::new());
+    repack_orig_tarball(
+        &filter_path,
+        &mut Archive::new(input.as_slice()),
+        &mut new_archive,
+        output_dir.path(),
+    )
+    .unwrap();
+    let output = new_archive.into_inner().unwrap();
+
+    let mut archive = Archive::new(output.as_slice());
+    let entries = archive
+        .entries()
+        .unwrap()
+        .map(|e| {
+            let e = e.unwrap();
+            (
+                e.path().unwrap().to_str().unwrap().to_string(),
+                e.header().entry_type(),
+                e.link_name()
+                    .unwrap()
+                    .map(|l| l.to_str().unwrap().to_string()),
+            )
+        })
+        .collect::<Vec<_>>();
+    let link = |s: &str| Some(s.to_string());
+    assert_eq!(
+        entries,
+        vec![
+            ("foo-1.0/a.rs".into(), tar::EntryType::Regular, None),
+            ("foo-1.0/src".into(), tar::EntryType::Directory, None),
+            ("foo-1.0/src/lib.rs".into(), tar::EntryType::Regular, None),
+            (
+                "foo-1.0/src/link.rs".into(),
+                tar::EntryType::Symlink,
+                link("lib.rs")
+            ),
+            (
+                "foo-1.0/src/z.rs".into(),
+                tar::EntryType::Link,
+                link("foo-1.0/src/lib.rs")
+            ),
+        ]
+    );
+}
+
+#[test]
+fn repack_orig_tarball_refuses_excluded_hardlink_target() {
+    let output_dir = tempfile::tempdir().unwrap();
+    fs::write(output_dir.path().join("Cargo.toml"), "[package]\n").unwrap();
+    fs::write(output_dir.path().join("Cargo.toml.orig"), "[package]\n").unwrap();
+
+    let mut builder = Builder::new(Vec::new());
+    let mut header = Header::new_gnu();
+    header.set_size(4);
+    builder
+        .append_data(&mut header, "foo-1.0/blob.bin", &b"blob"[..])
+        .unwrap();
+    let mut header = Header::new_gnu();
+    header.set_entry_type(tar::EntryType::Link);
+    header.set_size(0);
+    builder
+        .append_link(&mut header, "foo-1.0/copy.bin", "foo-1.0/blob.bin")
+        .unwrap();
+    let input = builder.into_inner().unwrap();
+
+    let repack = |excludes: &[&str]| {
+        let excludes: Vec<PathBuf> = excludes.iter().map(PathBuf::from).collect();
+        let filter_path = |p: &Path| Ok::<_, String>(excludes.iter().any(|e| e == p));
+        let mut new_archive = Builder::new(Vec::new());
+        repack_orig_tarball(
+            &filter_path,
+            &mut Archive::new(input.as_slice()),
+            &mut new_archive,
+            output_dir.path(),
+        )
+    };
+    let err = repack(&["foo-1.0/blob.bin"]).unwrap_err();
+    assert!(err.to_string().contains("copy.bin"), "{}", err);
+    // fine if the link is excluded as well
+    assert!(repack(&["foo-1.0/blob.bin", "foo-1.0/copy.bin"]).is_ok());
+}
+
+#[test]
+fn pack_orig_dir_skips_target_and_vcs() {
+    let output_dir = tempfile::tempdir().unwrap();
+    fs::write(output_dir.path().join("Cargo.toml"), "[package]\n").unwrap();
+    fs::write(output_dir.path().join("Cargo.toml.orig"), "[package]\n").unwrap();
+
+    let src_dir = tempfile::tempdir().unwrap();
+    for (path, data) in [
+        ("Cargo.toml", "[package]\n"),
+        ("README.md", "foo\n"),
+        ("src/lib.rs", "pub fn foo() {}\n"),
+        ("notes/todo.txt", ""),
+        ("target/debug/foo", ""),
+        (".git/HEAD", ""),
+    ] {
+        let path = src_dir.path().join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, data).unwrap();
+    }
+
+    // as if Cargo.toml had `exclude = ["notes"]`
+    let packaged = ["Cargo.toml", "Cargo.toml.orig", "README.md", "src/lib.rs"]
+        .iter()
+        .map(|p| Path::new("foo-1.0").join(p))
+        .collect();
+    let filter_path = |p: &Path| Ok::<_, String>(p.ends_with("README.md"));
+    let mut new_archive = Builder::new(Vec::new());
+    pack_orig_dir(
+        &filter_path,
+        src_dir.path(),
+        Path::new("foo-1.0"),
+        &packaged,
+        &mut new_archive,
+        output_dir.path(),
+    )
+    .unwrap();
+    let data = new_archive.into_inner().unwrap();
+
+    let mut archive = Archive::new(data.as_slice());
+    let paths = archive
+        .entries()
+        .unwrap()
+        .map(|e| e.unwrap().path().unwrap().to_str().unwrap().to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        paths,
+        vec![
+            "foo-1.0",
+            "foo-1.0/Cargo.toml",
+            "foo-1.0/Cargo.toml.orig",
+            "foo-1.0/src",
+            "foo-1.0/src/lib.rs"
+        ]
+    );
+}
+
+#[test]
+fn upstream_metadata_for_forges() {
+    assert_eq!(
+        upstream_metadata("https://github.com/rust-lang/cargo/", true),
+        "---
+Archive: crates.io
+Bug-Database: https://github.com/rust-lang/cargo/issues
+Bug-Submit: https://github.com/rust-lang/cargo/issues/new
+Repository: https://github.com/rust-lang/cargo.git
+Repository-Browse: https://github.com/rust-lang/cargo
+"
+    );
+    assert_eq!(
+        upstream_metadata("https://gitlab.com/foo/bar.git", true),
+        "---
+Archive: crates.io
+Bug-Database: https://gitlab.com/foo/bar/-/issues
+Bug-Submit: https://gitlab.com/foo/bar/-/issues/new
+Repository: https://gitlab.com/foo/bar.git
+Repository-Browse: https://gitlab.com/foo/bar
+"
+    );
+    assert_eq!(
+        upstream_metadata("https://gitlab.com/foo/bar/baz.git", true),
+        "---
+Archive: crates.io
+Bug-Database: https://gitlab.com/foo/bar/baz/-/issues
+Bug-Submit: https://gitlab.com/foo/bar/baz/-/issues/new
+Repository: https://gitlab.com/foo/bar/baz.git
+Repository-Browse: https://gitlab.com/foo/bar/baz
+"
+    );
+    assert_eq!(
+        forge_repository("https://salsa.debian.org/rust-team/tools/debcargo/-/tree/main"),
+        Some((
+            "https://salsa.debian.org/rust-team/tools/debcargo".to_string(),
+            "salsa.debian.org".to_string()
+        ))
+    );
+    assert_eq!(
+        forge_repository("https://github.com/rust-lang/cargo/tree/master/crates"),
+        Some((
+            "https://github.com/rust-lang/cargo".to_string(),
+            "github.com".to_string()
+        ))
+    );
+    assert_eq!(forge_repository("https://gitlab.com/foo"), None);
+    assert_eq!(
+        upstream_metadata("https://example.org/foo", true),
+        "---\nArchive: crates.io\nRepository: https://example.org/foo\n"
+    );
+    assert_eq!(
+        upstream_metadata("https://example.org/foo", false),
+        "---\nRepository: https://example.org/foo\n"
+    );
+}
+
+#[test]
+fn rustc_dep_includes_minver() {
+    assert_eq!("rustc:native (>= 1.65)", rustc_dep(&Some(&"1.65")));
+}
+
+#[test]
+fn rustc_dep_excludes_minver() {
+    assert_eq!("rustc:native", rustc_dep(&None));
+}
+
+#[test]
+fn libstd_rust_dep_from_rust_version() {
+    assert_eq!("libstd-rust-dev (>= 1.63)", libstd_rust_dep(&Some("1.63")));
+    assert_eq!("libstd-rust-dev", libstd_rust_dep(&None));
+}
+
+#[test]
+fn split_bin_package_names() {
+    assert_eq!(
+        split_bin_packages(&["foo-server", "foo_cli"]),
+        vec![
+            ("foo-server".to_string(), "foo-server"),
+            ("foo-cli".to_string(), "foo_cli"),
+        ]
+    );
+}
+
+#[test]
+fn split_bin_packages_in_control_and_rules() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let root = tempdir.path().join("mypackage");
+    fs::create_dir_all(root.join("src").join("bin")).unwrap();
+    fs::write(
+        root.join("Cargo.toml"),
+        "[package]\nname = \"mypackage\"\nversion = \"0.1.0\"\nlicense = \"MIT\"\n",
+    )
+    .unwrap();
+    for bin in ["foo-server", "foo_cli"] {
+        fs::write(
+            root.join("src").join("bin").join(format!("{}.rs", bin)),
+            "fn main() {}\n",
+        )
+        .unwrap();
+    }
+    let crate_info = CrateInfo::new_with_local_crate("mypackage", None, &root, true).unwrap();
+    let deb_info = DebInfo::new(&crate_info, "2.6.0", false, None, Compression::default()).unwrap();
+    let output_dir = tempdir.path().join("output");
+    crate_info.extract_crate(&output_dir).unwrap();
+    let overlay_dir = tempfile::tempdir().unwrap();
+
+    let generate = |split_bins: bool| {
+        let mut config = Config::default();
+        config.split_bins = split_bins;
+        let files = generate_debian_files(
+            &crate_info,
+            &deb_info,
+            None,
+            &config,
+            &output_dir,
+            overlay_dir.path(),
+            Some("Jane Doe <jane@example.org>"),
+            false,
+            false,
+        )
+        .unwrap();
+        let file = |name: &str| String::from_utf8(files[Path::new(name)].clone()).unwrap();
+        (file("control"), file("rules"))
+    };
+    let stanza = |control: &str, name: &str| {
+        control
+            .split("\n\n")
+            .find(|p| p.starts_with(&format!("Package: {}\n", name)))
+            .map(str::to_string)
+    };
+
+    // by default, one package ships all binaries
+    let (control, rules) = generate(false);
+    let mypackage = stanza(&control, "mypackage").unwrap();
+    assert!(mypackage.contains("foo-server"));
+    assert!(mypackage.contains("foo_cli"));
+    assert!(stanza(&control, "foo-server").is_none());
+    assert!(!rules.contains("override_dh_auto_install"));
+
+    // one package per binary, each describing just its own binary
+    let (control, rules) = generate(true);
+    assert!(stanza(&control, "mypackage").is_none());
+    let server = stanza(&control, "foo-server").unwrap();
+    assert!(server.contains(" - foo-server\n"));
+    assert!(!server.contains("foo_cli"));
+    let cli = stanza(&control, "foo-cli").unwrap();
+    assert!(cli.contains(" - foo_cli\n"));
+    assert!(!cli.contains("foo-server"));
+    // dh-cargo installs both into the first package, the other is moved
+    let (first, (other_pkg, other_bin)) = if rules.contains("mkdir -p debian/foo-cli/") {
+        ("foo-server", ("foo-cli", "foo_cli"))
+    } else {
+        ("foo-cli", ("foo-server", "foo-server"))
+    };
+    assert!(rules.contains(&format!(
+        "\tmv debian/{}/usr/bin/{} debian/{}/usr/bin/\n",
+        first, other_bin, other_pkg
+    )));
+}
+
+#[test]
+fn select_debian_files_to_regenerate() {
+    let all: DebianFiles = ["control", "copyright", "tests/control", "watch"]
+        .iter()
+        .map(|name| (PathBuf::from(name), vec![]))
+        .collect();
+
+    let mut files = all.clone();
+    select_debian_files(&mut files, &[]).unwrap();
+    assert_eq!(files, all);
+
+    let mut files = all.clone();
+    let only = vec!["copyright".to_string(), "tests/control".to_string()];
+    select_debian_files(&mut files, &only).unwrap();
+    assert_eq!(
+        files.keys().collect::<Vec<_>>(),
+        vec![Path::new("copyright"), Path::new("tests/control")]
+    );
+
+    let mut files = all;
+    assert!(select_debian_files(&mut files, &["patches/series".to_string()]).is_err());
+}
+
+#[test]
+fn git_watch_stanza() {
+    assert_eq!(
+        git_watch(
+            "https://github.com/foo/foo",
+            "foo",
+            "@ANY_VERSION@",
+            false,
+            false
+        ),
+        r#"version=4
+opts="mode=git,\
+pgpmode=none,\
+uversionmangle=s/(\d)[_\.\-\+]?((RC|rc|pre|dev|beta|alpha)\d*)$/$1~$2/" \
+https://github.com/foo/foo \
+refs/tags/(?:foo-)?v?@ANY_VERSION@
+"#
+    );
+    assert!(git_watch(
+        "https://github.com/foo/foo",
+        "foo",
+        "@ANY_VERSION@",
+        true,
+        false
+    )
+    .contains(&format!("repacksuffix={},", REPACK_SUFFIX)));
+    assert!(git_watch(
+        "https://github.com/foo/foo",
+        "foo",
+        "@ANY_VERSION@",
+        false,
+        true
+    )
+    .contains("pgpmode=gittag,"));
+}
+
+#[test]
+fn generate_debian_files_in_memory() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let root = tempdir.path().join("mypackage");
+    fs::create_dir_all(root.join("src")).unwrap();
+    fs::write(
+        root.join("Cargo.toml"),
+        "[package]\nname = \"mypackage\"\nversion = \"0.1.0\"\nlicense = \"MIT\"\n",
+    )
+    .unwrap();
+    fs::write(root.join("src").join("lib.rs"), "").unwrap();
+    let crate_info = CrateInfo::new_with_local_crate("mypackage", None, &root, true).unwrap();
+    let deb_info = DebInfo::new(&crate_info, "2.6.0", false, None, Compression::default()).unwrap();
+    let output_dir = tempdir.path().join("output");
+    crate_info.extract_crate(&output_dir).unwrap();
+    let overlay_dir = tempfile::tempdir().unwrap();
+
+    let files = generate_debian_files(
+        &crate_info,
+        &deb_info,
+        None,
+        &Config::default(),
+        &output_dir,
+        overlay_dir.path(),
+        Some("Jane Doe <jane@example.org>"),
+        false,
+        false,
+    )
+    .unwrap();
+    assert_eq!(
+        files
+            .keys()
+            .map(|p| p.to_str().unwrap())
+            .collect::<Vec<_>>(),
+        vec![
+            "cargo-checksum.json",
+            "changelog",
+            "compat",
+            "control",
+            "copyright",
+            "rules",
+            "source/format",
+            "tests/control",
+            "watch",
+        ]
+    );
+    assert!(files.values().all(|data| !data.is_empty()));
+    // nothing is written to disk
+    assert!(!output_dir.join("debian").exists());
+    assert_eq!(fs::read_dir(overlay_dir.path()).unwrap().count(), 0);
+}
+
+#[test]
+fn repacked_crate_versions() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let root = tempdir.path().join("mypackage");
+    fs::create_dir_all(root.join("src")).unwrap();
+    fs::create_dir_all(root.join("vendor")).unwrap();
+    fs::write(
+        root.join("Cargo.toml"),
+        "[package]\nname = \"mypackage\"\nversion = \"0.1.0\"\n\
+             license = \"MIT\"\ndescription = \"A package\"\n",
+    )
+    .unwrap();
+    fs::write(root.join("src").join("lib.rs"), "").unwrap();
+    fs::write(root.join("vendor").join("blob.bin"), "").unwrap();
+
+    let mut crate_info = CrateInfo::new_with_local_crate("mypackage", None, &root, true).unwrap();
+    crate_info
+        .set_includes_excludes(Some(&vec!["vendor/*".to_string()]), None)
+        .unwrap();
+    let deb_info = DebInfo::new(&crate_info, "2.6.0", false, None, Compression::default()).unwrap();
+    assert_eq!(deb_info.deb_upstream_version(), "0.1.0+dfsg1");
+    assert_eq!(
+        deb_info.orig_tarball_path(),
+        Path::new("rust-mypackage_0.1.0+dfsg1.orig.tar.gz")
+    );
+
+    let output_dir = tempdir.path().join("output");
+    crate_info.extract_crate(&output_dir).unwrap();
+    assert!(!output_dir.join("vendor").join("blob.bin").exists());
+    let overlay_dir = tempfile::tempdir().unwrap();
+    let files = generate_debian_files(
+        &crate_info,
+        &deb_info,
+        None,
+        &Config::default(),
+        &output_dir,
+        overlay_dir.path(),
+        Some("Jane Doe <jane@example.org>"),
+        false,
+        false,
+    )
+    .unwrap();
+    let file = |name: &str| String::from_utf8(files[Path::new(name)].clone()).unwrap();
+    assert!(file("changelog").starts_with("rust-mypackage (0.1.0+dfsg1-1) "));
+    assert!(file("copyright").contains("Files-Excluded:"));
+    // the tests run against the crate as published
+    let tests_control = file("tests/control");
+    assert!(tests_control.contains("cargo-auto-test mypackage 0.1.0 "));
+    assert!(!tests_control.contains("+dfsg1"));
+}
+
+#[test]
+fn epoch_versions() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let root = tempdir.path().join("mypackage");
+    fs::create_dir_all(root.join("src")).unwrap();
+    fs::write(
+        root.join("Cargo.toml"),
+        "[package]\nname = \"mypackage\"\nversion = \"0.1.0\"\nlicense = \"MIT\"\n",
+    )
+    .unwrap();
+    fs::write(root.join("src").join("lib.rs"), "").unwrap();
+    let crate_info = CrateInfo::new_with_local_crate("mypackage", None, &root, true).unwrap();
+    let deb_info =
+        DebInfo::new(&crate_info, "2.6.0", false, Some(1), Compression::default()).unwrap();
+    assert_eq!(deb_info.deb_upstream_version(), "0.1.0");
+    assert_eq!(deb_info.deb_epoch_upstream_version(), "1:0.1.0");
+    // but not in file names
+    assert_eq!(
+        deb_info.package_source_dir(),
+        Path::new("rust-mypackage-0.1.0")
+    );
+    assert_eq!(
+        deb_info.orig_tarball_path(),
+        Path::new("rust-mypackage_0.1.0.orig.tar.gz")
+    );
+
+    let output_dir = tempdir.path().join("output");
+    crate_info.extract_crate(&output_dir).unwrap();
+    let overlay_dir = tempfile::tempdir().unwrap();
+    let changelog = || {
+        let files = generate_debian_files(
+            &crate_info,
+            &deb_info,
+            None,
+            &Config::default(),
+            &output_dir,
+            overlay_dir.path(),
+            Some("Jane Doe <jane@example.org>"),
+            false,
+            false,
+        )
+        .unwrap();
+        String::from_utf8(files[Path::new("changelog")].clone()).unwrap()
+    };
+    assert!(changelog().starts_with("rust-mypackage (1:0.1.0-1) "));
+
+    // the revision of an earlier upload with the same epoch is bumped
+    fs::write(
+        overlay_dir.path().join("changelog"),
+        "rust-mypackage (1:0.1.0-1) unstable; urgency=medium\n\n  * Upload.\n\n \
+             -- Jane Doe <jane@example.org>  Thu, 01 Jan 2026 00:00:00 +0000\n",
+    )
+    .unwrap();
+    assert!(changelog().starts_with("rust-mypackage (1:0.1.0-2) "));
+}
+
+#[test]
+fn cargo_checksum_of_patched_sources() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let root = tempdir.path().join("mypackage");
+    fs::create_dir_all(root.join("src")).unwrap();
+    fs::write(
+        root.join("Cargo.toml"),
+        "[package]\nname = \"mypackage\"\nversion = \"0.1.0\"\nlicense = \"MIT\"\n",
+    )
+    .unwrap();
+    fs::write(root.join("src").join("lib.rs"), "pub fn f() {}\n").unwrap();
+    let patches = tempdir.path().join("overlay").join("patches");
+    fs::create_dir_all(&patches).unwrap();
+    fs::write(patches.join("series"), "rename.patch\n").unwrap();
+    fs::write(
+        patches.join("rename.patch"),
+        "--- a/src/lib.rs\n+++ b/src/lib.rs\n@@ -1 +1 @@\n-pub fn f() {}\n+pub fn g() {}\n",
+    )
+    .unwrap();
+    let config_path = tempdir.path().join("debcargo.toml");
+    let config = parse_config("overlay = \"overlay\"\n");
+
+    let mut crate_info = CrateInfo::new_with_local_crate("mypackage", None, &root, true).unwrap();
+    let deb_info = DebInfo::new(&crate_info, "2.6.0", false, None, Compression::default()).unwrap();
+    let output_dir = tempdir.path().join("output");
+    crate_info.extract_crate(&output_dir).unwrap();
+    let overlay_dir =
+        apply_overlay_and_patches(&mut crate_info, Some(&config_path), &config, &output_dir)
+            .unwrap();
+    // the patches are unapplied again
+    assert_eq!(
+        fs::read_to_string(output_dir.join("src").join("lib.rs")).unwrap(),
+        "pub fn f() {}\n"
+    );
+
+    let files = generate_debian_files(
+        &crate_info,
+        &deb_info,
+        Some(&config_path),
+        &config,
+        &output_dir,
+        overlay_dir.path(),
+        Some("Jane Doe <jane@example.org>"),
+        false,
+        false,
+    )
+    .unwrap();
+    let checksum: serde_json::Value =
+        serde_json::from_slice(&files[Path::new("cargo-checksum.json")]).unwrap();
+    let patched = cargo_util::Sha256::new()
+        .update(b"pub fn g() {}\n")
+        .finish_hex();
+    assert_eq!(checksum["files"]["src/lib.rs"], patched.as_str());
+}
+
+#[test]
+fn locked_build_with_kept_cargo_lock() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let root = tempdir.path().join("mypackage");
+    fs::create_dir_all(root.join("src")).unwrap();
+    fs::write(
+        root.join("Cargo.toml"),
+        "[package]\nname = \"mypackage\"\nversion = \"0.1.0\"\nlicense = \"MIT\"\n",
+    )
+    .unwrap();
+    fs::write(root.join("src").join("main.rs"), "fn main() {}\n").unwrap();
+    let crate_info = CrateInfo::new_with_local_crate("mypackage", None, &root, true).unwrap();
+    let deb_info = DebInfo::new(&crate_info, "2.6.0", false, None, Compression::default()).unwrap();
+    let output_dir = tempdir.path().join("output");
+    crate_info.extract_crate(&output_dir).unwrap();
+    let overlay_dir = tempfile::tempdir().unwrap();
+
+    let rules = |keep_cargo_lock: Option<bool>| {
+        let mut config = Config::default();
+        config.keep_cargo_lock = keep_cargo_lock;
+        let files = generate_debian_files(
+            &crate_info,
+            &deb_info,
+            None,
+            &config,
+            &output_dir,
+            overlay_dir.path(),
+            Some("Jane Doe <jane@example.org>"),
+            false,
+            false,
+        )
+        .unwrap();
+        String::from_utf8(files[Path::new("rules")].clone()).unwrap()
+    };
+    assert!(!rules(None).contains("--locked"));
+    assert!(rules(Some(true))
+        .contains("\noverride_dh_auto_build:\n\tdh_auto_build -- build --locked\n"));
+}
+
+#[test]
+fn data_package_files() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let root = tempdir.path().join("mypackage");
+    fs::create_dir_all(root.join("src")).unwrap();
+    fs::create_dir_all(root.join("data")).unwrap();
+    fs::write(
+        root.join("Cargo.toml"),
+        "[package]\nname = \"mypackage\"\nversion = \"0.1.0\"\nlicense = \"MIT\"\n",
+    )
+    .unwrap();
+    fs::write(root.join("src").join("lib.rs"), "").unwrap();
+    fs::write(root.join("data").join("table.bin"), "").unwrap();
+    let crate_info = CrateInfo::new_with_local_crate("mypackage", None, &root, true).unwrap();
+    let deb_info = DebInfo::new(&crate_info, "2.6.0", false, None, Compression::default()).unwrap();
+    let output_dir = tempdir.path().join("output");
+    crate_info.extract_crate(&output_dir).unwrap();
+    let overlay_dir = tempfile::tempdir().unwrap();
+
+    let mut config = Config::default();
+    config.data_package = true;
+    config.data_package_paths = Some(vec!["data".to_string()]);
+    let files = generate_debian_files(
+        &crate_info,
+        &deb_info,
+        None,
+        &config,
+        &output_dir,
+        overlay_dir.path(),
+        Some("Jane Doe <jane@example.org>"),
+        false,
+        false,
+    )
+    .unwrap();
+    let control = String::from_utf8(files[Path::new("control")].clone()).unwrap();
+    assert!(control
+        .contains("\nPackage: librust-mypackage-data\nArchitecture: all\nMulti-Arch: foreign\n"));
+    let dev = control
+        .split("\n\n")
+        .find(|p| p.starts_with("Package: librust-mypackage-dev\n"))
+        .unwrap();
+    assert!(dev.contains("\n librust-mypackage-data (= ${source:Version})"));
+    let rules = String::from_utf8(files[Path::new("rules")].clone()).unwrap();
+    let registry = "usr/share/cargo/registry/mypackage-0.1.0";
+    assert!(rules.contains(&format!(
+        "\tmv debian/librust-mypackage-dev/{}/data debian/librust-mypackage-data/{}/\n",
+        registry, registry
+    )));
+}
+
+#[test]
+fn clean_files_from_config_and_lockfile() {
+    let output_dir = tempfile::tempdir().unwrap();
+    let mut config = Config::default();
+    assert!(clean_files(&config, output_dir.path()).is_empty());
+
+    config.keep_cargo_lock = Some(false);
+    assert_eq!(clean_files(&config, output_dir.path()), vec!["Cargo.lock"]);
+
+    fs::write(output_dir.path().join("Cargo.lock"), "").unwrap();
+    config.keep_cargo_lock = Some(true);
+    assert!(clean_files(&config, output_dir.path()).is_empty());
+
+    config.clean = Some(vec!["src/bindings.rs".to_string()]);
+    assert_eq!(
+        clean_files(&config, output_dir.path()),
+        vec!["src/bindings.rs"]
+    );
+}
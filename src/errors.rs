@@ -1,43 +1,144 @@
 use anyhow;
 
+use std::cell::RefCell;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+
 pub type Result<T> = ::std::result::Result<T, anyhow::Error>;
 pub use anyhow::format_err;
 
+/// Failure categories that library users may want to tell apart. Errors are
+/// still returned as `anyhow::Error`, so that context can be attached on the
+/// way up; use `error_kind` to find out which category, if any, an error
+/// belongs to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DebcargoError {
+    /// The maintainer name or email could not be determined.
+    MissingAuthor(String),
+    /// Cargo.toml of the crate could not be read or parsed.
+    Manifest(String),
+    /// Applying or unapplying the overlay patches with quilt failed.
+    Quilt(String),
+    /// The downloaded .crate does not match the registry checksum.
+    ChecksumMismatch(String),
+    /// debcargo.toml could not be read or parsed.
+    Config(String),
+}
+
+impl DebcargoError {
+    /// Whether the error is specific to the crate being packaged, so that a
+    /// batch run may skip it and carry on, as opposed to an error in the
+    /// setup that will make every other crate fail too.
+    pub fn is_recoverable(&self) -> bool {
+        use self::DebcargoError::*;
+        match self {
+            Manifest(_) | Quilt(_) | ChecksumMismatch(_) => true,
+            MissingAuthor(_) | Config(_) => false,
+        }
+    }
+}
+
+impl fmt::Display for DebcargoError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::DebcargoError::*;
+        match self {
+            MissingAuthor(msg)
+            | Manifest(msg)
+            | Quilt(msg)
+            | ChecksumMismatch(msg)
+            | Config(msg) => f.write_str(msg),
+        }
+    }
+}
+
+impl std::error::Error for DebcargoError {}
+
+/// The `DebcargoError` in the chain of `err`, if any.
+pub fn error_kind(err: &anyhow::Error) -> Option<&DebcargoError> {
+    err.chain().find_map(|e| e.downcast_ref::<DebcargoError>())
+}
+
+/// Severity of a diagnostic message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Info,
+    Warning,
+}
+
+/// A message reported via `debcargo_info!` or `debcargo_warn!`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub level: Level,
+    pub message: String,
+}
+
+thread_local! {
+    // one Vec per active collect_diagnostics call, innermost last
+    static COLLECTORS: RefCell<Vec<Vec<Diagnostic>>> = RefCell::new(Vec::new());
+}
+
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// Don't print diagnostics to stderr; they are still collected by
+/// `collect_diagnostics`.
+pub fn set_quiet(quiet: bool) {
+    QUIET.store(quiet, Ordering::Relaxed);
+}
+
+/// Report a diagnostic: record it for any active `collect_diagnostics` on
+/// this thread, and print it to stderr unless `set_quiet` was called.
+pub fn report(level: Level, message: String) {
+    use ansi_term::Colour::{Green, RGB};
+    if !QUIET.load(Ordering::Relaxed) {
+        match level {
+            Level::Info => eprintln!("{}", Green.paint(&message)),
+            Level::Warning => eprintln!("{}", RGB(255, 165, 0).bold().paint(&message)),
+        }
+    }
+    COLLECTORS.with(|c| {
+        if let Some(diagnostics) = c.borrow_mut().last_mut() {
+            diagnostics.push(Diagnostic { level, message });
+        }
+    });
+}
+
+/// Run `f` and return the diagnostics that were reported on this thread
+/// meanwhile. Calls may be nested, outer calls also get the diagnostics of
+/// inner ones.
+pub fn collect_diagnostics<T>(f: impl FnOnce() -> T) -> (T, Vec<Diagnostic>) {
+    COLLECTORS.with(|c| c.borrow_mut().push(Vec::new()));
+    let result = f();
+    let diagnostics = COLLECTORS.with(|c| {
+        let mut c = c.borrow_mut();
+        let diagnostics = c.pop().unwrap();
+        if let Some(outer) = c.last_mut() {
+            outer.extend(diagnostics.iter().cloned());
+        }
+        diagnostics
+    });
+    (result, diagnostics)
+}
+
 #[macro_export]
 macro_rules! debcargo_info {
     ($e:expr) => {
-        {
-            use ansi_term::Colour::Green;
-            eprintln!("{}", Green.paint($e));
-        }
+        $crate::errors::report($crate::errors::Level::Info, format!("{}", $e))
     };
 
     ($fmt:expr, $( $arg:tt)+) => {
-        {
-            use ansi_term::Colour::Green;
-            let print_string = format!($fmt, $($arg)+);
-            eprintln!("{}", Green.paint(print_string));
-        }
+        $crate::errors::report($crate::errors::Level::Info, format!($fmt, $($arg)+))
     };
 }
 
 #[macro_export]
 macro_rules! debcargo_warn {
     ($e:expr) => {
-        {
-            use ansi_term::Colour::RGB;
-            eprintln!("{}", RGB(255,165,0).bold().paint($e));
-        }
+        $crate::errors::report($crate::errors::Level::Warning, format!("{}", $e))
     };
 
     ($fmt:expr, $( $arg:tt)+) => {
-        {
-            use ansi_term::Colour::RGB;
-            let print_string = RGB(255,165,0).bold().paint(format!($fmt, $($arg)+));
-            eprintln!("{}", print_string);
-        }
+        $crate::errors::report($crate::errors::Level::Warning, format!($fmt, $($arg)+))
     };
-
 }
 
 #[macro_export]
@@ -53,3 +154,50 @@ macro_rules! debcargo_bail {
         }
     };
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diagnostics_are_collected() {
+        set_quiet(true);
+        let ((_, inner), outer) = collect_diagnostics(|| {
+            debcargo_info!("outer");
+            collect_diagnostics(|| debcargo_warn!("inner {}", 1))
+        });
+        debcargo_warn!("not collected");
+        set_quiet(false);
+
+        assert_eq!(
+            inner,
+            vec![Diagnostic {
+                level: Level::Warning,
+                message: "inner 1".to_string()
+            }]
+        );
+        assert_eq!(
+            outer
+                .iter()
+                .map(|d| (d.level, d.message.as_str()))
+                .collect::<Vec<_>>(),
+            vec![(Level::Info, "outer"), (Level::Warning, "inner 1")]
+        );
+    }
+
+    #[test]
+    fn error_kind_through_context() {
+        let err = anyhow::Error::from(DebcargoError::Quilt("quilt push failed".to_string()))
+            .context("could not apply overlay");
+        assert_eq!(
+            error_kind(&err),
+            Some(&DebcargoError::Quilt("quilt push failed".to_string()))
+        );
+        assert!(error_kind(&err).unwrap().is_recoverable());
+        assert_eq!(err.to_string(), "could not apply overlay");
+        assert_eq!(err.root_cause().to_string(), "quilt push failed");
+
+        assert_eq!(error_kind(&format_err!("other")), None);
+        assert!(!DebcargoError::Config(String::new()).is_recoverable());
+    }
+}
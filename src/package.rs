@@ -7,7 +7,7 @@ use clap::{crate_version, Parser};
 use crate::config::Config;
 use crate::crates::CrateInfo;
 use crate::debian::{self, DebInfo};
-use crate::errors::Result;
+use crate::errors::{collect_diagnostics, Diagnostic, Result};
 use crate::util;
 
 pub struct PackageProcess {
@@ -23,6 +23,8 @@ pub struct PackageProcess {
     /// Tempdir that contains a working copy of the eventual output.
     pub temp_output_dir: Option<tempfile::TempDir>,
     pub orig_tarball: Option<PathBuf>,
+    /// Warnings and other messages from preparing the debian folder.
+    pub diagnostics: Vec<Diagnostic>,
 }
 
 #[derive(Debug, Clone, Parser)]
@@ -35,6 +37,11 @@ pub struct PackageInitArgs {
     /// TOML file providing package-specific options.
     #[clap(long)]
     pub config: Option<PathBuf>,
+    /// Don't access the network. The crate and the registry index are read
+    /// from the local cargo registry cache, e.g. $CARGO_HOME/registry, or
+    /// from crate_src_path if set, and it is an error if they are missing.
+    #[clap(long)]
+    pub offline: bool,
 }
 
 #[derive(Debug, Clone, Parser)]
@@ -50,12 +57,34 @@ pub struct PackageExecuteArgs {
     /// Assume the changelog is already bumped, and leave it alone.
     #[clap(long)]
     pub changelog_ready: bool,
-    /// Guess extra values for d/copyright. Might be slow.
+    /// Guess extra values for d/copyright, such as the upstream copyright
+    /// years from the git history of crate_src_path or the repository. Might
+    /// be slow.
     #[clap(long)]
     pub copyright_guess_harder: bool,
     /// Don't write back hint files or d/changelog to the source overlay directory.
     #[clap(long)]
     pub no_overlay_write_back: bool,
+    /// Write a JSON summary of the generated packages to
+    /// debian/.debcargo-summary.json. Remove it before building the package.
+    #[clap(long)]
+    pub write_summary: bool,
+    /// Regenerate only this file under debian/, e.g. copyright or watch, in
+    /// the existing package directory, and leave all other files untouched.
+    /// Can be given multiple times. The crate is not extracted again, and no
+    /// orig tarball is created.
+    #[clap(long, value_name = "FILE")]
+    pub regenerate_only: Vec<String>,
+    /// Print a unified diff of the existing debian/ in the package directory
+    /// against what would be generated, including the overlay, without
+    /// writing anything. Like --regenerate-only, the crate is not extracted
+    /// again, and both options can be combined.
+    #[clap(long)]
+    pub diff: bool,
+    /// If generating debian/ fails, keep the temporary directory with the
+    /// files generated so far, and print its location, for debugging.
+    #[clap(long)]
+    pub keep_tempdir: bool,
 }
 
 impl PackageProcess {
@@ -65,8 +94,16 @@ impl PackageProcess {
         config_path: Option<PathBuf>,
         config: Config,
     ) -> Result<Self> {
-        crate_info.set_includes_excludes(config.orig_tar_excludes(), config.orig_tar_whitelist());
-        let deb_info = DebInfo::new(&crate_info, crate_version!(), config.semver_suffix);
+        crate_info
+            .set_includes_excludes(config.orig_tar_excludes(), config.orig_tar_whitelist())?;
+        crate_info.set_strip_cargo_lock(config.keep_cargo_lock == Some(false));
+        let deb_info = DebInfo::new(
+            &crate_info,
+            crate_version!(),
+            config.semver_suffix,
+            config.epoch(),
+            config.orig_compression,
+        )?;
 
         Ok(Self {
             crate_info,
@@ -77,6 +114,7 @@ impl PackageProcess {
             source_modified: None,
             temp_output_dir: None,
             orig_tarball: None,
+            diagnostics: Vec::new(),
         })
     }
 
@@ -94,9 +132,15 @@ impl PackageProcess {
         };
 
         let crate_path = config.crate_src_path(config_path.as_deref());
-        let crate_info = match crate_path {
-            Some(p) => CrateInfo::new_with_local_crate(crate_name, version, &p)?,
-            None => CrateInfo::new(crate_name, version)?,
+        let crate_info = match (crate_path, config.registry.as_deref()) {
+            (Some(p), _) => {
+                CrateInfo::new_with_local_crate(crate_name, version, &p, init_args.offline)?
+            }
+            (None, registry) if init_args.offline => {
+                CrateInfo::new_offline(crate_name, version, registry)?
+            }
+            (None, Some(registry)) => CrateInfo::new_with_registry(crate_name, version, registry)?,
+            (None, None) => CrateInfo::new(crate_name, version)?,
         };
 
         Self::new(crate_info, config_path, config)
@@ -124,6 +168,28 @@ impl PackageProcess {
         Ok(())
     }
 
+    /// Use the package directory from a previous run instead of extracting
+    /// the crate again, to regenerate some files in place.
+    pub fn reuse_extracted(&mut self, extract: PackageExtractArgs) -> Result<()> {
+        assert!(self.output_dir.is_none());
+        assert!(self.source_modified.is_none());
+        let output_dir = extract
+            .directory
+            .unwrap_or_else(|| self.deb_info.package_source_dir().to_path_buf());
+        if !output_dir.join("debian").is_dir() {
+            debcargo_bail!(
+                "{} has no debian directory to regenerate or diff files in, run without \
+                 --regenerate-only or --diff first",
+                output_dir.display()
+            );
+        }
+
+        // stage finished; set vars
+        self.output_dir = Some(output_dir);
+        self.source_modified = Some(false);
+        Ok(())
+    }
+
     pub fn apply_overrides(&mut self) -> Result<()> {
         assert!(self.temp_output_dir.is_none());
         let Self {
@@ -153,6 +219,7 @@ impl PackageProcess {
         let Self {
             crate_info,
             deb_info,
+            config,
             output_dir,
             source_modified,
             ..
@@ -161,18 +228,29 @@ impl PackageProcess {
         let source_modified = source_modified.as_ref().unwrap();
         // vars read; begin stage
 
+        if config.source_format.is_native() {
+            debcargo_info!("native source format; not creating an orig tarball");
+            return Ok(());
+        }
         let orig_tarball = output_dir
             .parent()
             .unwrap()
             .join(deb_info.orig_tarball_path());
-        debian::prepare_orig_tarball(crate_info, &orig_tarball, *source_modified, output_dir)?;
+        debian::prepare_orig_tarball(
+            crate_info,
+            &orig_tarball,
+            deb_info.orig_compression(),
+            *source_modified,
+            output_dir,
+        )?;
 
         // stage finished; set vars
         self.orig_tarball = Some(orig_tarball);
         Ok(())
     }
 
-    pub fn prepare_debian_folder(&mut self, args: PackageExecuteArgs) -> Result<()> {
+    /// Returns the diff of the debian/ folder, if only that was asked for.
+    pub fn prepare_debian_folder(&mut self, args: PackageExecuteArgs) -> Result<Option<String>> {
         let Self {
             crate_info,
             deb_info,
@@ -183,23 +261,34 @@ impl PackageProcess {
             ..
         } = self;
         let output_dir = output_dir.as_ref().unwrap();
-        let temp_output_dir = temp_output_dir.as_ref().unwrap();
         // vars read; begin stage
 
-        debian::prepare_debian_folder(
-            crate_info,
-            deb_info,
-            config_path.as_deref(),
-            config,
-            output_dir,
-            temp_output_dir,
-            args.changelog_ready,
-            args.copyright_guess_harder,
-            !args.no_overlay_write_back,
-        )?;
+        let (result, diagnostics) = collect_diagnostics(|| {
+            let temp_output_dir = temp_output_dir.as_ref().unwrap();
+            debian::prepare_debian_folder(
+                crate_info,
+                deb_info,
+                config_path.as_deref(),
+                config,
+                output_dir,
+                temp_output_dir,
+                args.changelog_ready,
+                args.copyright_guess_harder,
+                !args.no_overlay_write_back,
+                args.write_summary,
+                &args.regenerate_only,
+                args.diff,
+            )
+        });
+        let result = if args.keep_tempdir {
+            debian::keep_tempdir_on_error(temp_output_dir, result)
+        } else {
+            result
+        };
 
         // stage finished; set vars
-        Ok(())
+        self.diagnostics.extend(diagnostics);
+        result
     }
 
     pub fn post_package_checks(&self) -> Result<()> {
@@ -211,14 +300,15 @@ impl PackageProcess {
             ..
         } = self;
         let output_dir = output_dir.as_ref().unwrap();
-        let orig_tarball = orig_tarball.as_ref().unwrap();
 
         let curdir = std::env::current_dir()?;
-        debcargo_info!(
-            concat!("Package Source: {}\n", "Original Tarball for package: {}\n"),
-            util::rel_p(output_dir, &curdir),
-            util::rel_p(orig_tarball, &curdir)
-        );
+        debcargo_info!("Package Source: {}", util::rel_p(output_dir, &curdir));
+        if let Some(orig_tarball) = orig_tarball {
+            debcargo_info!(
+                "Original Tarball for package: {}\n",
+                util::rel_p(orig_tarball, &curdir)
+            );
+        }
         let fixmes = util::lookup_fixmes(output_dir.join("debian").as_path())?;
         if !fixmes.is_empty() {
             debcargo_warn!("FIXME found in the following files.");
@@ -7,7 +7,6 @@ use std::iter::Iterator;
 use std::os::unix::ffi::OsStrExt;
 use std::os::unix::fs::symlink;
 use std::path::{Path, PathBuf};
-use std::process::Command;
 
 use itertools::Itertools;
 use walkdir::WalkDir;
@@ -81,6 +80,35 @@ pub fn copy_tree(oldtree: &Path, newtree: &Path) -> Result<(), Error> {
     Ok(())
 }
 
+/// errno for a rename across filesystems; ErrorKind::CrossesDevices is not
+/// stable yet.
+const EXDEV: i32 = 18;
+
+/// Rename `from` to `to`, falling back to copying and then removing `from`
+/// if they are on different filesystems.
+pub fn rename_or_copy(from: &Path, to: &Path) -> Result<(), Error> {
+    rename_or_copy_with(from, to, |from, to| fs::rename(from, to))
+}
+
+fn rename_or_copy_with<F>(from: &Path, to: &Path, rename: F) -> Result<(), Error>
+where
+    F: FnOnce(&Path, &Path) -> Result<(), Error>,
+{
+    match rename(from, to) {
+        Err(e) if e.raw_os_error() == Some(EXDEV) => {
+            if from.is_dir() {
+                fs::create_dir(to)?;
+                copy_tree(from, to)?;
+                fs::remove_dir_all(from)
+            } else {
+                fs::copy(from, to)?;
+                fs::remove_file(from)
+            }
+        }
+        r => r,
+    }
+}
+
 pub fn show_vec_with<'a, T, F>(it: impl IntoIterator<Item = &'a T>, f: F) -> String
 where
     T: 'a,
@@ -96,19 +124,6 @@ where
     show_vec_with(it, std::string::ToString::to_string)
 }
 
-pub fn expect_success(cmd: &mut Command, err: &str) {
-    match cmd.status() {
-        Ok(status) => {
-            if !status.success() {
-                panic!("{}", err);
-            }
-        }
-        Err(e) => {
-            panic!("{}\n{}", err, e);
-        }
-    }
-}
-
 pub(crate) fn traverse_depth<'a, V, F>(succ: &'a F, key: V) -> BTreeSet<V>
 where
     V: Ord + Copy + 'a,
@@ -251,3 +266,44 @@ where
         Ok(sort)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::io::Error;
+    use std::path::Path;
+
+    use super::{rename_or_copy_with, EXDEV};
+
+    #[test]
+    fn rename_falls_back_to_copy_across_filesystems() {
+        let tmp = tempfile::tempdir().unwrap();
+        let cross_device =
+            |_: &Path, _: &Path| -> Result<(), Error> { Err(Error::from_raw_os_error(EXDEV)) };
+
+        let from = tmp.path().join("from");
+        fs::create_dir_all(from.join("sub")).unwrap();
+        fs::write(from.join("sub/file"), "data").unwrap();
+        std::os::unix::fs::symlink("sub/file", from.join("link")).unwrap();
+        let to = tmp.path().join("to");
+        rename_or_copy_with(&from, &to, cross_device).unwrap();
+        assert!(!from.exists());
+        assert_eq!(fs::read_to_string(to.join("sub/file")).unwrap(), "data");
+        assert_eq!(
+            fs::read_link(to.join("link")).unwrap().to_str(),
+            Some("sub/file")
+        );
+
+        let from = tmp.path().join("file");
+        fs::write(&from, "data").unwrap();
+        let to = tmp.path().join("file.moved");
+        rename_or_copy_with(&from, &to, cross_device).unwrap();
+        assert!(!from.exists());
+        assert_eq!(fs::read_to_string(&to).unwrap(), "data");
+
+        let other_error =
+            |_: &Path, _: &Path| -> Result<(), Error> { Err(Error::from_raw_os_error(EXDEV + 1)) };
+        assert!(rename_or_copy_with(&to, &from, other_error).is_err());
+        assert!(to.exists());
+    }
+}
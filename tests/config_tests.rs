@@ -1,6 +1,8 @@
-extern crate debcargo;
-
-use debcargo_vendor::config::{Config, PackageKey};
+use debcargo_vendor::config::{
+    json_schema, AllowPrereleaseDeps, BrokenTestPolicy, Config, PackageKey, SourceFormat,
+};
+use debcargo_vendor::debian::changelog;
+use debcargo_vendor::errors::{error_kind, DebcargoError};
 use std::path::Path;
 
 #[test]
@@ -24,6 +26,18 @@ fn source_package_override() {
     assert_eq!(homepage.unwrap(), "https://clap.rs");
 
     assert!(config.section().is_none());
+    assert!(config.bin_section().is_none());
+    assert_eq!(config.source_format, SourceFormat::Quilt);
+    assert_eq!(config.changelog_distribution(), changelog::DEFAULT_DIST);
+    assert_eq!(config.changelog_urgency(), "medium");
+    assert_eq!(config.debian_revision(), "0tah1");
+    assert!(changelog::check_debian_revision(config.debian_revision()).is_ok());
+    assert_eq!(
+        config.allow_prerelease_deps,
+        AllowPrereleaseDeps::Crates(vec!["tokio".to_string()])
+    );
+    assert!(config.allow_prerelease_deps.allows("tokio"));
+    assert!(!config.allow_prerelease_deps.allows("hyper"));
     assert!(config.build_depends().is_none());
 
     let filepath = Path::new("tests/debcargo_override.toml");
@@ -37,6 +51,7 @@ fn source_package_override() {
     let section = config.section();
     assert!(section.is_some());
     assert_eq!(section.unwrap(), "rust");
+    assert_eq!(config.bin_section(), Some("devel"));
 
     assert!(config.packages.is_some());
     let sd = config.package_summary(PackageKey::Bin);
@@ -76,6 +91,16 @@ fn sd_top_level() {
     assert!(section.is_some());
     assert_eq!(section.unwrap(), "rust");
 
+    assert_eq!(config.source_format, SourceFormat::Native);
+    assert_eq!(config.changelog_distribution(), "experimental");
+    assert_eq!(config.changelog_urgency(), "low");
+    assert_eq!(config.debian_revision(), changelog::DEFAULT_REVISION);
+    assert!(!config.allow_prerelease_deps.allows("tokio"));
+    assert_eq!(
+        config.orig_tar_excludes(),
+        Some(&vec!["benches/**".to_string()])
+    );
+
     assert_eq!(
         config.summary.unwrap(),
         "Tool to create Debian package from Rust crate"
@@ -91,3 +116,179 @@ Debian Rust team.
 "
     );
 }
+
+#[test]
+fn changelog_dist_urgency() {
+    let config = Config::parse(Path::new("tests/debcargo_override_top_level.toml")).unwrap();
+    assert!(changelog::check_dist_urgency(
+        config.changelog_distribution(),
+        config.changelog_urgency()
+    )
+    .is_ok());
+    assert!(changelog::check_dist_urgency("", "low").is_err());
+    assert!(changelog::check_dist_urgency("experimental", "urgent").is_err());
+}
+
+#[test]
+fn package_override_by_feature() {
+    let config = Config::parse(Path::new("tests/clap_override.toml")).unwrap();
+
+    assert_eq!(
+        config.package_summary(PackageKey::feature("color")),
+        Some("Simple, efficient and full featured Command line argument parser - colored errors")
+    );
+    assert_eq!(
+        config.package_summary(PackageKey::feature("")),
+        config.package_summary(PackageKey::BareLib)
+    );
+    assert!(config
+        .package_summary(PackageKey::feature("suggestions"))
+        .is_none());
+}
+
+#[test]
+fn broken_test_policy_by_feature() {
+    let config: Config = toml::from_str(
+        "[packages.lib]\ntest_is_broken = true\nbroken_test_policy = \"skip\"\n\
+         [packages.\"lib+std\"]\nbroken_test_policy = \"fail\"\n",
+    )
+    .unwrap();
+    assert_eq!(
+        config.package_broken_test_policy(PackageKey::BareLib),
+        Some(BrokenTestPolicy::Skip)
+    );
+    assert_eq!(
+        config.package_broken_test_policy(PackageKey::feature("std")),
+        Some(BrokenTestPolicy::Fail)
+    );
+    assert_eq!(
+        config.package_broken_test_policy(PackageKey::feature("alloc")),
+        None
+    );
+    assert!(toml::from_str::<Config>("[packages.lib]\nbroken_test_policy = \"ignore\"\n").is_err());
+}
+
+#[test]
+fn unknown_keys() {
+    for path in &[
+        "tests/clap_override.toml",
+        "tests/debcargo_override.toml",
+        "tests/debcargo_override_top_level.toml",
+    ] {
+        let config = Config::parse(Path::new(path)).unwrap();
+        assert!(config.unknown_keys().is_empty(), "{}", path);
+    }
+
+    let config: Config = toml::from_str(
+        r#"
+summary = "foo"
+sumary = "foo"
+
+[source]
+buld_depends = ["libfoo-dev"]
+
+[packages.lib]
+recomends = ["bar"]
+
+[packages."lib+std"]
+test_is_brken = true
+"#,
+    )
+    .unwrap();
+    assert_eq!(config.summary.as_deref(), Some("foo"));
+    assert_eq!(
+        config.unknown_keys(),
+        vec![
+            "sumary",
+            "source.buld_depends",
+            "packages.lib.recomends",
+            "packages.\"lib+std\".test_is_brken",
+        ]
+    );
+}
+
+#[test]
+fn config_json_schema() {
+    let schema = json_schema();
+    let properties = &schema["properties"];
+    for key in &[
+        "bin",
+        "overlay",
+        "excludes",
+        "source",
+        "packages",
+        "orig_compression",
+    ] {
+        assert!(properties.get(key).is_some(), "{}", key);
+    }
+    // aliases and the catch-all for unknown keys are not part of the schema
+    assert!(properties.get("orig_tar_excludes").is_none());
+    assert!(properties.get("unknown").is_none());
+
+    let definitions = &schema["definitions"];
+    assert!(definitions["SourceOverride"]["properties"]["build_depends"].is_object());
+    assert!(definitions["PackageOverride"]["properties"]["test_is_broken"].is_object());
+    assert_eq!(
+        definitions["Compression"]["enum"],
+        serde_json::json!(["gzip", "xz", "zstd"])
+    );
+}
+
+#[test]
+fn merge_configs() {
+    let base = Config::parse(Path::new("tests/clap_override.toml")).unwrap();
+    let other: Config = r#"
+maintainer = "Debian Rust Maintainers <rust@example.org>"
+uploaders = ["Someone Else <someone@example.org>"]
+
+[source]
+homepage = "https://github.com/clap-rs/clap"
+section = "rust"
+
+[packages."lib+color"]
+depends = ["libfoo-dev"]
+"#
+    .parse()
+    .unwrap();
+    let config = base.merge(other);
+
+    assert_eq!(
+        config.maintainer(),
+        "Debian Rust Maintainers <rust@example.org>"
+    );
+    // lists are replaced rather than concatenated
+    assert_eq!(
+        config.uploaders(),
+        Some(&vec!["Someone Else <someone@example.org>".to_string()])
+    );
+    // values that are unset in the second config are kept
+    assert_eq!(config.debian_revision(), "0tah1");
+    assert!(config.allow_prerelease_deps.allows("tokio"));
+    // tables are merged field by field
+    assert_eq!(config.policy_version(), Some("4.0.0"));
+    assert_eq!(config.homepage(), Some("https://github.com/clap-rs/clap"));
+    assert_eq!(config.section(), Some("rust"));
+    assert_eq!(
+        config.package_summary(PackageKey::feature("color")),
+        Some("Simple, efficient and full featured Command line argument parser - colored errors")
+    );
+    assert_eq!(
+        config.package_depends(PackageKey::feature("color")),
+        Some(&vec!["libfoo-dev".to_string()])
+    );
+    assert!(config.package_summary(PackageKey::BareLib).is_some());
+}
+
+#[test]
+fn config_from_str_rejects_invalid_toml() {
+    assert!("bin = ".parse::<Config>().is_err());
+    assert!("orig_compression = \"lzma\"".parse::<Config>().is_err());
+
+    let err = "bin = ".parse::<Config>().unwrap_err();
+    assert!(matches!(error_kind(&err), Some(DebcargoError::Config(_))));
+    let err = Config::parse(Path::new("tests/does-not-exist.toml")).unwrap_err();
+    match error_kind(&err) {
+        Some(DebcargoError::Config(msg)) => assert!(msg.starts_with("tests/does-not-exist.toml: ")),
+        kind => panic!("unexpected error kind {:?}", kind),
+    }
+}